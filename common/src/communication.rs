@@ -1,70 +1,331 @@
 use serde::{de::DeserializeOwned, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio_vsock::{VsockAddr, VsockStream as VsockStreamRaw};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 
+use crate::transport::{Transport, TransportAddr};
+use crate::{HandshakeError, PROTOCOL_VERSION};
+
+/// The bytes every connection starts with, in both directions, followed by the [`PROTOCOL_VERSION`] (u32 BE).
+///
+/// This is sent before any frame, so peers that disagree on the framing itself fail with a version error,
+/// rather than reading a frame header as garbage.
+pub const PROTOCOL_MAGIC: [u8; 4] = *b"SP1T";
+
+/// The class of a frame.
+///
+/// Sent alongside the length of every frame, so the receiver can apply the right [`FrameLimits`]
+/// before allocating any memory for the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// A small message, e.g. a request for the public key.
+    Control = 0,
+    /// A message carrying user supplied data, e.g. a program and its stdin.
+    Payload = 1,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = CommunicationError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Control),
+            1 => Ok(Self::Payload),
+            other => Err(CommunicationError::InvalidFrameKind(other)),
+        }
+    }
+}
+
+/// A message that can be sent over a [`VsockStream`].
+pub trait Frame {
+    /// The limits applied to this message type if none are configured on the stream.
+    const DEFAULT_LIMITS: FrameLimits;
+
+    /// The class of this message.
+    fn frame_kind(&self) -> FrameKind;
+}
+
+/// The maximum size (in bytes) of a frame, for each [`FrameKind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLimits {
+    pub control: u32,
+    pub payload: u32,
+}
+
+impl FrameLimits {
+    /// The limit for the given kind of frame.
+    pub const fn limit(&self, kind: FrameKind) -> u32 {
+        match kind {
+            FrameKind::Control => self.control,
+            FrameKind::Payload => self.payload,
+        }
+    }
+}
+
+/// The ID of a request, responses are tagged with the ID of the request they answer.
+///
+/// This allows many requests to be in flight on a single connection.
+pub type RequestId = u64;
+
+/// A length-prefixed, bincode encoded stream of messages.
+///
+/// Both peers must first call [`VsockStream::exchange_preamble`]. After that, each frame is of the form `[ len (u32 BE) || kind (u8) || id (u64 BE) || message ]`,
+/// where `len` is the length of the message.
+///
+/// Despite the name, this works over any [`Transport`], vsock is just the one used in production.
 pub struct VsockStream<In, Out> {
-    stream: VsockStreamRaw,
+    stream: Box<dyn Transport>,
+    /// The limits applied to frames we send.
+    send_limits: FrameLimits,
+    /// The limits applied to frames we receive.
+    recv_limits: FrameLimits,
     _marker: std::marker::PhantomData<(In, Out)>,
 }
 
-impl<In, Out> VsockStream<In, Out> {
-    pub fn new(stream: VsockStreamRaw) -> Self {
+impl<In, Out> VsockStream<In, Out>
+where
+    In: Frame,
+    Out: Frame,
+{
+    pub fn new(stream: impl Transport) -> Self {
+        Self::from_boxed(Box::new(stream))
+    }
+
+    pub fn from_boxed(stream: Box<dyn Transport>) -> Self {
         Self {
             stream,
+            send_limits: Out::DEFAULT_LIMITS,
+            recv_limits: In::DEFAULT_LIMITS,
             _marker: std::marker::PhantomData,
         }
     }
 
-    pub async fn connect(cid: u32, port: u32) -> Result<Self, CommunicationError> {
-        let addr = VsockAddr::new(cid, port);
-        let stream = VsockStreamRaw::connect(addr).await?;
+    pub async fn connect(addr: &TransportAddr) -> Result<Self, CommunicationError> {
+        let stream = addr.connect().await?;
 
-        Ok(Self {
-            stream,
-            _marker: std::marker::PhantomData,
-        })
+        Ok(Self::from_boxed(stream))
+    }
+
+    /// Set the limits applied to frames sent on this stream.
+    pub fn with_send_limits(mut self, limits: FrameLimits) -> Self {
+        self.send_limits = limits;
+        self
+    }
+
+    /// Set the limits applied to frames received on this stream.
+    pub fn with_recv_limits(mut self, limits: FrameLimits) -> Self {
+        self.recv_limits = limits;
+        self
+    }
+
+    /// Split the stream into halves that can be used concurrently from different tasks.
+    pub fn into_split(self) -> (FrameReader<In>, FrameWriter<Out>) {
+        let (reader, writer) = tokio::io::split(self.stream);
+
+        (
+            FrameReader {
+                reader,
+                limits: self.recv_limits,
+                _marker: std::marker::PhantomData,
+            },
+            FrameWriter {
+                writer,
+                limits: self.send_limits,
+                _marker: std::marker::PhantomData,
+            },
+        )
     }
 }
 
 /// Async methods.
 impl<In, Out> VsockStream<In, Out>
 where
-    In: DeserializeOwned,
-    Out: Serialize,
+    In: DeserializeOwned + Frame,
+    Out: Serialize + Frame,
 {
-    pub async fn send(&mut self, message: Out) -> Result<(), CommunicationError> {
-        let message_bytes = bincode::serialize(&message)?;
+    /// Sends [`PROTOCOL_MAGIC`] and our [`PROTOCOL_VERSION`], and reads the peer's, returning its version.
+    ///
+    /// This must be the first thing done on a new connection, by both peers.
+    ///
+    /// # Errors
+    /// - [`HandshakeError::InvalidPreamble`] - The peer did not start with [`PROTOCOL_MAGIC`],
+    ///   i.e. it predates the preamble or is not an SP1 TEE peer.
+    pub async fn exchange_preamble(&mut self) -> Result<u32, CommunicationError> {
+        let mut preamble = [0; 8];
+        preamble[..4].copy_from_slice(&PROTOCOL_MAGIC);
+        preamble[4..].copy_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+
+        self.stream.write_all(&preamble).await?;
 
-        if message_bytes.len() > u32::MAX as usize {
-            return Err(CommunicationError::MessageTooLarge);
+        self.stream.read_exact(&mut preamble).await?;
+
+        if preamble[..4] != PROTOCOL_MAGIC {
+            return Err(HandshakeError::InvalidPreamble.into());
         }
 
-        let message_len = (message_bytes.len() as u32).to_be_bytes();
+        Ok(u32::from_be_bytes(
+            preamble[4..].try_into().expect("slice is 4 bytes"),
+        ))
+    }
+
+    pub async fn send(&mut self, id: RequestId, message: Out) -> Result<(), CommunicationError> {
+        write_frame(&mut self.stream, id, &message, &self.send_limits).await
+    }
+
+    /// Receive a message from the stream.
+    ///
+    /// If the frame exceeds the configured limits, its bytes are discarded without being buffered
+    /// and [`CommunicationError::FrameTooLarge`] is returned, the stream can still be used after this.
+    pub async fn recv(&mut self) -> Result<(RequestId, In), CommunicationError> {
+        read_frame(&mut self.stream, &self.recv_limits).await
+    }
+}
+
+/// The receiving half of a [`VsockStream`].
+pub struct FrameReader<In> {
+    reader: ReadHalf<Box<dyn Transport>>,
+    limits: FrameLimits,
+    _marker: std::marker::PhantomData<In>,
+}
+
+impl<In> FrameReader<In>
+where
+    In: DeserializeOwned + Frame,
+{
+    /// See [`VsockStream::recv`].
+    pub async fn recv(&mut self) -> Result<(RequestId, In), CommunicationError> {
+        read_frame(&mut self.reader, &self.limits).await
+    }
+}
+
+/// The sending half of a [`VsockStream`].
+pub struct FrameWriter<Out> {
+    writer: WriteHalf<Box<dyn Transport>>,
+    limits: FrameLimits,
+    _marker: std::marker::PhantomData<Out>,
+}
+
+impl<Out> FrameWriter<Out>
+where
+    Out: Serialize + Frame,
+{
+    /// See [`VsockStream::send`].
+    pub async fn send(&mut self, id: RequestId, message: Out) -> Result<(), CommunicationError> {
+        write_frame(&mut self.writer, id, &message, &self.limits).await
+    }
+}
 
-        self.stream.write_all(&message_len).await?;
-        self.stream.write_all(&message_bytes).await?;
+/// Serialize and write a single frame to the writer.
+async fn write_frame<W, T>(
+    writer: &mut W,
+    id: RequestId,
+    message: &T,
+    limits: &FrameLimits,
+) -> Result<(), CommunicationError>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+    T: Serialize + Frame,
+{
+    let kind = message.frame_kind();
+    let message_bytes = bincode::serialize(message)?;
+
+    if message_bytes.len() > u32::MAX as usize {
+        return Err(CommunicationError::MessageTooLarge);
+    }
 
-        Ok(())
+    let limit = limits.limit(kind);
+    if message_bytes.len() > limit as usize {
+        return Err(CommunicationError::FrameTooLarge {
+            id,
+            kind,
+            size: message_bytes.len() as u64,
+            limit,
+        });
     }
 
-    pub async fn recv(&mut self) -> Result<In, CommunicationError> {
-        // Read a u32 from the stream so we can allocate the correct amount of memory for the message bytes.
-        // Interprets this u32 as a be_bytes of the message length.
-        let mut message_len_buf = [0; 4];
-        self.stream.read_exact(&mut message_len_buf).await?;
+    // Write the header in one go, so its not split across packets.
+    let mut header = [0; 13];
+    header[..4].copy_from_slice(&(message_bytes.len() as u32).to_be_bytes());
+    header[4] = kind as u8;
+    header[5..].copy_from_slice(&id.to_be_bytes());
+
+    writer.write_all(&header).await?;
+    writer.write_all(&message_bytes).await?;
+
+    Ok(())
+}
+
+/// Read and deserialize a single frame from the reader.
+async fn read_frame<R, T>(
+    reader: &mut R,
+    limits: &FrameLimits,
+) -> Result<(RequestId, T), CommunicationError>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    T: DeserializeOwned + Frame,
+{
+    // Read a u32 from the stream so we can allocate the correct amount of memory for the message bytes.
+    // Interprets this u32 as a be_bytes of the message length.
+    let mut message_len_buf = [0; 4];
+    reader.read_exact(&mut message_len_buf).await?;
+
+    // Convert the message length to a u32.
+    let message_len = u32::from_be_bytes(message_len_buf);
+
+    // Read the kind of the frame, so we know which limit to apply, and the request it belongs to.
+    let kind = reader.read_u8().await?;
+    let id = reader.read_u64().await?;
+
+    let kind = match FrameKind::try_from(kind) {
+        Ok(kind) => kind,
+        Err(e) => {
+            discard(reader, message_len).await?;
 
-        // Convert the message length to a u32.
-        let message_len = u32::from_be_bytes(message_len_buf);
+            return Err(e);
+        }
+    };
+
+    // Check the length before allocating anything, the peer controls this value.
+    let limit = limits.limit(kind);
+    if message_len > limit {
+        discard(reader, message_len).await?;
+
+        return Err(CommunicationError::FrameTooLarge {
+            id,
+            kind,
+            size: message_len as u64,
+            limit,
+        });
+    }
+
+    // Allocate a buffer to store the message bytes.
+    let mut message_buf = vec![0; message_len as usize];
 
-        // Allocate a buffer to store the message bytes.
-        let mut message_buf = vec![0; message_len as usize];
+    // Read the message bytes from the stream.
+    reader.read_exact(&mut message_buf).await?;
 
-        // Read the message bytes from the stream.
-        self.stream.read_exact(&mut message_buf).await?;
+    // Deserialize the message bytes into the desired type.
+    let message: T = bincode::deserialize(&message_buf)?;
 
-        // Deserialize the message bytes into the desired type.
-        Ok(bincode::deserialize(&message_buf)?)
+    // Ensure the peer didnt lie about the kind of the frame to get around the limits.
+    if message.frame_kind() != kind {
+        return Err(CommunicationError::FrameKindMismatch {
+            declared: kind,
+            actual: message.frame_kind(),
+        });
     }
+
+    Ok((id, message))
+}
+
+/// Read and drop `len` bytes from the reader, without buffering them.
+async fn discard<R>(reader: &mut R, len: u32) -> Result<(), CommunicationError>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let mut limited = reader.take(len as u64);
+    tokio::io::copy(&mut limited, &mut tokio::io::sink()).await?;
+
+    Ok(())
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -77,4 +338,217 @@ pub enum CommunicationError {
 
     #[error("Enclave message too large")]
     MessageTooLarge,
+
+    #[error("{kind:?} frame of {size} bytes exceeds the limit of {limit} bytes")]
+    FrameTooLarge {
+        id: RequestId,
+        kind: FrameKind,
+        size: u64,
+        limit: u32,
+    },
+
+    #[error("Handshake failed: {0}")]
+    Handshake(#[from] crate::HandshakeError),
+
+    #[error("Connection to the peer was closed")]
+    Disconnected,
+
+    #[error("Invalid frame kind: {0}")]
+    InvalidFrameKind(u8),
+
+    #[error("Frame declared as {declared:?} but contained a {actual:?} message")]
+    FrameKindMismatch {
+        declared: FrameKind,
+        actual: FrameKind,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Message {
+        Ping(u32),
+        Data(Vec<u8>),
+    }
+
+    impl Frame for Message {
+        const DEFAULT_LIMITS: FrameLimits = FrameLimits {
+            control: 16,
+            payload: 1024,
+        };
+
+        fn frame_kind(&self) -> FrameKind {
+            match self {
+                Message::Ping(_) => FrameKind::Control,
+                Message::Data(_) => FrameKind::Payload,
+            }
+        }
+    }
+
+    type Stream = VsockStream<Message, Message>;
+
+    fn pair() -> (Stream, Stream) {
+        let (a, b) = tokio::io::duplex(64 * 1024);
+
+        (Stream::new(a), Stream::new(b))
+    }
+
+    /// Writes a frame header declaring `kind`, followed by `message`, bypassing the checks of [`write_frame`].
+    async fn write_raw(stream: &mut Stream, kind: u8, id: RequestId, message: &Message) {
+        let bytes = bincode::serialize(message).unwrap();
+
+        stream
+            .stream
+            .write_all(&(bytes.len() as u32).to_be_bytes())
+            .await
+            .unwrap();
+        stream.stream.write_u8(kind).await.unwrap();
+        stream.stream.write_u64(id).await.unwrap();
+        stream.stream.write_all(&bytes).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let (mut a, mut b) = pair();
+
+        a.send(1, Message::Ping(7)).await.unwrap();
+        a.send(u64::MAX, Message::Data(vec![1, 2, 3]))
+            .await
+            .unwrap();
+
+        assert_eq!(b.recv().await.unwrap(), (1, Message::Ping(7)));
+        assert_eq!(
+            b.recv().await.unwrap(),
+            (u64::MAX, Message::Data(vec![1, 2, 3]))
+        );
+    }
+
+    #[tokio::test]
+    async fn frame_header_layout() {
+        let (mut a, mut b) = pair();
+
+        a.send(0x0102030405060708, Message::Ping(1)).await.unwrap();
+
+        let mut header = [0; 13];
+        b.stream.read_exact(&mut header).await.unwrap();
+
+        // Ping(1) is a u32 variant index and a u32, both little endian.
+        assert_eq!(header[..4], 8u32.to_be_bytes());
+        assert_eq!(header[4], FrameKind::Control as u8);
+        assert_eq!(header[5..], [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[tokio::test]
+    async fn rejects_frame_kind_mismatch() {
+        let (mut a, mut b) = pair();
+
+        // A payload sized message declared as a control frame, to get around the payload limit.
+        write_raw(
+            &mut a,
+            FrameKind::Control as u8,
+            3,
+            &Message::Data(vec![0; 4]),
+        )
+        .await;
+
+        assert!(matches!(
+            b.recv().await,
+            Err(CommunicationError::FrameKindMismatch {
+                declared: FrameKind::Control,
+                actual: FrameKind::Payload,
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn discards_oversized_frames() {
+        let (mut a, mut b) = pair();
+
+        // Over the payload limit, which the sender would have refused to write.
+        write_raw(
+            &mut a,
+            FrameKind::Payload as u8,
+            4,
+            &Message::Data(vec![0; 2048]),
+        )
+        .await;
+        a.send(5, Message::Data(vec![0; 32])).await.unwrap();
+
+        assert!(matches!(
+            b.recv().await,
+            Err(CommunicationError::FrameTooLarge {
+                id: 4,
+                kind: FrameKind::Payload,
+                limit: 1024,
+                ..
+            })
+        ));
+
+        // The oversized frame was skipped, so the stream is still usable.
+        assert_eq!(b.recv().await.unwrap(), (5, Message::Data(vec![0; 32])));
+    }
+
+    #[tokio::test]
+    async fn send_rejects_oversized_frames() {
+        let (mut a, _b) = pair();
+
+        a.send(6, Message::Data(vec![0; 1000])).await.unwrap();
+        assert!(matches!(
+            a.send(7, Message::Data(vec![0; 2048])).await,
+            Err(CommunicationError::FrameTooLarge { id: 7, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_frame_kind() {
+        let (mut a, mut b) = pair();
+
+        write_raw(&mut a, 9, 8, &Message::Ping(0)).await;
+        a.send(9, Message::Ping(1)).await.unwrap();
+
+        assert!(matches!(
+            b.recv().await,
+            Err(CommunicationError::InvalidFrameKind(9))
+        ));
+        assert_eq!(b.recv().await.unwrap(), (9, Message::Ping(1)));
+    }
+
+    #[tokio::test]
+    async fn preamble_exchanges_versions() {
+        let (mut a, mut b) = pair();
+
+        let (a_version, b_version) = tokio::join!(a.exchange_preamble(), b.exchange_preamble());
+
+        assert_eq!(a_version.unwrap(), PROTOCOL_VERSION);
+        assert_eq!(b_version.unwrap(), PROTOCOL_VERSION);
+    }
+
+    #[tokio::test]
+    async fn preamble_reports_peer_version() {
+        let (mut a, mut b) = pair();
+
+        let mut preamble = PROTOCOL_MAGIC.to_vec();
+        preamble.extend_from_slice(&(PROTOCOL_VERSION + 1).to_be_bytes());
+        b.stream.write_all(&preamble).await.unwrap();
+
+        assert_eq!(a.exchange_preamble().await.unwrap(), PROTOCOL_VERSION + 1);
+    }
+
+    #[tokio::test]
+    async fn preamble_rejects_unframed_peer() {
+        let (mut a, mut b) = pair();
+
+        // A peer that predates the preamble starts with a frame.
+        b.send(1, Message::Ping(0)).await.unwrap();
+
+        assert!(matches!(
+            a.exchange_preamble().await,
+            Err(CommunicationError::Handshake(
+                HandshakeError::InvalidPreamble
+            ))
+        ));
+    }
 }
@@ -0,0 +1,14 @@
+use serde::{Deserialize, Serialize};
+
+/// The execution policy the enclave was started with.
+///
+/// The same values are committed to in the enclave's attestations, see [`crate::AttestationUserData`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnclaveConfig {
+    /// The maximum cycle limit the enclave accepts for an execution.
+    pub max_cycles: u64,
+    /// The cycle limit of unconstrained blocks.
+    pub unconstrained_cycle_limit: u64,
+    /// How long (in seconds) an execution may run before the enclave stops it.
+    pub max_execution_secs: u64,
+}
@@ -0,0 +1,361 @@
+//! COSE_Sign1 attestation documents, as issued by the NSM.
+//!
+//! Nitro attestations are verified against the AWS Nitro root with `attestation-doc-validation`.
+//! This module signs and verifies them against any root, so a software NSM can issue documents
+//! from a test CA, and the same documents can be verified end to end off Nitro.
+
+use aws_nitro_enclaves_nsm_api::api::AttestationDoc;
+use ring::{rand::SystemRandom, signature::EcdsaKeyPair};
+use rustls_pki_types::{CertificateDer, UnixTime};
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+/// The COSE header label of the signing algorithm.
+const ALGORITHM_LABEL: i128 = 1;
+
+/// The COSE identifier of ECDSA P-384 with SHA-384, the algorithm the NSM signs with.
+const ES384: i128 = -35;
+
+/// The length of each of the `r` and `s` components of a P-384 signature.
+const P384_SCALAR_LEN: usize = 48;
+
+/// Signs an attestation document, with `key` being the key of the document's certificate.
+///
+/// The key must be for [`ring::signature::ECDSA_P384_SHA384_FIXED_SIGNING`], as COSE expects `r || s`.
+pub fn sign(doc: &AttestationDoc, key: &EcdsaKeyPair) -> Result<Vec<u8>, CoseError> {
+    let protected = protected_header()?;
+    let payload = doc.to_binary();
+
+    let signature = key
+        .sign(&SystemRandom::new(), &sig_structure(&protected, &payload)?)
+        .map_err(|_| CoseError::Signing)?;
+
+    Ok(serde_cbor::to_vec(&Value::Array(vec![
+        Value::Bytes(protected),
+        Value::Map(BTreeMap::new()),
+        Value::Bytes(payload),
+        Value::Bytes(signature.as_ref().to_vec()),
+    ]))?)
+}
+
+/// Verifies an attestation document against the DER encoded `root` certificate, returning its contents.
+///
+/// This checks the certificate chain in the document leads to `root`, and that the document is signed by its certificate.
+///
+/// # Errors
+/// - [`CoseError::Malformed`] - The document is not a COSE_Sign1 structure signed with ES384.
+/// - [`CoseError::Certificate`] - The certificate chain does not lead to `root`, or has expired.
+/// - [`CoseError::Signature`] - The signature does not match the certificate.
+pub fn verify(document: &[u8], root: &[u8]) -> Result<AttestationDoc, CoseError> {
+    let Value::Array(parts) = serde_cbor::from_slice(document)? else {
+        return Err(CoseError::Malformed("expected an array"));
+    };
+
+    let [Value::Bytes(protected), Value::Map(_), Value::Bytes(payload), Value::Bytes(signature)] =
+        parts.as_slice()
+    else {
+        return Err(CoseError::Malformed(
+            "expected a protected header, unprotected header, payload and signature",
+        ));
+    };
+
+    let Value::Map(header) = serde_cbor::from_slice(protected)? else {
+        return Err(CoseError::Malformed(
+            "expected the protected header to be a map",
+        ));
+    };
+
+    if header.get(&Value::Integer(ALGORITHM_LABEL)) != Some(&Value::Integer(ES384)) {
+        return Err(CoseError::Malformed("expected the ES384 algorithm"));
+    }
+
+    let doc = AttestationDoc::from_binary(payload)
+        .map_err(|_| CoseError::Malformed("invalid attestation document payload"))?;
+
+    let root = CertificateDer::from(root);
+    let anchor = webpki::anchor_from_trusted_cert(&root).map_err(CoseError::Certificate)?;
+
+    // The bundle starts with the root, followed by any intermediates.
+    let intermediates = doc
+        .cabundle
+        .iter()
+        .filter(|cert| cert.as_slice() != root.as_ref())
+        .map(|cert| CertificateDer::from(cert.as_slice()))
+        .collect::<Vec<_>>();
+
+    let certificate = CertificateDer::from(doc.certificate.as_slice());
+    let certificate =
+        webpki::EndEntityCert::try_from(&certificate).map_err(CoseError::Certificate)?;
+
+    certificate
+        .verify_for_usage(
+            &[webpki::ring::ECDSA_P384_SHA384],
+            &[anchor],
+            &intermediates,
+            UnixTime::now(),
+            webpki::KeyUsage::client_auth(),
+            None,
+            None,
+        )
+        .map_err(CoseError::Certificate)?;
+
+    certificate
+        .verify_signature(
+            webpki::ring::ECDSA_P384_SHA384,
+            &sig_structure(protected, payload)?,
+            &fixed_to_asn1(signature)?,
+        )
+        .map_err(|_| CoseError::Signature)?;
+
+    Ok(doc)
+}
+
+/// The protected header, which only specifies the algorithm.
+fn protected_header() -> Result<Vec<u8>, CoseError> {
+    Ok(serde_cbor::to_vec(&Value::Map(BTreeMap::from([(
+        Value::Integer(ALGORITHM_LABEL),
+        Value::Integer(ES384),
+    )])))?)
+}
+
+/// The message that is actually signed, see RFC 9052 section 4.4.
+fn sig_structure(protected: &[u8], payload: &[u8]) -> Result<Vec<u8>, CoseError> {
+    Ok(serde_cbor::to_vec(&Value::Array(vec![
+        Value::Text("Signature1".to_string()),
+        Value::Bytes(protected.to_vec()),
+        // No external AAD.
+        Value::Bytes(Vec::new()),
+        Value::Bytes(payload.to_vec()),
+    ]))?)
+}
+
+/// Converts a COSE `r || s` signature into the ASN.1 encoding used by X.509.
+fn fixed_to_asn1(signature: &[u8]) -> Result<Vec<u8>, CoseError> {
+    if signature.len() != 2 * P384_SCALAR_LEN {
+        return Err(CoseError::Signature);
+    }
+
+    let (r, s) = signature.split_at(P384_SCALAR_LEN);
+    let (r, s) = (asn1_integer(r), asn1_integer(s));
+
+    // Each integer is at most 51 bytes, so the short form length always fits.
+    let mut der = vec![0x30, (r.len() + s.len()) as u8];
+    der.extend_from_slice(&r);
+    der.extend_from_slice(&s);
+
+    Ok(der)
+}
+
+/// Encodes an unsigned big endian integer as a DER INTEGER.
+fn asn1_integer(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bytes.len() - 1);
+    let bytes = &bytes[start..];
+
+    // Prefix a zero byte, so the integer is not read as negative.
+    let pad = bytes[0] & 0x80 != 0;
+
+    let mut der = vec![0x02, (bytes.len() + pad as usize) as u8];
+    if pad {
+        der.push(0);
+    }
+    der.extend_from_slice(bytes);
+
+    der
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CoseError {
+    #[error("Malformed attestation document: {0}")]
+    Malformed(&'static str),
+
+    #[error("Invalid CBOR in attestation document: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+
+    #[error("Invalid certificate chain: {0}")]
+    Certificate(webpki::Error),
+
+    #[error("Invalid signature on attestation document")]
+    Signature,
+
+    #[error("Failed to sign attestation document")]
+    Signing,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_ca::TestCa;
+    use aws_nitro_enclaves_nsm_api::api::Digest;
+
+    fn doc(ca: &TestCa, user_data: &[u8]) -> AttestationDoc {
+        AttestationDoc::new(
+            "test".to_string(),
+            Digest::SHA384,
+            0,
+            BTreeMap::from([(0, vec![0; 48])]),
+            ca.leaf_cert().to_vec(),
+            vec![ca.root_cert().to_vec()],
+            Some(user_data.to_vec()),
+            Some(b"nonce".to_vec()),
+            Some(b"public key".to_vec()),
+        )
+    }
+
+    /// Replaces one part of a signed document, keeping the rest.
+    fn replace_part(document: &[u8], index: usize, part: Value) -> Vec<u8> {
+        let Value::Array(mut parts) = serde_cbor::from_slice(document).unwrap() else {
+            panic!("expected an array");
+        };
+
+        parts[index] = part;
+
+        serde_cbor::to_vec(&Value::Array(parts)).unwrap()
+    }
+
+    #[test]
+    fn sign_verify_round_trip() {
+        let ca = TestCa::generate().unwrap();
+
+        let document = sign(&doc(&ca, b"user data"), ca.leaf_key()).unwrap();
+        let verified = verify(&document, ca.root_cert()).unwrap();
+
+        assert_eq!(
+            verified.user_data.map(|bytes| bytes.to_vec()),
+            Some(b"user data".to_vec())
+        );
+        assert_eq!(
+            verified.nonce.map(|bytes| bytes.to_vec()),
+            Some(b"nonce".to_vec())
+        );
+        assert_eq!(
+            verified.public_key.map(|bytes| bytes.to_vec()),
+            Some(b"public key".to_vec())
+        );
+        assert_eq!(verified.pcrs[&0], vec![0; 48]);
+    }
+
+    #[test]
+    fn rejects_other_roots() {
+        let ca = TestCa::generate().unwrap();
+        let other = TestCa::generate().unwrap();
+
+        let document = sign(&doc(&ca, b"user data"), ca.leaf_key()).unwrap();
+
+        assert!(matches!(
+            verify(&document, other.root_cert()),
+            Err(CoseError::Certificate(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_payloads() {
+        let ca = TestCa::generate().unwrap();
+
+        let document = sign(&doc(&ca, b"user data"), ca.leaf_key()).unwrap();
+        let tampered = replace_part(
+            &document,
+            2,
+            Value::Bytes(doc(&ca, b"other data").to_binary()),
+        );
+
+        assert!(matches!(
+            verify(&tampered, ca.root_cert()),
+            Err(CoseError::Signature)
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_signatures() {
+        let ca = TestCa::generate().unwrap();
+
+        let document = sign(&doc(&ca, b"user data"), ca.leaf_key()).unwrap();
+
+        let truncated = replace_part(&document, 3, Value::Bytes(vec![1; 95]));
+        assert!(matches!(
+            verify(&truncated, ca.root_cert()),
+            Err(CoseError::Signature)
+        ));
+
+        let forged = replace_part(&document, 3, Value::Bytes(vec![1; 96]));
+        assert!(matches!(
+            verify(&forged, ca.root_cert()),
+            Err(CoseError::Signature)
+        ));
+    }
+
+    #[test]
+    fn rejects_other_algorithms() {
+        let ca = TestCa::generate().unwrap();
+
+        let document = sign(&doc(&ca, b"user data"), ca.leaf_key()).unwrap();
+        let es256 = serde_cbor::to_vec(&Value::Map(BTreeMap::from([(
+            Value::Integer(ALGORITHM_LABEL),
+            Value::Integer(-7),
+        )])))
+        .unwrap();
+
+        assert!(matches!(
+            verify(
+                &replace_part(&document, 0, Value::Bytes(es256)),
+                ca.root_cert()
+            ),
+            Err(CoseError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn asn1_integers() {
+        assert_eq!(asn1_integer(&[0x01, 0x02]), [0x02, 0x02, 0x01, 0x02]);
+
+        // The high bit is set, so a zero byte keeps it positive.
+        assert_eq!(asn1_integer(&[0x80, 0x01]), [0x02, 0x03, 0x00, 0x80, 0x01]);
+
+        // Leading zeros are stripped.
+        assert_eq!(asn1_integer(&[0x00, 0x00, 0x7f]), [0x02, 0x01, 0x7f]);
+
+        // Unless the next byte has the high bit set.
+        assert_eq!(asn1_integer(&[0x00, 0x00, 0xff]), [0x02, 0x02, 0x00, 0xff]);
+
+        // Zero is a single zero byte.
+        assert_eq!(asn1_integer(&[0x00; 48]), [0x02, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn fixed_to_asn1_signatures() {
+        let mut signature = [0x11; 2 * P384_SCALAR_LEN];
+        // r has the high bit set.
+        signature[0] = 0x80;
+        // s has two leading zeros.
+        signature[P384_SCALAR_LEN] = 0x00;
+        signature[P384_SCALAR_LEN + 1] = 0x00;
+
+        let der = fixed_to_asn1(&signature).unwrap();
+
+        let r = [&[0x02, 49, 0x00, 0x80][..], &[0x11; 47]].concat();
+        let s = [&[0x02, 46][..], &[0x11; 46]].concat();
+
+        assert_eq!(
+            der,
+            [&[0x30, (r.len() + s.len()) as u8][..], &r, &s].concat()
+        );
+
+        // The largest signature still has a short form length.
+        let der = fixed_to_asn1(&[0xff; 2 * P384_SCALAR_LEN]).unwrap();
+        assert_eq!(der[..2], [0x30, 2 * 51]);
+    }
+
+    #[test]
+    fn fixed_to_asn1_rejects_other_lengths() {
+        assert!(matches!(
+            fixed_to_asn1(&[0x11; 2 * P384_SCALAR_LEN - 1]),
+            Err(CoseError::Signature)
+        ));
+        assert!(matches!(
+            fixed_to_asn1(&[0x11; 2 * P384_SCALAR_LEN + 1]),
+            Err(CoseError::Signature)
+        ));
+    }
+}
@@ -0,0 +1,123 @@
+use serde::{Deserialize, Serialize};
+
+/// An error returned by the enclave, in response to any request.
+///
+/// Errors are either the fault of the request (which should not be retried),
+/// or of the enclave and its environment (which may succeed if retried).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+pub enum EnclaveError {
+    /// The request was malformed, i.e. the stdin could not be decoded or an upload was invalid.
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    /// The request frame exceeded the enclave's frame limits, and was discarded without being read.
+    #[error("Request of {size} bytes exceeds the frame limit of {limit} bytes")]
+    RequestTooLarge { size: u64, limit: u32 },
+
+    /// The requested cycle limit is higher than the enclave allows.
+    #[error("Cycle limit is too high: {requested}, max: {max}")]
+    CycleLimitTooHigh { requested: u64, max: u64 },
+
+    /// The program failed to execute, i.e. it panicked or exited with a non-zero code.
+    #[error("Failed to execute program: {0}")]
+    ExecutionFailed(String),
+
+    /// The program ran for more cycles than the cycle limit.
+    #[error("Cycle limit exceeded: {0}")]
+    CycleLimitExceeded(u64),
+
+    /// The program ran for longer than the enclave allows, in seconds.
+    #[error("Execution time limit exceeded: {0}s")]
+    ExecutionTimeLimitExceeded(u64),
+
+    /// The proof of a [`crate::EnclaveRequest::VerifyProof`] is not valid, or not a kind the enclave verifies.
+    #[error("Invalid proof: {0}")]
+    InvalidProof(String),
+
+    /// The worker running the program exited without a result, i.e. it panicked or ran out of memory.
+    #[error("Execution worker crashed: {0}")]
+    WorkerCrashed(String),
+
+    /// The enclave does not have the resources to handle the request right now.
+    #[error("Enclave resources exhausted: {0}")]
+    ResourceExhausted(String),
+
+    /// The enclave failed to talk to the Nitro Secure Module.
+    #[error("Attestation failed: {0}")]
+    AttestationFailed(String),
+
+    /// The signing key could not be sealed or unsealed, i.e. KMS was unreachable or refused the request.
+    #[error("Failed to seal signing key: {0}")]
+    SealingFailed(String),
+
+    /// The program of an [`crate::EnclaveRequest::ExecuteCached`] is not in the cache.
+    ///
+    /// The host should retry with the full program.
+    #[error("Program not cached: 0x{}", hex_string(.program_hash))]
+    ProgramNotCached { program_hash: [u8; 32] },
+
+    /// The enclave's self-test has not passed, so it does not advertise its signing key yet.
+    #[error("Enclave not ready: {0}")]
+    NotReady(String),
+
+    /// The host cancelled the request before it completed.
+    #[error("Request cancelled")]
+    Cancelled,
+
+    /// The request is not supported by this enclave.
+    #[error("Not implemented: {0}")]
+    NotImplemented(String),
+
+    /// An unexpected failure in the enclave, this is likely a bug.
+    #[error("Internal enclave error: {0}")]
+    Internal(String),
+}
+
+impl EnclaveError {
+    /// A stable, machine readable code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EnclaveError::InvalidRequest(_) => "INVALID_REQUEST",
+            EnclaveError::RequestTooLarge { .. } => "REQUEST_TOO_LARGE",
+            EnclaveError::CycleLimitTooHigh { .. } => "CYCLE_LIMIT_TOO_HIGH",
+            EnclaveError::ExecutionFailed(_) => "EXECUTION_FAILED",
+            EnclaveError::CycleLimitExceeded(_) => "CYCLE_LIMIT_EXCEEDED",
+            EnclaveError::ExecutionTimeLimitExceeded(_) => "EXECUTION_TIME_LIMIT_EXCEEDED",
+            EnclaveError::InvalidProof(_) => "INVALID_PROOF",
+            EnclaveError::WorkerCrashed(_) => "WORKER_CRASHED",
+            EnclaveError::ResourceExhausted(_) => "RESOURCE_EXHAUSTED",
+            EnclaveError::AttestationFailed(_) => "ATTESTATION_FAILED",
+            EnclaveError::SealingFailed(_) => "SEALING_FAILED",
+            EnclaveError::ProgramNotCached { .. } => "PROGRAM_NOT_CACHED",
+            EnclaveError::NotReady(_) => "NOT_READY",
+            EnclaveError::Cancelled => "CANCELLED",
+            EnclaveError::NotImplemented(_) => "NOT_IMPLEMENTED",
+            EnclaveError::Internal(_) => "INTERNAL",
+        }
+    }
+
+    /// Returns true if the error was caused by the request, retrying it will fail again.
+    pub fn is_user_error(&self) -> bool {
+        matches!(
+            self,
+            EnclaveError::InvalidRequest(_)
+                | EnclaveError::RequestTooLarge { .. }
+                | EnclaveError::CycleLimitTooHigh { .. }
+                | EnclaveError::ExecutionFailed(_)
+                | EnclaveError::CycleLimitExceeded(_)
+                | EnclaveError::ExecutionTimeLimitExceeded(_)
+                | EnclaveError::InvalidProof(_)
+                | EnclaveError::WorkerCrashed(_)
+                | EnclaveError::NotImplemented(_)
+        )
+    }
+
+    /// Returns true if the request may succeed if retried.
+    pub fn is_retryable(&self) -> bool {
+        !self.is_user_error()
+    }
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+/// The revision of the host <-> enclave protocol.
+///
+/// This MUST be bumped whenever the encoding of [`crate::EnclaveRequest`] or
+/// [`crate::EnclaveResponse`] changes, or the framing of [`crate::VsockStream`],
+/// otherwise bincode will silently decode garbage.
+///
+/// It is exchanged before any frame, see [`crate::VsockStream::exchange_preamble`].
+pub const PROTOCOL_VERSION: u32 = 21;
+
+/// The [`crate::RequestId`] used for the handshake, regular requests start after this.
+pub const HANDSHAKE_REQUEST_ID: crate::RequestId = 0;
+
+/// Information about the enclave, sent in response to a [`crate::EnclaveRequest::Hello`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnclaveInfo {
+    /// The [`PROTOCOL_VERSION`] the enclave speaks.
+    pub protocol_version: u32,
+    /// The `SP1_TEE_VERSION` the enclave signs with.
+    pub tee_version: u32,
+    /// How the enclave binary was built.
+    pub build: BuildInfo,
+}
+
+/// Build information of the enclave binary.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    /// The version of the enclave crate.
+    pub crate_version: String,
+    /// The git revision the enclave was built from, or `unknown`.
+    pub git_revision: String,
+    /// Whether the enclave was built with the `debug-mode` feature.
+    pub debug_mode: bool,
+}
+
+impl EnclaveInfo {
+    /// Checks that the enclave is compatible with the host.
+    ///
+    /// # Errors
+    /// - [`HandshakeError::ProtocolMismatch`] - The enclave speaks a different protocol revision.
+    /// - [`HandshakeError::TeeVersionMismatch`] - The enclave signs with a different TEE version.
+    pub fn check_compatible(&self, tee_version: u32) -> Result<(), HandshakeError> {
+        if self.protocol_version != PROTOCOL_VERSION {
+            return Err(HandshakeError::ProtocolMismatch {
+                host: PROTOCOL_VERSION,
+                enclave: self.protocol_version,
+            });
+        }
+
+        if self.tee_version != tee_version {
+            return Err(HandshakeError::TeeVersionMismatch {
+                host: tee_version,
+                enclave: self.tee_version,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HandshakeError {
+    #[error("Protocol version mismatch, host: {host}, enclave: {enclave}")]
+    ProtocolMismatch { host: u32, enclave: u32 },
+
+    #[error("SP1 TEE version mismatch, host: {host}, enclave: {enclave}")]
+    TeeVersionMismatch { host: u32, enclave: u32 },
+
+    #[error("The peer did not send the protocol preamble, it may predate protocol version 21")]
+    InvalidPreamble,
+
+    #[error("The peer did not send the protocol preamble in time")]
+    PreambleTimeout,
+
+    #[error("Enclave rejected the handshake: {0}")]
+    Rejected(String),
+
+    #[error("Unexpected response to handshake: {0}")]
+    UnexpectedResponse(&'static str),
+}
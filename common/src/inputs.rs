@@ -0,0 +1,122 @@
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+use sp1_sdk::SP1Stdin;
+
+/// Prefixes the canonical encoding of a stdin, see [`stdin_hash`].
+pub const STDIN_HASH_TAG: &[u8] = b"SP1TeeStdin.v1";
+
+/// The inputs of an execution, committed to in its signature when the request sets `sign_inputs`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputCommitment {
+    /// The keccak256 hash of the ELF that ran, see [`crate::program_hash`].
+    pub program_hash: [u8; 32],
+    /// The canonical hash of the stdin, see [`stdin_hash`].
+    pub stdin_hash: [u8; 32],
+}
+
+impl InputCommitment {
+    pub fn new(program: &[u8], stdin: &SP1Stdin) -> Self {
+        Self {
+            program_hash: crate::program_hash(program),
+            stdin_hash: stdin_hash(stdin),
+        }
+    }
+}
+
+/// The canonical hash of a stdin, independent of how it was encoded in transit.
+///
+/// This is the keccak256 hash of:
+///
+/// ```text
+/// STDIN_HASH_TAG
+///     || buffer_count || (len || buffer) for each buffer
+///     || proof_count || keccak(bincode(proof, vkey)) for each proof
+/// ```
+///
+/// Counts and lengths are big endian `u64`s. Proofs are encoded with bincode's default options,
+/// as they are on the wire. The read position of the stdin is not part of the hash.
+pub fn stdin_hash(stdin: &SP1Stdin) -> [u8; 32] {
+    let mut hasher = Keccak256::new_with_prefix(STDIN_HASH_TAG);
+
+    hasher.update((stdin.buffer.len() as u64).to_be_bytes());
+    for buffer in &stdin.buffer {
+        hasher.update((buffer.len() as u64).to_be_bytes());
+        hasher.update(buffer);
+    }
+
+    hasher.update((stdin.proofs.len() as u64).to_be_bytes());
+    for proof in &stdin.proofs {
+        let proof = bincode::serialize(proof).expect("Failed to serialize proof, this is a bug.");
+        hasher.update(Keccak256::digest(proof));
+    }
+
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdin(buffers: &[&[u8]]) -> SP1Stdin {
+        let mut stdin = SP1Stdin::new();
+        for buffer in buffers {
+            stdin.write_vec(buffer.to_vec());
+        }
+
+        stdin
+    }
+
+    #[test]
+    fn stdin_hash_encoding() {
+        let expected = Keccak256::digest(
+            [
+                STDIN_HASH_TAG,
+                // Two buffers.
+                &2u64.to_be_bytes(),
+                &2u64.to_be_bytes(),
+                b"ab",
+                &0u64.to_be_bytes(),
+                // No proofs.
+                &0u64.to_be_bytes(),
+            ]
+            .concat(),
+        );
+
+        assert_eq!(
+            stdin_hash(&stdin(&[b"ab", b""])),
+            <[u8; 32]>::from(expected)
+        );
+    }
+
+    #[test]
+    fn empty_stdin_hash_encoding() {
+        let expected = Keccak256::digest([STDIN_HASH_TAG, &[0; 8], &[0; 8]].concat());
+
+        assert_eq!(stdin_hash(&SP1Stdin::new()), <[u8; 32]>::from(expected));
+    }
+
+    #[test]
+    fn stdin_hash_commits_to_buffer_boundaries() {
+        assert_ne!(
+            stdin_hash(&stdin(&[b"ab"])),
+            stdin_hash(&stdin(&[b"a", b"b"]))
+        );
+        assert_ne!(stdin_hash(&stdin(&[b""])), stdin_hash(&SP1Stdin::new()));
+    }
+
+    #[test]
+    fn stdin_hash_ignores_the_read_position() {
+        let mut read = stdin(&[b"ab", b"cd"]);
+        read.ptr = 1;
+
+        assert_eq!(stdin_hash(&read), stdin_hash(&stdin(&[b"ab", b"cd"])));
+    }
+
+    #[test]
+    fn input_commitment() {
+        let commitment = InputCommitment::new(b"elf", &stdin(&[b"ab"]));
+
+        assert_eq!(commitment.program_hash, crate::program_hash(b"elf"));
+        assert_eq!(commitment.stdin_hash, stdin_hash(&stdin(&[b"ab"])));
+    }
+}
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+/// A signing key of the enclave, and an attestation document binding it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttestedKey {
+    /// The SEC1 encoded public key.
+    pub public_key: k256::EncodedPoint,
+    /// The attestation document, with the public key field set.
+    pub attestation: Vec<u8>,
+}
+
+/// The signing keys of the enclave, see [`crate::EnclaveRequest::RotateKey`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttestedKeys {
+    /// The key executions are signed with.
+    pub active: AttestedKey,
+    /// The key replacing `active` once the grace period is over, if a rotation is in progress.
+    pub next: Option<NextKey>,
+}
+
+/// A key that becomes active after a grace period.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NextKey {
+    pub key: AttestedKey,
+    /// The number of seconds until this key replaces the active key.
+    pub activates_in_secs: u64,
+}
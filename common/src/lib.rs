@@ -1,7 +1,66 @@
 use serde::{Deserialize, Serialize};
 
 mod communication;
-pub use communication::{CommunicationError, VsockStream};
+pub use communication::{
+    CommunicationError, Frame, FrameKind, FrameLimits, FrameReader, FrameWriter, RequestId,
+    VsockStream, PROTOCOL_MAGIC,
+};
+
+mod config;
+pub use config::EnclaveConfig;
+
+pub mod cose;
+
+pub mod test_ca;
+
+mod error;
+pub use error::EnclaveError;
+
+mod handshake;
+pub use handshake::{
+    BuildInfo, EnclaveInfo, HandshakeError, HANDSHAKE_REQUEST_ID, PROTOCOL_VERSION,
+};
+
+mod inputs;
+pub use inputs::{stdin_hash, InputCommitment, STDIN_HASH_TAG};
+
+mod keys;
+pub use keys::{AttestedKey, AttestedKeys, NextKey};
+
+mod receipt;
+pub use receipt::{FailureKind, FailureReceipt, SignedFailureReceipt, FAILURE_RECEIPT_TAG};
+
+mod report;
+pub use report::ExecutionReport;
+
+mod scheduler;
+pub use scheduler::SchedulerStatus;
+
+mod sealing;
+pub use sealing::AwsCredentials;
+
+mod selftest;
+pub use selftest::SelfTestReport;
+
+mod upload;
+pub use upload::{program_hash, UploadHasher, UploadId, UploadKind, UPLOAD_CHUNK_SIZE};
+
+mod user_data;
+pub use user_data::{AttestationUserData, UserDataError, SIGNATURE_FORMAT, USER_DATA_VERSION};
+
+mod signature;
+pub use signature::{
+    DomainVerifier, SignatureDomain, SignatureError, SignedMessage, SIGNATURE_DOMAIN_TAG,
+};
+
+mod threshold;
+pub use threshold::{ThresholdError, ThresholdProof, ThresholdVerifier, THRESHOLD_VERIFIER_NAME};
+
+mod verdict;
+pub use verdict::{ProofKind, ProofVerdict, SignedProofVerdict, PROOF_VERDICT_TAG};
+
+mod transport;
+pub use transport::{Transport, TransportAddr, TransportAddrParseError, TransportListener};
 
 /// A VSOCK address is defined as the tuple of (CID, port).
 ///
@@ -11,60 +70,270 @@ pub const ENCLAVE_PORT: u16 = 5005;
 /// The CID of the enclave.
 pub const ENCLAVE_CID: u32 = 10;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The maximum length (in bytes) of a nonce in an attestation document, as accepted by the NSM.
+pub const MAX_NONCE_LEN: usize = 512;
+
+/// The default limits for frames sent from the host to the enclave.
+///
+/// The enclave has a fixed amount of memory, so these are enforced before allocating a frame.
+pub const DEFAULT_REQUEST_FRAME_LIMITS: FrameLimits = FrameLimits {
+    // 1 MiB
+    control: 1024 * 1024,
+    // 512 MiB
+    payload: 512 * 1024 * 1024,
+};
+
+/// The default limits for frames sent from the enclave to the host.
+pub const DEFAULT_RESPONSE_FRAME_LIMITS: FrameLimits = FrameLimits {
+    // 1 MiB
+    control: 1024 * 1024,
+    // 256 MiB
+    payload: 256 * 1024 * 1024,
+};
+
+/// NOTE: This is not `Debug`, as [`sp1_sdk::SP1VerifyingKey`] is not.
+#[derive(Serialize, Deserialize)]
 pub enum EnclaveRequest {
+    /// The first message on every connection.
+    ///
+    /// NOTE: This MUST remain the first variant, so its encoding is stable across protocol versions.
+    Hello {
+        protocol_version: u32,
+        tee_version: u32,
+    },
     /// Print from the enclave to the debug console.
     Print(String),
     /// Request the enclave's public key.
     GetPublicKey,
-    /// Request the enclave's signing key for crash tolerane.
-    GetEncryptedSigningKey,
+    /// Request the enclave's sealed signing key, for crash tolerance.
+    ///
+    /// The enclave responds with [`EnclaveResponse::EncryptedSigningKey`], which the host should persist
+    /// and send back with [`EnclaveRequest::SetSigningKey`] when the enclave restarts.
+    ///
+    /// `credentials` are required if the enclave seals with AWS KMS.
+    GetEncryptedSigningKey { credentials: Option<AwsCredentials> },
     /// Request the enclave to attest to the signing key.
-    AttestSigningKey,
+    ///
+    /// The `nonce` is bound in the attestation document, so a verifier can check it is fresh.
+    /// It must be at most [`MAX_NONCE_LEN`] bytes.
+    AttestSigningKey { nonce: Option<Vec<u8>> },
     /// An execution request, sent from the host to the enclave.
+    ///
+    /// If `sign_cycles` is set, the cycle count is appended to the signed message,
+    /// and if `sign_inputs` is set, the [`InputCommitment`] is.
+    /// If `domain` is set, the signature is bound to it, see [`SignedMessage`].
     Execute {
         stdin: sp1_sdk::SP1Stdin,
         program: Vec<u8>,
         cycle_limit: u64,
+        sign_cycles: bool,
+        sign_inputs: bool,
+        domain: Option<SignatureDomain>,
+    },
+    /// Restore the enclave's signing keys, as previously sealed by [`EnclaveRequest::GetEncryptedSigningKey`].
+    ///
+    /// The keys are only replaced if the enclave restarted since they were sealed.
+    /// The enclave responds with its active [`EnclaveResponse::PublicKey`].
+    SetSigningKey {
+        sealed_key: Vec<u8>,
+        credentials: Option<AwsCredentials>,
+    },
+    /// Start a chunked upload of `total_len` bytes.
+    ///
+    /// The enclave reserves the memory for the upload up front, and responds with [`EnclaveResponse::UploadStarted`].
+    BeginUpload { kind: UploadKind, total_len: u64 },
+    /// The next chunk of an upload, `offset` must equal the number of bytes received so far.
+    ///
+    /// The enclave responds with [`EnclaveResponse::UploadProgress`] once the chunk is buffered,
+    /// the host should wait for this before sending the next chunk.
+    UploadChunk {
+        upload_id: UploadId,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Finish an upload, the enclave checks all the bytes were received and their hash matches `digest`.
+    CommitUpload {
+        upload_id: UploadId,
+        digest: [u8; 32],
+    },
+    /// Discard an upload, releasing its memory.
+    AbortUpload { upload_id: UploadId },
+    /// An execution request, using a committed program and stdin upload.
+    ///
+    /// Both uploads are consumed by this request.
+    ExecuteUploaded {
+        program: UploadId,
+        stdin: UploadId,
+        cycle_limit: u64,
+        sign_cycles: bool,
+        sign_inputs: bool,
+        domain: Option<SignatureDomain>,
     },
-    /// Set the enclave's signing key.
-    SetSigningKey(Vec<u8>),
     /// Close the session, the enclave will drop the connection after this request.
     CloseSession,
+    /// An execution request for a program in the enclave's cache, identified by its [`program_hash`].
+    ///
+    /// If the program is not cached, the enclave responds with [`EnclaveError::ProgramNotCached`]
+    /// and the host should fall back to [`EnclaveRequest::Execute`].
+    ExecuteCached {
+        program_hash: [u8; 32],
+        stdin: sp1_sdk::SP1Stdin,
+        cycle_limit: u64,
+        sign_cycles: bool,
+        sign_inputs: bool,
+        domain: Option<SignatureDomain>,
+    },
+    /// Cancel an in-flight request on this connection.
+    ///
+    /// Cancellation is cooperative, the cancelled request responds with [`EnclaveError::Cancelled`]
+    /// once it stops. Cancelling a request that already finished is a no-op.
+    Cancel { request_id: RequestId },
+    /// Start migrating the signing key from another enclave, see [`EnclaveRequest::ExportSigningKey`].
+    ///
+    /// The enclave responds with [`EnclaveResponse::KeyMigrationRequest`], to be sent to the donor.
+    BeginKeyMigration,
+    /// Encrypt the signing keys to the enclave that produced `attestation`, as a donor.
+    ///
+    /// A rotation in progress is migrated with the keys, the requester keeps signing with the active key
+    /// until the next key's grace period is over.
+    ///
+    /// The requesting enclave must run the same image (PCR0) and TEE version.
+    /// The enclave responds with [`EnclaveResponse::ExportedSigningKey`].
+    ExportSigningKey { attestation: Vec<u8> },
+    /// Finish a migration, with the donor's [`EnclaveResponse::ExportedSigningKey`].
+    ///
+    /// The enclave responds with the migrated active [`EnclaveResponse::PublicKey`].
+    ImportSigningKey {
+        attestation: Vec<u8>,
+        ciphertext: Vec<u8>,
+    },
+    /// Generate a new signing key, which replaces the current one after `grace_period_secs`.
+    ///
+    /// Until then, executions are still signed with the current key, so the new one can be registered.
+    /// The enclave responds with [`EnclaveResponse::SigningKeys`].
+    RotateKey { grace_period_secs: u64 },
+    /// Request the enclave's signing keys, including the next key of a rotation in progress.
+    GetSigningKeys,
+    /// Request the state of the enclave's execution scheduler.
+    GetSchedulerStatus,
+    /// Request the execution policy the enclave was started with.
+    ///
+    /// The enclave responds with [`EnclaveResponse::Config`].
+    GetConfig,
+    /// Verify a compressed, Plonk or Groth16 proof of the program with verifying key `vkey`.
+    ///
+    /// If the proof is valid, the enclave responds with [`EnclaveResponse::ProofVerified`],
+    /// otherwise with [`EnclaveError::InvalidProof`]. If `domain` is set, the verdict is bound to it.
+    VerifyProof {
+        proof: Box<sp1_sdk::SP1ProofWithPublicValues>,
+        vkey: sp1_sdk::SP1VerifyingKey,
+        domain: Option<SignatureDomain>,
+    },
+    /// Run the known-answer test, executing a fixture and checking its vkey, public values and signature.
+    ///
+    /// The enclave responds with [`EnclaveResponse::SelfTest`], and refuses to advertise its signing key
+    /// until a self-test passes. Once one has, its report is returned without running the test again.
+    SelfTest,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum EnclaveResponse {
+    /// The response to [`EnclaveRequest::Hello`].
+    ///
+    /// NOTE: This MUST remain the first variant, so its encoding is stable across protocol versions.
+    Hello(EnclaveInfo),
     PublicKey(k256::EncodedPoint),
-    /// The enclave's signing key, encrypted with the host's public key.
+    /// The enclave's signing key, sealed so that only an enclave with the same measurements can recover it.
     EncryptedSigningKey(Vec<u8>),
     /// An attestation document with the public key field set.
     SigningKeyAttestation(Vec<u8>),
     /// The result of an execution, sent from the enclave to the host.
     ///
-    /// The signature is of the form [ vkey || public_values ]
+    /// The signature is over the [`SignedMessage`], which includes the cycle count
+    /// if [`ExecutionReport::cycles_signed`] is set, and the [`ExecutionReport::domain`]
+    /// and [`ExecutionReport::inputs`] if any.
     SignedPublicValues {
         vkey: [u8; 32],
         public_values: Vec<u8>,
         signature: k256::ecdsa::Signature,
         recovery_id: u8,
+        report: ExecutionReport,
+    },
+    /// The enclave is ready to receive the chunks of an upload.
+    UploadStarted {
+        upload_id: UploadId,
+    },
+    /// The number of bytes of an upload the enclave has received.
+    UploadProgress {
+        upload_id: UploadId,
+        received: u64,
     },
-    /// The receiver of this variant should print this message to stdout.
-    Error(String),
+    /// All the bytes of an upload were received, and hashed to `digest`.
+    UploadCommitted {
+        upload_id: UploadId,
+        digest: [u8; 32],
+    },
+    /// The request failed, see [`EnclaveError`] for whether it should be retried.
+    Error(EnclaveError),
     /// Indicate to the host that the enclave has received the message.
     Ack,
+    /// An attestation to an ephemeral key, asking a donor enclave for its signing key.
+    KeyMigrationRequest {
+        attestation: Vec<u8>,
+    },
+    /// The donor's signing key, encrypted to the requester's ephemeral key.
+    ///
+    /// The attestation binds the donor's ephemeral key, and uses the requester's as the nonce.
+    ExportedSigningKey {
+        attestation: Vec<u8>,
+        ciphertext: Vec<u8>,
+    },
+    /// The enclave's signing keys, each with an attestation.
+    SigningKeys(AttestedKeys),
+    /// The state of the enclave's execution scheduler.
+    SchedulerStatus(SchedulerStatus),
+    /// The execution policy the enclave was started with.
+    Config(EnclaveConfig),
+    /// The program deterministically failed, the enclave signed a receipt of the failure.
+    ///
+    /// This is sent instead of [`EnclaveResponse::Error`] for the errors of a [`FailureKind`].
+    SignedFailure {
+        error: EnclaveError,
+        receipt: SignedFailureReceipt,
+    },
+    /// The proof of a [`EnclaveRequest::VerifyProof`] is valid, the enclave signed a verdict saying so.
+    ProofVerified(SignedProofVerdict),
+    /// The outcome of a [`EnclaveRequest::SelfTest`], which may have failed.
+    SelfTest(SelfTestReport),
 }
 
 impl EnclaveRequest {
     pub fn type_of(&self) -> &'static str {
         match self {
+            EnclaveRequest::Hello { .. } => "Hello",
             EnclaveRequest::CloseSession => "CloseSession",
             EnclaveRequest::GetPublicKey => "GetPublicKey",
             EnclaveRequest::Print(_) => "Print",
-            EnclaveRequest::GetEncryptedSigningKey => "GetEncryptedSigningKey",
+            EnclaveRequest::GetEncryptedSigningKey { .. } => "GetEncryptedSigningKey",
             EnclaveRequest::Execute { .. } => "Execute",
-            EnclaveRequest::SetSigningKey(_) => "SetSigningKey",
-            EnclaveRequest::AttestSigningKey => "AttestSigningKey",
+            EnclaveRequest::SetSigningKey { .. } => "SetSigningKey",
+            EnclaveRequest::AttestSigningKey { .. } => "AttestSigningKey",
+            EnclaveRequest::BeginUpload { .. } => "BeginUpload",
+            EnclaveRequest::UploadChunk { .. } => "UploadChunk",
+            EnclaveRequest::CommitUpload { .. } => "CommitUpload",
+            EnclaveRequest::AbortUpload { .. } => "AbortUpload",
+            EnclaveRequest::ExecuteUploaded { .. } => "ExecuteUploaded",
+            EnclaveRequest::ExecuteCached { .. } => "ExecuteCached",
+            EnclaveRequest::Cancel { .. } => "Cancel",
+            EnclaveRequest::BeginKeyMigration => "BeginKeyMigration",
+            EnclaveRequest::ExportSigningKey { .. } => "ExportSigningKey",
+            EnclaveRequest::ImportSigningKey { .. } => "ImportSigningKey",
+            EnclaveRequest::RotateKey { .. } => "RotateKey",
+            EnclaveRequest::GetSigningKeys => "GetSigningKeys",
+            EnclaveRequest::GetSchedulerStatus => "GetSchedulerStatus",
+            EnclaveRequest::GetConfig => "GetConfig",
+            EnclaveRequest::VerifyProof { .. } => "VerifyProof",
+            EnclaveRequest::SelfTest => "SelfTest",
         }
     }
 }
@@ -72,12 +341,51 @@ impl EnclaveRequest {
 impl EnclaveResponse {
     pub fn type_of(&self) -> &'static str {
         match self {
+            EnclaveResponse::Hello(_) => "Hello",
             EnclaveResponse::PublicKey(_) => "PublicKey",
             EnclaveResponse::EncryptedSigningKey(_) => "EncryptedSigningKey",
             EnclaveResponse::SigningKeyAttestation(_) => "SigningKeyAttestation",
             EnclaveResponse::SignedPublicValues { .. } => "SignedPublicValues",
+            EnclaveResponse::UploadStarted { .. } => "UploadStarted",
+            EnclaveResponse::UploadProgress { .. } => "UploadProgress",
+            EnclaveResponse::UploadCommitted { .. } => "UploadCommitted",
             EnclaveResponse::Error(_) => "Error",
             EnclaveResponse::Ack => "Ack",
+            EnclaveResponse::KeyMigrationRequest { .. } => "KeyMigrationRequest",
+            EnclaveResponse::ExportedSigningKey { .. } => "ExportedSigningKey",
+            EnclaveResponse::SigningKeys(_) => "SigningKeys",
+            EnclaveResponse::SchedulerStatus(_) => "SchedulerStatus",
+            EnclaveResponse::Config(_) => "Config",
+            EnclaveResponse::SignedFailure { .. } => "SignedFailure",
+            EnclaveResponse::ProofVerified(_) => "ProofVerified",
+            EnclaveResponse::SelfTest(_) => "SelfTest",
+        }
+    }
+}
+
+impl Frame for EnclaveRequest {
+    const DEFAULT_LIMITS: FrameLimits = DEFAULT_REQUEST_FRAME_LIMITS;
+
+    fn frame_kind(&self) -> FrameKind {
+        match self {
+            EnclaveRequest::Execute { .. }
+            | EnclaveRequest::ExecuteCached { .. }
+            | EnclaveRequest::UploadChunk { .. }
+            | EnclaveRequest::VerifyProof { .. } => FrameKind::Payload,
+            _ => FrameKind::Control,
+        }
+    }
+}
+
+impl Frame for EnclaveResponse {
+    const DEFAULT_LIMITS: FrameLimits = DEFAULT_RESPONSE_FRAME_LIMITS;
+
+    fn frame_kind(&self) -> FrameKind {
+        match self {
+            EnclaveResponse::SignedPublicValues { .. } | EnclaveResponse::ProofVerified(_) => {
+                FrameKind::Payload
+            }
+            _ => FrameKind::Control,
         }
     }
 }
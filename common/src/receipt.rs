@@ -0,0 +1,206 @@
+use crate::signature::recover_address;
+use crate::{EnclaveError, SignatureDomain, SignatureError};
+
+use k256::ecdsa::Signature;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+
+/// Prefixes the message of a failure receipt, so it can never be mistaken for the signature of a result.
+pub const FAILURE_RECEIPT_TAG: &[u8] = b"SP1TeeFailureReceipt";
+
+/// How a program deterministically failed, running it again on the same input fails the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum FailureKind {
+    /// The program panicked, or otherwise halted abnormally.
+    ExecutionFailed = 1,
+    /// The program ran for more cycles than the cycle limit.
+    CycleLimitExceeded = 2,
+}
+
+impl FailureKind {
+    /// The kind of a deterministic failure, `None` if the error could be caused by the enclave or its environment.
+    pub fn of(error: &EnclaveError) -> Option<Self> {
+        match error {
+            EnclaveError::ExecutionFailed(_) => Some(FailureKind::ExecutionFailed),
+            EnclaveError::CycleLimitExceeded(_) => Some(FailureKind::CycleLimitExceeded),
+            _ => None,
+        }
+    }
+}
+
+/// Attests that a program failed on an input, signed by the enclave like a result.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailureReceipt {
+    /// The `SP1_TEE_VERSION` of the enclave.
+    pub tee_version: u32,
+    pub kind: FailureKind,
+    pub vkey: [u8; 32],
+    /// The canonical hash of the stdin, see [`crate::stdin_hash`].
+    pub stdin_hash: [u8; 32],
+    /// The number of cycles the program ran for before failing.
+    pub cycles: u64,
+    /// The cycle limit the program ran under.
+    pub cycle_limit: u64,
+    /// The domain the receipt is bound to, if the request set one.
+    pub domain: Option<SignatureDomain>,
+}
+
+impl FailureReceipt {
+    /// Encodes the message, before hashing:
+    ///
+    /// ```text
+    /// keccak(FAILURE_RECEIPT_TAG) || keccak(tee_version) || vkey || stdin_hash
+    ///     || kind || cycles || cycle_limit [|| chain_id || verifier || request_id || expires_at]
+    /// ```
+    ///
+    /// The kind is a single byte, the TEE version is little endian and the other integers are big endian `u64`s,
+    /// as in [`crate::SignedMessage`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut message = Vec::new();
+
+        message.extend_from_slice(&Keccak256::digest(FAILURE_RECEIPT_TAG));
+        message.extend_from_slice(&Keccak256::digest(self.tee_version.to_le_bytes()));
+        message.extend_from_slice(&self.vkey);
+        message.extend_from_slice(&self.stdin_hash);
+        message.push(self.kind as u8);
+        message.extend_from_slice(&self.cycles.to_be_bytes());
+        message.extend_from_slice(&self.cycle_limit.to_be_bytes());
+
+        if let Some(domain) = &self.domain {
+            domain.encode_into(&mut message);
+        }
+
+        message
+    }
+
+    /// The hasher over the encoded message, ready to be signed or recovered from.
+    pub fn digest(&self) -> Keccak256 {
+        Keccak256::new_with_prefix(self.encode())
+    }
+}
+
+/// A [`FailureReceipt`] and the enclave's signature over it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedFailureReceipt {
+    pub receipt: FailureReceipt,
+    pub signature: Signature,
+    /// Either `0` or `1`, as returned by the enclave.
+    pub recovery_id: u8,
+}
+
+impl SignedFailureReceipt {
+    /// Recovers the Ethereum address that signed the receipt, which must be checked against the registered signers.
+    ///
+    /// # Errors
+    /// - [`SignatureError::InvalidSignature`] - No public key could be recovered from the signature.
+    pub fn recover_signer(&self) -> Result<[u8; 20], SignatureError> {
+        recover_address(self.receipt.digest(), &self.signature, self.recovery_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signature::tests::{address, domain, signing_key};
+    use k256::ecdsa::SigningKey;
+
+    fn receipt(domain: Option<SignatureDomain>) -> FailureReceipt {
+        FailureReceipt {
+            tee_version: 1,
+            kind: FailureKind::CycleLimitExceeded,
+            vkey: [0xab; 32],
+            stdin_hash: [0xcd; 32],
+            cycles: 0x0102,
+            cycle_limit: 0x0100,
+            domain,
+        }
+    }
+
+    fn sign(key: &SigningKey, receipt: FailureReceipt) -> SignedFailureReceipt {
+        let (signature, recovery_id) = key.sign_digest_recoverable(receipt.digest()).unwrap();
+
+        SignedFailureReceipt {
+            receipt,
+            signature,
+            recovery_id: recovery_id.to_byte(),
+        }
+    }
+
+    #[test]
+    fn only_deterministic_failures_are_receipted() {
+        assert_eq!(
+            FailureKind::of(&EnclaveError::ExecutionFailed("panic".to_string())),
+            Some(FailureKind::ExecutionFailed)
+        );
+        assert_eq!(
+            FailureKind::of(&EnclaveError::CycleLimitExceeded(100)),
+            Some(FailureKind::CycleLimitExceeded)
+        );
+
+        assert_eq!(
+            FailureKind::of(&EnclaveError::ExecutionTimeLimitExceeded(60)),
+            None
+        );
+        assert_eq!(FailureKind::of(&EnclaveError::Cancelled), None);
+        assert_eq!(
+            FailureKind::of(&EnclaveError::WorkerCrashed("oom".to_string())),
+            None
+        );
+    }
+
+    #[test]
+    fn receipt_encoding() {
+        let expected = [
+            &Keccak256::digest(FAILURE_RECEIPT_TAG)[..],
+            &Keccak256::digest(1u32.to_le_bytes()),
+            &[0xab; 32],
+            &[0xcd; 32],
+            &[2],
+            &0x0102u64.to_be_bytes(),
+            &0x0100u64.to_be_bytes(),
+        ]
+        .concat();
+
+        assert_eq!(receipt(None).encode(), expected);
+    }
+
+    #[test]
+    fn domain_is_appended() {
+        let domain = domain(0x22);
+        let mut expected = receipt(None).encode();
+        domain.encode_into(&mut expected);
+
+        assert_eq!(receipt(Some(domain)).encode(), expected);
+    }
+
+    #[test]
+    fn receipts_are_not_result_signatures() {
+        let message = crate::SignedMessage {
+            tee_version: 1,
+            vkey: &[0xab; 32],
+            public_values: &[],
+            cycles: None,
+            domain: None,
+            inputs: None,
+        };
+
+        assert_ne!(receipt(None).encode()[..32], message.encode()[..32]);
+    }
+
+    #[test]
+    fn recovers_the_signer() {
+        let key = signing_key();
+        let signed = sign(&key, receipt(None));
+        let address = address(&key);
+
+        assert_eq!(signed.recover_signer().unwrap(), address);
+
+        // A tampered receipt recovers another signer, or none at all.
+        let mut tampered = signed.clone();
+        tampered.receipt.cycles += 1;
+        assert!(tampered
+            .recover_signer()
+            .map_or(true, |signer| signer != address));
+    }
+}
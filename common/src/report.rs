@@ -0,0 +1,32 @@
+use crate::{InputCommitment, SignatureDomain};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Statistics about an execution, returned alongside its signed public values.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionReport {
+    /// The number of cycles the program ran for.
+    pub cycles: u64,
+    /// Whether `cycles` is part of the signed message.
+    pub cycles_signed: bool,
+    /// The domain the signature is bound to, if the request set one.
+    #[serde(default)]
+    pub domain: Option<SignatureDomain>,
+    /// The inputs of the execution, if they are part of the signed message.
+    #[serde(default)]
+    pub inputs: Option<InputCommitment>,
+    /// The number of times each syscall was invoked, by name.
+    pub syscall_counts: BTreeMap<String, u64>,
+    /// The time spent setting up the program, zero if it was cached.
+    pub setup_time_ms: u64,
+    /// The time spent executing the program.
+    pub execute_time_ms: u64,
+    /// The time spent signing the public values.
+    pub sign_time_ms: u64,
+    /// The peak resident memory of the worker that ran the execution, if it could be measured.
+    ///
+    /// This covers setup and this execution only, not the signer or executions that ran alongside it.
+    /// To size the host's `--enclave-memory`, sum it over the executions expected to run at once, plus the signer.
+    pub peak_memory_bytes: Option<u64>,
+}
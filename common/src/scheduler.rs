@@ -0,0 +1,16 @@
+use serde::{Deserialize, Serialize};
+
+/// The state of the enclave's execution scheduler.
+///
+/// Executions are admitted while their estimated memory fits in the budget.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchedulerStatus {
+    /// The memory available to executions, in bytes.
+    pub memory_budget: u64,
+    /// The memory reserved by running executions, in bytes.
+    pub reserved_memory: u64,
+    /// The number of running executions.
+    pub running: u32,
+    /// The number of executions waiting to be admitted.
+    pub queued: u32,
+}
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+/// Temporary AWS credentials, forwarded by the host so the enclave can call KMS.
+///
+/// The enclave has no network access or instance metadata of its own,
+/// so it cannot resolve credentials itself.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl std::fmt::Debug for AwsCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Requests are logged by type, but never print the secrets even if one is debug printed.
+        f.debug_struct("AwsCredentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .field(
+                "session_token",
+                &self.session_token.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
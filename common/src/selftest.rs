@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// The outcome of the enclave's known-answer test, see [`crate::EnclaveRequest::SelfTest`].
+///
+/// Until a self-test passes, the enclave refuses to advertise its signing key.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SelfTestReport {
+    /// Whether the fixture's vkey, public values and signature all matched.
+    pub passed: bool,
+    /// Why the self-test failed, `None` if it passed.
+    pub error: Option<String>,
+    /// The time spent setting up the fixture.
+    pub setup_time_ms: u64,
+    /// The time spent executing the fixture.
+    pub execute_time_ms: u64,
+    /// The time spent signing the public values, and checking the signature.
+    pub sign_time_ms: u64,
+    /// The time the whole self-test took, including waiting for memory.
+    pub total_time_ms: u64,
+    /// The unix timestamp (in seconds) at which the self-test finished, by the enclave's clock.
+    pub completed_at: u64,
+}
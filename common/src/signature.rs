@@ -0,0 +1,468 @@
+use crate::InputCommitment;
+
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+use std::collections::HashSet;
+
+/// Prefixes the message of a domain separated signature, so it can never be mistaken for the legacy format.
+pub const SIGNATURE_DOMAIN_TAG: &[u8] = b"SP1TeeVerifier.SignatureDomain";
+
+/// Binds a signature to a single verifier deployment and request, so it cannot be replayed elsewhere.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureDomain {
+    /// The ID of the chain the verifier is deployed on.
+    pub chain_id: u64,
+    /// The address of the verifier contract.
+    pub verifier: [u8; 20],
+    /// The ID of the request, `TEERequest.id`.
+    pub request_id: [u8; 32],
+    /// The unix timestamp (in seconds) after which verifiers must reject the signature.
+    pub expires_at: u64,
+}
+
+impl SignatureDomain {
+    /// Appends the domain to a message, as `chain_id || verifier || request_id || expires_at`.
+    pub(crate) fn encode_into(&self, message: &mut Vec<u8>) {
+        message.extend_from_slice(&self.chain_id.to_be_bytes());
+        message.extend_from_slice(&self.verifier);
+        message.extend_from_slice(&self.request_id);
+        message.extend_from_slice(&self.expires_at.to_be_bytes());
+    }
+}
+
+/// The message the enclave signs for an execution, see [`crate::SIGNATURE_FORMAT`].
+#[derive(Debug, Clone, Copy)]
+pub struct SignedMessage<'a> {
+    /// The `SP1_TEE_VERSION` of the enclave.
+    pub tee_version: u32,
+    pub vkey: &'a [u8; 32],
+    pub public_values: &'a [u8],
+    /// The cycle count, if the request signed it.
+    pub cycles: Option<u64>,
+    /// The domain, if the request opted into domain separation.
+    pub domain: Option<&'a SignatureDomain>,
+    /// The inputs of the execution, if the request signed them.
+    pub inputs: Option<&'a InputCommitment>,
+}
+
+impl SignedMessage<'_> {
+    /// Encodes the message, before hashing.
+    ///
+    /// Without a domain, this is the legacy message:
+    ///
+    /// ```text
+    /// keccak(tee_version) || vkey || keccak(public_values) [|| cycles]
+    /// ```
+    ///
+    /// With a domain, the message is prefixed with `keccak(SIGNATURE_DOMAIN_TAG)`, and the domain is appended:
+    ///
+    /// ```text
+    /// keccak(SIGNATURE_DOMAIN_TAG) || keccak(tee_version) || vkey || keccak(public_values)
+    ///     || chain_id || verifier || request_id || expires_at [|| cycles]
+    /// ```
+    ///
+    /// In either format, signed inputs are appended last, as `program_hash || stdin_hash`.
+    ///
+    /// The TEE version is little endian, the other integers are big endian `u64`s, as `abi.encodePacked` would encode them.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut message = Vec::new();
+
+        if self.domain.is_some() {
+            message.extend_from_slice(&Keccak256::digest(SIGNATURE_DOMAIN_TAG));
+        }
+
+        message.extend_from_slice(&Keccak256::digest(self.tee_version.to_le_bytes()));
+        message.extend_from_slice(self.vkey);
+        message.extend_from_slice(&Keccak256::digest(self.public_values));
+
+        if let Some(domain) = self.domain {
+            domain.encode_into(&mut message);
+        }
+
+        if let Some(cycles) = self.cycles {
+            message.extend_from_slice(&cycles.to_be_bytes());
+        }
+
+        if let Some(inputs) = self.inputs {
+            message.extend_from_slice(&inputs.program_hash);
+            message.extend_from_slice(&inputs.stdin_hash);
+        }
+
+        message
+    }
+
+    /// The hasher over the encoded message, ready to be signed or recovered from.
+    pub fn digest(&self) -> Keccak256 {
+        Keccak256::new_with_prefix(self.encode())
+    }
+
+    /// The keccak256 hash of the encoded message, which two signatures must share to be over the same message.
+    pub fn hash(&self) -> [u8; 32] {
+        self.digest().finalize().into()
+    }
+
+    /// Recovers the Ethereum address that signed this message.
+    ///
+    /// The `recovery_id` is the one returned by the enclave, either `0` or `1`.
+    ///
+    /// # Errors
+    /// - [`SignatureError::InvalidSignature`] - No public key could be recovered from the signature.
+    pub fn recover_signer(
+        &self,
+        signature: &Signature,
+        recovery_id: u8,
+    ) -> Result<[u8; 20], SignatureError> {
+        recover_address(self.digest(), signature, recovery_id)
+    }
+}
+
+/// Recovers the Ethereum address that signed `digest`.
+pub(crate) fn recover_address(
+    digest: Keccak256,
+    signature: &Signature,
+    recovery_id: u8,
+) -> Result<[u8; 20], SignatureError> {
+    let recovery_id = RecoveryId::from_byte(recovery_id).ok_or(SignatureError::InvalidSignature)?;
+
+    let key = VerifyingKey::recover_from_digest(digest, signature, recovery_id)
+        .map_err(|_| SignatureError::InvalidSignature)?;
+
+    // Ethereum addresses are the last 20 bytes of `keccak256(x || y)`.
+    let point = key.to_encoded_point(false);
+    let hash = Keccak256::digest(&point.as_bytes()[1..]);
+
+    let mut address = [0; 20];
+    address.copy_from_slice(&hash[12..]);
+
+    Ok(address)
+}
+
+/// A reference verifier for domain separated signatures.
+///
+/// This makes the checks a verifier contract deployed at `address` on `chain_id` should make,
+/// including rejecting a request ID it has already accepted.
+pub struct DomainVerifier {
+    chain_id: u64,
+    address: [u8; 20],
+    signers: HashSet<[u8; 20]>,
+    /// The request IDs of the signatures accepted so far.
+    consumed: HashSet<[u8; 32]>,
+}
+
+impl DomainVerifier {
+    pub fn new(
+        chain_id: u64,
+        address: [u8; 20],
+        signers: impl IntoIterator<Item = [u8; 20]>,
+    ) -> Self {
+        Self {
+            chain_id,
+            address,
+            signers: signers.into_iter().collect(),
+            consumed: HashSet::new(),
+        }
+    }
+
+    /// Verifies a domain separated signature at the unix timestamp `now`, returning its signer.
+    ///
+    /// On success, the request ID is consumed, so the same signature is rejected if it is replayed.
+    ///
+    /// # Errors
+    /// - [`SignatureError::MissingDomain`] - The message is not domain separated.
+    /// - [`SignatureError::WrongChain`] - The signature is for another chain.
+    /// - [`SignatureError::WrongVerifier`] - The signature is for another verifier.
+    /// - [`SignatureError::Expired`] - The signature expired before `now`.
+    /// - [`SignatureError::RequestReplayed`] - A signature for the same request was already accepted.
+    /// - [`SignatureError::InvalidSignature`] - The signature is malformed.
+    /// - [`SignatureError::UnknownSigner`] - The signer is not a registered enclave key.
+    pub fn verify(
+        &mut self,
+        message: &SignedMessage<'_>,
+        signature: &Signature,
+        recovery_id: u8,
+        now: u64,
+    ) -> Result<[u8; 20], SignatureError> {
+        let domain = message.domain.ok_or(SignatureError::MissingDomain)?;
+
+        if domain.chain_id != self.chain_id {
+            return Err(SignatureError::WrongChain {
+                expected: self.chain_id,
+                found: domain.chain_id,
+            });
+        }
+
+        if domain.verifier != self.address {
+            return Err(SignatureError::WrongVerifier);
+        }
+
+        if now > domain.expires_at {
+            return Err(SignatureError::Expired {
+                expires_at: domain.expires_at,
+            });
+        }
+
+        if self.consumed.contains(&domain.request_id) {
+            return Err(SignatureError::RequestReplayed);
+        }
+
+        let signer = message.recover_signer(signature, recovery_id)?;
+
+        if !self.signers.contains(&signer) {
+            return Err(SignatureError::UnknownSigner(signer));
+        }
+
+        self.consumed.insert(domain.request_id);
+
+        Ok(signer)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignatureError {
+    #[error("The signature is not domain separated")]
+    MissingDomain,
+
+    #[error("The signature is for chain {found}, expected {expected}")]
+    WrongChain { expected: u64, found: u64 },
+
+    #[error("The signature is for another verifier")]
+    WrongVerifier,
+
+    #[error("The signature expired at {expires_at}")]
+    Expired { expires_at: u64 },
+
+    #[error("A signature for this request was already accepted")]
+    RequestReplayed,
+
+    #[error("Invalid signature")]
+    InvalidSignature,
+
+    #[error("Unknown signer: 0x{}", hex_string(.0))]
+    UnknownSigner([u8; 20]),
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+
+    const VKEY: [u8; 32] = [0xab; 32];
+    const PUBLIC_VALUES: &[u8] = b"public values";
+    const CHAIN_ID: u64 = 1;
+    const VERIFIER: [u8; 20] = [0x11; 20];
+    const EXPIRES_AT: u64 = 1_000;
+
+    fn message<'a>(domain: Option<&'a SignatureDomain>) -> SignedMessage<'a> {
+        SignedMessage {
+            tee_version: 1,
+            vkey: &VKEY,
+            public_values: PUBLIC_VALUES,
+            cycles: None,
+            domain,
+            inputs: None,
+        }
+    }
+
+    pub(crate) fn domain(request_id: u8) -> SignatureDomain {
+        SignatureDomain {
+            chain_id: CHAIN_ID,
+            verifier: VERIFIER,
+            request_id: [request_id; 32],
+            expires_at: EXPIRES_AT,
+        }
+    }
+
+    pub(crate) fn signing_key() -> SigningKey {
+        SigningKey::from_slice(&[7; 32]).unwrap()
+    }
+
+    fn sign(key: &SigningKey, message: &SignedMessage<'_>) -> (Signature, u8) {
+        let (signature, recovery_id) = key.sign_digest_recoverable(message.digest()).unwrap();
+
+        (signature, recovery_id.to_byte())
+    }
+
+    pub(crate) fn address(key: &SigningKey) -> [u8; 20] {
+        let point = key.verifying_key().to_encoded_point(false);
+
+        Keccak256::digest(&point.as_bytes()[1..])[12..]
+            .try_into()
+            .unwrap()
+    }
+
+    fn verifier() -> DomainVerifier {
+        DomainVerifier::new(CHAIN_ID, VERIFIER, [address(&signing_key())])
+    }
+
+    #[test]
+    fn legacy_message_encoding() {
+        let message = message(None);
+
+        // keccak(keccak(version) || vkey || keccak(publicValues)), as the deployed verifier hashes it.
+        let expected = [
+            &Keccak256::digest(1u32.to_le_bytes())[..],
+            &VKEY,
+            &Keccak256::digest(PUBLIC_VALUES),
+        ]
+        .concat();
+
+        assert_eq!(message.encode(), expected);
+        assert_eq!(message.encode().len(), 96);
+        assert_eq!(
+            message.hash(),
+            <[u8; 32]>::from(Keccak256::digest(&expected))
+        );
+    }
+
+    #[test]
+    fn cycles_are_appended_big_endian() {
+        let message = SignedMessage {
+            cycles: Some(0x0102),
+            ..message(None)
+        };
+
+        let encoded = message.encode();
+
+        assert_eq!(encoded[..96], self::message(None).encode());
+        assert_eq!(encoded[96..], [0, 0, 0, 0, 0, 0, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn domain_message_encoding() {
+        let domain = domain(3);
+        let encoded = message(Some(&domain)).encode();
+
+        let expected = [
+            &Keccak256::digest(SIGNATURE_DOMAIN_TAG)[..],
+            &message(None).encode(),
+            &CHAIN_ID.to_be_bytes(),
+            &VERIFIER,
+            &[3; 32],
+            &EXPIRES_AT.to_be_bytes(),
+        ]
+        .concat();
+
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn recovers_the_signer() {
+        let key = signing_key();
+        let message = message(None);
+        let (signature, recovery_id) = sign(&key, &message);
+
+        assert_eq!(
+            message.recover_signer(&signature, recovery_id).unwrap(),
+            address(&key)
+        );
+        assert!(matches!(
+            message.recover_signer(&signature, 4),
+            Err(SignatureError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn accepts_domain_signatures_until_they_expire() {
+        let domain = domain(1);
+        let message = message(Some(&domain));
+        let (signature, recovery_id) = sign(&signing_key(), &message);
+
+        assert_eq!(
+            verifier()
+                .verify(&message, &signature, recovery_id, EXPIRES_AT)
+                .unwrap(),
+            address(&signing_key())
+        );
+
+        assert!(matches!(
+            verifier().verify(&message, &signature, recovery_id, EXPIRES_AT + 1),
+            Err(SignatureError::Expired {
+                expires_at: EXPIRES_AT
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_replayed_requests() {
+        let mut verifier = verifier();
+
+        let domain = domain(1);
+        let message = message(Some(&domain));
+        let (signature, recovery_id) = sign(&signing_key(), &message);
+
+        verifier
+            .verify(&message, &signature, recovery_id, 0)
+            .unwrap();
+
+        assert!(matches!(
+            verifier.verify(&message, &signature, recovery_id, 0),
+            Err(SignatureError::RequestReplayed)
+        ));
+
+        // Another request is still accepted.
+        let domain = self::domain(2);
+        let message = self::message(Some(&domain));
+        let (signature, recovery_id) = sign(&signing_key(), &message);
+
+        verifier
+            .verify(&message, &signature, recovery_id, 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn rejected_signatures_do_not_consume_the_request() {
+        let mut verifier = verifier();
+
+        let domain = domain(1);
+        let message = message(Some(&domain));
+
+        let other_key = SigningKey::from_slice(&[8; 32]).unwrap();
+        let (signature, recovery_id) = sign(&other_key, &message);
+
+        assert!(matches!(
+            verifier.verify(&message, &signature, recovery_id, 0),
+            Err(SignatureError::UnknownSigner(signer)) if signer == address(&other_key)
+        ));
+
+        let (signature, recovery_id) = sign(&signing_key(), &message);
+        verifier
+            .verify(&message, &signature, recovery_id, 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn rejects_other_domains() {
+        let (signature, recovery_id) = sign(&signing_key(), &message(None));
+        assert!(matches!(
+            verifier().verify(&message(None), &signature, recovery_id, 0),
+            Err(SignatureError::MissingDomain)
+        ));
+
+        let other_chain = SignatureDomain {
+            chain_id: CHAIN_ID + 1,
+            ..domain(1)
+        };
+        let message = self::message(Some(&other_chain));
+        let (signature, recovery_id) = sign(&signing_key(), &message);
+        assert!(matches!(
+            verifier().verify(&message, &signature, recovery_id, 0),
+            Err(SignatureError::WrongChain { .. })
+        ));
+
+        let other_verifier = SignatureDomain {
+            verifier: [0x22; 20],
+            ..domain(1)
+        };
+        let message = self::message(Some(&other_verifier));
+        let (signature, recovery_id) = sign(&signing_key(), &message);
+        assert!(matches!(
+            verifier().verify(&message, &signature, recovery_id, 0),
+            Err(SignatureError::WrongVerifier)
+        ));
+    }
+}
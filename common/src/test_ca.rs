@@ -0,0 +1,383 @@
+//! A certificate chain for a software NSM to sign attestations with, see [`crate::cose`].
+//!
+//! This mirrors the chain of a Nitro attestation, a root CA and a leaf certificate that signs the document,
+//! both ECDSA P-384. The certificates are encoded by hand, as they only need the fields a verifier checks.
+//!
+//! The root can be shared, see [`TestCa::load_or_generate`], so the attestations of several enclaves
+//! verify against one root, as they do on Nitro.
+
+use crate::EnclaveError;
+use ring::{
+    rand::SystemRandom,
+    signature::{
+        EcdsaKeyPair, KeyPair, ECDSA_P384_SHA384_ASN1_SIGNING, ECDSA_P384_SHA384_FIXED_SIGNING,
+    },
+};
+use std::path::Path;
+
+/// The DER encoded OID of ecdsa-with-SHA384.
+const ECDSA_WITH_SHA384: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+
+/// The DER encoded OID of id-ecPublicKey.
+const EC_PUBLIC_KEY: &[u8] = &[0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+
+/// The DER encoded OID of the secp384r1 curve.
+const SECP384R1: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x22];
+
+/// The DER encoded OID of the common name attribute.
+const COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+
+/// The DER encoded OID of the basic constraints extension.
+const BASIC_CONSTRAINTS: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x13];
+
+/// The DER encoded OID of the key usage extension.
+const KEY_USAGE: &[u8] = &[0x06, 0x03, 0x55, 0x1d, 0x0f];
+
+const ROOT_NAME: &str = "sp1-tee test root";
+const LEAF_NAME: &str = "sp1-tee mock nsm";
+
+/// The file of a shared root's PKCS#8 encoded key, see [`TestCa::load_or_generate`].
+pub const ROOT_KEY_FILE: &str = "root.key";
+
+/// The file of a shared root's DER encoded certificate, see [`TestCa::load_or_generate`].
+pub const ROOT_CERT_FILE: &str = "root.der";
+
+pub struct TestCa {
+    /// The DER encoded root certificate, which verifiers must trust.
+    root_cert: Vec<u8>,
+    /// The DER encoded certificate of `leaf_key`, issued by the root.
+    leaf_cert: Vec<u8>,
+    /// Signs attestation documents, as `r || s` for COSE.
+    leaf_key: EcdsaKeyPair,
+}
+
+impl TestCa {
+    /// Generates a new root and leaf certificate.
+    pub fn generate() -> Result<Self, EnclaveError> {
+        let (root_key, root_cert) = generate_root()?;
+
+        Self::issue(&root_key, root_cert)
+    }
+
+    /// Loads the root from `dir`, generating it if the directory has none, and issues a new leaf certificate.
+    ///
+    /// The root is stored as [`ROOT_KEY_FILE`] and [`ROOT_CERT_FILE`], verifiers should trust the latter.
+    pub fn load_or_generate(dir: &Path) -> Result<Self, EnclaveError> {
+        let key_path = dir.join(ROOT_KEY_FILE);
+        let cert_path = dir.join(ROOT_CERT_FILE);
+
+        let io_error = |e: std::io::Error| {
+            EnclaveError::Internal(format!("Failed to access the test CA in {:?}: {}", dir, e))
+        };
+
+        if !key_path.exists() {
+            let (root_key, root_cert) = generate_root()?;
+
+            std::fs::create_dir_all(dir).map_err(io_error)?;
+            std::fs::write(&cert_path, &root_cert).map_err(io_error)?;
+            // Written last, so a root is only loaded once both files are complete.
+            std::fs::write(&key_path, &root_key).map_err(io_error)?;
+
+            return Self::issue(&root_key, root_cert);
+        }
+
+        let root_key = std::fs::read(&key_path).map_err(io_error)?;
+        let root_cert = std::fs::read(&cert_path).map_err(io_error)?;
+
+        Self::issue(&root_key, root_cert)
+    }
+
+    /// Issues a new leaf certificate from the PKCS#8 encoded `root_key`, whose certificate is `root_cert`.
+    fn issue(root_key: &[u8], root_cert: Vec<u8>) -> Result<Self, EnclaveError> {
+        let rng = SystemRandom::new();
+
+        let root_key = EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_ASN1_SIGNING, root_key, &rng)
+            .map_err(|_| EnclaveError::Internal("Invalid test CA root key".to_string()))?;
+        let leaf_key = generate_key(&ECDSA_P384_SHA384_FIXED_SIGNING, &rng)?;
+
+        let leaf_cert = certificate(
+            &root_key,
+            2,
+            ROOT_NAME,
+            LEAF_NAME,
+            leaf_key.public_key().as_ref(),
+            false,
+            &rng,
+        )?;
+
+        Ok(Self {
+            root_cert,
+            leaf_cert,
+            leaf_key,
+        })
+    }
+
+    pub fn root_cert(&self) -> &[u8] {
+        &self.root_cert
+    }
+
+    pub fn leaf_cert(&self) -> &[u8] {
+        &self.leaf_cert
+    }
+
+    pub fn leaf_key(&self) -> &EcdsaKeyPair {
+        &self.leaf_key
+    }
+}
+
+/// Generates a root key and its self signed certificate, returning the PKCS#8 encoded key and the certificate.
+fn generate_root() -> Result<(Vec<u8>, Vec<u8>), EnclaveError> {
+    let rng = SystemRandom::new();
+    let failed = || EnclaveError::Internal("Failed to generate test CA key".to_string());
+
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P384_SHA384_ASN1_SIGNING, &rng)
+        .map_err(|_| failed())?;
+    let root_key = EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+        .map_err(|_| failed())?;
+
+    let root_cert = certificate(
+        &root_key,
+        1,
+        ROOT_NAME,
+        ROOT_NAME,
+        root_key.public_key().as_ref(),
+        true,
+        &rng,
+    )?;
+
+    Ok((pkcs8.as_ref().to_vec(), root_cert))
+}
+
+fn generate_key(
+    alg: &'static ring::signature::EcdsaSigningAlgorithm,
+    rng: &SystemRandom,
+) -> Result<EcdsaKeyPair, EnclaveError> {
+    let failed = || EnclaveError::Internal("Failed to generate test CA key".to_string());
+
+    let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, rng).map_err(|_| failed())?;
+
+    EcdsaKeyPair::from_pkcs8(alg, pkcs8.as_ref(), rng).map_err(|_| failed())
+}
+
+/// Issues a certificate for `public_key`, signed by `issuer_key`.
+///
+/// The certificates never expire, the chain only lives as long as the enclave.
+fn certificate(
+    issuer_key: &EcdsaKeyPair,
+    serial: u8,
+    issuer: &str,
+    subject: &str,
+    public_key: &[u8],
+    is_ca: bool,
+    rng: &SystemRandom,
+) -> Result<Vec<u8>, EnclaveError> {
+    let signature_algorithm = sequence(&[ECDSA_WITH_SHA384]);
+
+    let validity = sequence(&[
+        // 2000-01-01
+        &tlv(0x17, b"000101000000Z"),
+        // The "no well-defined expiration date" value of RFC 5280.
+        &tlv(0x18, b"99991231235959Z"),
+    ]);
+
+    let public_key_info = sequence(&[
+        &sequence(&[EC_PUBLIC_KEY, SECP384R1]),
+        &bit_string(public_key),
+    ]);
+
+    let extensions = if is_ca {
+        vec![
+            // Critical, cA = true.
+            extension(BASIC_CONSTRAINTS, &sequence(&[&[0x01, 0x01, 0xff]])),
+            // keyCertSign and cRLSign.
+            extension(KEY_USAGE, &[0x03, 0x02, 0x01, 0x06]),
+        ]
+    } else {
+        // digitalSignature.
+        vec![extension(KEY_USAGE, &[0x03, 0x02, 0x07, 0x80])]
+    };
+    let extensions = extensions.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+    let tbs_certificate = sequence(&[
+        // Version 3.
+        &tlv(0xa0, &[0x02, 0x01, 0x02]),
+        &[0x02, 0x01, serial],
+        &signature_algorithm,
+        &name(issuer),
+        &validity,
+        &name(subject),
+        &public_key_info,
+        &tlv(0xa3, &sequence(&extensions)),
+    ]);
+
+    let signature = issuer_key
+        .sign(rng, &tbs_certificate)
+        .map_err(|_| EnclaveError::Internal("Failed to sign test CA certificate".to_string()))?;
+
+    Ok(sequence(&[
+        &tbs_certificate,
+        &signature_algorithm,
+        &bit_string(signature.as_ref()),
+    ]))
+}
+
+/// A name with only a common name.
+fn name(common_name: &str) -> Vec<u8> {
+    let attribute = sequence(&[COMMON_NAME, &tlv(0x0c, common_name.as_bytes())]);
+
+    sequence(&[&tlv(0x31, &attribute)])
+}
+
+/// A critical extension, `value` is wrapped in an OCTET STRING.
+fn extension(oid: &[u8], value: &[u8]) -> Vec<u8> {
+    sequence(&[oid, &[0x01, 0x01, 0xff], &tlv(0x04, value)])
+}
+
+fn bit_string(bytes: &[u8]) -> Vec<u8> {
+    // No unused bits.
+    tlv(0x03, &[&[0x00], bytes].concat())
+}
+
+fn sequence(parts: &[&[u8]]) -> Vec<u8> {
+    tlv(0x30, &parts.concat())
+}
+
+/// Encodes a DER tag, length and value.
+fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut der = vec![tag];
+
+    if value.len() < 0x80 {
+        der.push(value.len() as u8);
+    } else {
+        let len = value.len().to_be_bytes();
+        let len = &len[len
+            .iter()
+            .position(|byte| *byte != 0)
+            .unwrap_or(len.len() - 1)..];
+
+        der.push(0x80 | len.len() as u8);
+        der.extend_from_slice(len);
+    }
+
+    der.extend_from_slice(value);
+
+    der
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls_pki_types::{CertificateDer, UnixTime};
+
+    #[test]
+    fn tlv_short_form_lengths() {
+        assert_eq!(tlv(0x04, &[]), [0x04, 0x00]);
+        assert_eq!(tlv(0x04, &[0xaa; 0x7f])[..2], [0x04, 0x7f]);
+    }
+
+    #[test]
+    fn tlv_long_form_lengths() {
+        let der = tlv(0x04, &[0xaa; 0x80]);
+        assert_eq!(der[..3], [0x04, 0x81, 0x80]);
+        assert_eq!(der.len(), 3 + 0x80);
+
+        let der = tlv(0x04, &[0xaa; 0xff]);
+        assert_eq!(der[..3], [0x04, 0x81, 0xff]);
+
+        let der = tlv(0x04, &[0xaa; 0x100]);
+        assert_eq!(der[..4], [0x04, 0x82, 0x01, 0x00]);
+        assert_eq!(der.len(), 4 + 0x100);
+
+        let der = tlv(0x04, &[0xaa; 0x1_0000]);
+        assert_eq!(der[..5], [0x04, 0x83, 0x01, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn bit_strings_have_no_unused_bits() {
+        assert_eq!(bit_string(&[0xff]), [0x03, 0x02, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn webpki_accepts_the_chain() {
+        let ca = TestCa::generate().unwrap();
+
+        let root = CertificateDer::from(ca.root_cert());
+        let anchor = webpki::anchor_from_trusted_cert(&root).unwrap();
+
+        let leaf = CertificateDer::from(ca.leaf_cert());
+        let leaf = webpki::EndEntityCert::try_from(&leaf).unwrap();
+
+        leaf.verify_for_usage(
+            &[webpki::ring::ECDSA_P384_SHA384],
+            &[anchor],
+            &[],
+            UnixTime::now(),
+            webpki::KeyUsage::client_auth(),
+            None,
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn webpki_rejects_other_roots() {
+        let ca = TestCa::generate().unwrap();
+        let other = TestCa::generate().unwrap();
+
+        let root = CertificateDer::from(other.root_cert());
+        let anchor = webpki::anchor_from_trusted_cert(&root).unwrap();
+
+        let leaf = CertificateDer::from(ca.leaf_cert());
+        let leaf = webpki::EndEntityCert::try_from(&leaf).unwrap();
+
+        assert!(leaf
+            .verify_for_usage(
+                &[webpki::ring::ECDSA_P384_SHA384],
+                &[anchor],
+                &[],
+                UnixTime::now(),
+                webpki::KeyUsage::client_auth(),
+                None,
+                None,
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn shares_a_root() {
+        let dir = std::env::temp_dir().join(format!("sp1-tee-test-ca-{}", std::process::id()));
+
+        let first = TestCa::load_or_generate(&dir).unwrap();
+        let second = TestCa::load_or_generate(&dir).unwrap();
+
+        assert_eq!(first.root_cert(), second.root_cert());
+        assert_eq!(
+            std::fs::read(dir.join(ROOT_CERT_FILE)).unwrap(),
+            first.root_cert()
+        );
+        assert_ne!(first.leaf_cert(), second.leaf_cert());
+
+        // Both leaves chain to the shared root.
+        let root = CertificateDer::from(first.root_cert());
+        let anchor = webpki::anchor_from_trusted_cert(&root).unwrap();
+
+        for ca in [&first, &second] {
+            let leaf = CertificateDer::from(ca.leaf_cert());
+
+            webpki::EndEntityCert::try_from(&leaf)
+                .unwrap()
+                .verify_for_usage(
+                    &[webpki::ring::ECDSA_P384_SHA384],
+                    std::slice::from_ref(&anchor),
+                    &[],
+                    UnixTime::now(),
+                    webpki::KeyUsage::client_auth(),
+                    None,
+                    None,
+                )
+                .unwrap();
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
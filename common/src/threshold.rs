@@ -0,0 +1,534 @@
+use crate::{SignatureError, SignedMessage};
+
+use k256::ecdsa::Signature;
+use sha3::{Digest, Keccak256};
+use std::collections::HashSet;
+
+/// The name hashed into the selector of threshold proofs.
+///
+/// This differs from the single signature `SP1TeeVerifier`, so neither format is accepted as the other.
+pub const THRESHOLD_VERIFIER_NAME: &[u8] = b"SP1TeeThresholdVerifier";
+
+/// The length of a signature in a threshold proof, `v || r || s`.
+const SIGNATURE_LEN: usize = 65;
+
+/// Signatures from several enclaves over the same message.
+///
+/// The `SP1TeeVerifier` contract only accepts single signatures, it rejects these proofs by their selector.
+/// [`ThresholdVerifier`] is a reference verifier for them.
+///
+/// Encoded as:
+///
+/// ```text
+/// selector (4) || version_len (1) || version || count (1) || (v (1) || r (32) || s (32)) * count
+/// ```
+///
+/// The selector is the first 4 bytes of `keccak256(THRESHOLD_VERIFIER_NAME)`, the version is the little endian
+/// TEE version, and `v` is the recovery ID plus 27. Signatures are sorted by the address of their signer,
+/// so verifiers can reject duplicates by requiring strictly increasing addresses.
+/// The count is a single byte, so a proof holds at most [`ThresholdProof::MAX_SIGNATURES`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThresholdProof {
+    /// The `SP1_TEE_VERSION` the signatures were made with.
+    pub tee_version: u32,
+    /// Each signature with its recovery ID, either `0` or `1`.
+    signatures: Vec<(Signature, u8)>,
+}
+
+impl ThresholdProof {
+    /// The most signatures a proof can hold.
+    pub const MAX_SIGNATURES: usize = u8::MAX as usize;
+
+    /// Builds a proof from signatures over `message`, sorting them by signer and dropping duplicate signers.
+    ///
+    /// # Errors
+    /// - [`ThresholdError::Signature`] - A signer could not be recovered from a signature.
+    /// - [`ThresholdError::TooManySignatures`] - There are more than [`ThresholdProof::MAX_SIGNATURES`] signers.
+    pub fn new(
+        message: &SignedMessage<'_>,
+        signatures: impl IntoIterator<Item = (Signature, u8)>,
+    ) -> Result<Self, ThresholdError> {
+        let mut signed = signatures
+            .into_iter()
+            .map(|(signature, recovery_id)| {
+                Ok((
+                    message.recover_signer(&signature, recovery_id)?,
+                    (signature, recovery_id),
+                ))
+            })
+            .collect::<Result<Vec<_>, SignatureError>>()?;
+
+        signed.sort_by_key(|(signer, _)| *signer);
+        signed.dedup_by_key(|(signer, _)| *signer);
+
+        if signed.len() > Self::MAX_SIGNATURES {
+            return Err(ThresholdError::TooManySignatures(signed.len()));
+        }
+
+        Ok(Self {
+            tee_version: message.tee_version,
+            signatures: signed.into_iter().map(|(_, signature)| signature).collect(),
+        })
+    }
+
+    /// Each signature with its recovery ID, sorted by signer.
+    pub fn signatures(&self) -> &[(Signature, u8)] {
+        &self.signatures
+    }
+
+    /// The selector at the start of every threshold proof.
+    pub fn selector() -> [u8; 4] {
+        let hash = Keccak256::digest(THRESHOLD_VERIFIER_NAME);
+
+        [hash[0], hash[1], hash[2], hash[3]]
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let version = self.tee_version.to_le_bytes();
+
+        let mut bytes = Self::selector().to_vec();
+        bytes.push(version.len() as u8);
+        bytes.extend_from_slice(&version);
+        // `new` and `decode` never hold more than `MAX_SIGNATURES`.
+        bytes.push(self.signatures.len() as u8);
+
+        for (signature, recovery_id) in &self.signatures {
+            bytes.push(recovery_id + 27);
+            bytes.extend_from_slice(&signature.to_bytes());
+        }
+
+        bytes
+    }
+
+    /// Decodes a threshold proof.
+    ///
+    /// # Errors
+    /// - [`ThresholdError::Malformed`] - The bytes are not a threshold proof.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ThresholdError> {
+        let rest = bytes
+            .strip_prefix(&Self::selector())
+            .ok_or(ThresholdError::Malformed("wrong selector"))?;
+
+        let version = match rest.split_first() {
+            Some((4, rest)) if rest.len() >= 4 => rest,
+            _ => return Err(ThresholdError::Malformed("expected a 4 byte version")),
+        };
+        let (version, rest) = version.split_at(4);
+        let tee_version = u32::from_le_bytes(version.try_into().expect("version is 4 bytes"));
+
+        let Some((&count, rest)) = rest.split_first() else {
+            return Err(ThresholdError::Malformed("missing signature count"));
+        };
+
+        if rest.len() != count as usize * SIGNATURE_LEN {
+            return Err(ThresholdError::Malformed("wrong number of signature bytes"));
+        }
+
+        let signatures = rest
+            .chunks_exact(SIGNATURE_LEN)
+            .map(|chunk| {
+                let recovery_id = chunk[0]
+                    .checked_sub(27)
+                    .filter(|recovery_id| *recovery_id <= 1)
+                    .ok_or(ThresholdError::Malformed("invalid recovery ID"))?;
+                let signature = Signature::from_slice(&chunk[1..])
+                    .map_err(|_| ThresholdError::Malformed("invalid signature"))?;
+
+                Ok((signature, recovery_id))
+            })
+            .collect::<Result<_, ThresholdError>>()?;
+
+        Ok(Self {
+            tee_version,
+            signatures,
+        })
+    }
+}
+
+/// A reference verifier for [`ThresholdProof`]s, requiring `threshold` distinct registered signers.
+pub struct ThresholdVerifier {
+    signers: HashSet<[u8; 20]>,
+    threshold: usize,
+}
+
+impl ThresholdVerifier {
+    /// Creates a verifier for the registered `signers`.
+    ///
+    /// # Errors
+    /// - [`ThresholdError::InvalidThreshold`] - The threshold is 0, which would accept a proof without signatures,
+    ///   or more than the number of distinct signers, which no proof could meet.
+    pub fn new(
+        signers: impl IntoIterator<Item = [u8; 20]>,
+        threshold: usize,
+    ) -> Result<Self, ThresholdError> {
+        let signers: HashSet<_> = signers.into_iter().collect();
+
+        if threshold == 0 || threshold > signers.len() {
+            return Err(ThresholdError::InvalidThreshold {
+                threshold,
+                signers: signers.len(),
+            });
+        }
+
+        Ok(Self { signers, threshold })
+    }
+
+    /// Verifies the bytes of a threshold proof over the default signed message, given the same arguments
+    /// as `SP1TeeVerifier.verifyProof`.
+    ///
+    /// # Errors
+    /// See [`ThresholdVerifier::verify`].
+    pub fn verify_proof(
+        &self,
+        vkey: &[u8; 32],
+        public_values: &[u8],
+        proof_bytes: &[u8],
+    ) -> Result<Vec<[u8; 20]>, ThresholdError> {
+        let proof = ThresholdProof::decode(proof_bytes)?;
+
+        let message = SignedMessage {
+            tee_version: proof.tee_version,
+            vkey,
+            public_values,
+            cycles: None,
+            domain: None,
+            inputs: None,
+        };
+
+        self.verify(&message, &proof)
+    }
+
+    /// Verifies that `proof` holds at least `threshold` signatures over `message`, returning the signers.
+    ///
+    /// # Errors
+    /// - [`ThresholdError::VersionMismatch`] - The proof is for another TEE version than the message.
+    /// - [`ThresholdError::Signature`] - A signer could not be recovered from a signature.
+    /// - [`ThresholdError::UnsortedSigners`] - The signers are not strictly increasing, i.e. one signed twice.
+    /// - [`ThresholdError::UnknownSigner`] - A signer is not registered.
+    /// - [`ThresholdError::BelowThreshold`] - There are fewer than `threshold` signatures.
+    pub fn verify(
+        &self,
+        message: &SignedMessage<'_>,
+        proof: &ThresholdProof,
+    ) -> Result<Vec<[u8; 20]>, ThresholdError> {
+        if proof.tee_version != message.tee_version {
+            return Err(ThresholdError::VersionMismatch);
+        }
+
+        let mut signers: Vec<[u8; 20]> = Vec::with_capacity(proof.signatures.len());
+
+        for (signature, recovery_id) in &proof.signatures {
+            let signer = message.recover_signer(signature, *recovery_id)?;
+
+            if signers.last().is_some_and(|last| *last >= signer) {
+                return Err(ThresholdError::UnsortedSigners);
+            }
+
+            if !self.signers.contains(&signer) {
+                return Err(ThresholdError::UnknownSigner(signer));
+            }
+
+            signers.push(signer);
+        }
+
+        if signers.len() < self.threshold {
+            return Err(ThresholdError::BelowThreshold {
+                threshold: self.threshold,
+                found: signers.len(),
+            });
+        }
+
+        Ok(signers)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ThresholdError {
+    #[error("Malformed threshold proof: {0}")]
+    Malformed(&'static str),
+
+    #[error("The proof is for another TEE version")]
+    VersionMismatch,
+
+    #[error(transparent)]
+    Signature(#[from] SignatureError),
+
+    #[error("The signatures are not sorted by signer, or a signer signed twice")]
+    UnsortedSigners,
+
+    #[error("Unknown signer: 0x{}", hex_string(.0))]
+    UnknownSigner([u8; 20]),
+
+    #[error("Expected at least {threshold} signatures, found {found}")]
+    BelowThreshold { threshold: usize, found: usize },
+
+    #[error(
+        "The threshold must be between 1 and the number of signers ({signers}), found {threshold}"
+    )]
+    InvalidThreshold { threshold: usize, signers: usize },
+
+    #[error(
+        "A proof holds at most {} signatures, found {0}",
+        ThresholdProof::MAX_SIGNATURES
+    )]
+    TooManySignatures(usize),
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+
+    const VKEY: [u8; 32] = [0xab; 32];
+    const PUBLIC_VALUES: &[u8] = b"public values";
+
+    fn message() -> SignedMessage<'static> {
+        SignedMessage {
+            tee_version: 1,
+            vkey: &VKEY,
+            public_values: PUBLIC_VALUES,
+            cycles: None,
+            domain: None,
+            inputs: None,
+        }
+    }
+
+    fn key(n: u16) -> SigningKey {
+        let mut bytes = [0; 32];
+        bytes[30..].copy_from_slice(&(n + 1).to_be_bytes());
+
+        SigningKey::from_slice(&bytes).unwrap()
+    }
+
+    fn sign(key: &SigningKey) -> (Signature, u8) {
+        let (signature, recovery_id) = key.sign_digest_recoverable(message().digest()).unwrap();
+
+        (signature, recovery_id.to_byte())
+    }
+
+    fn signer(key: &SigningKey) -> [u8; 20] {
+        let (signature, recovery_id) = sign(key);
+
+        message().recover_signer(&signature, recovery_id).unwrap()
+    }
+
+    /// A proof signed by the first `n` keys.
+    fn proof(n: u16) -> ThresholdProof {
+        ThresholdProof::new(&message(), (0..n).map(|i| sign(&key(i)))).unwrap()
+    }
+
+    fn verifier(signers: u16, threshold: usize) -> ThresholdVerifier {
+        ThresholdVerifier::new((0..signers).map(|i| signer(&key(i))), threshold).unwrap()
+    }
+
+    #[test]
+    fn rejects_invalid_thresholds() {
+        let signers = || (0..3).map(|i| signer(&key(i)));
+
+        assert!(matches!(
+            ThresholdVerifier::new(signers(), 0),
+            Err(ThresholdError::InvalidThreshold {
+                threshold: 0,
+                signers: 3
+            })
+        ));
+        assert!(matches!(
+            ThresholdVerifier::new(signers(), 4),
+            Err(ThresholdError::InvalidThreshold {
+                threshold: 4,
+                signers: 3
+            })
+        ));
+
+        // Duplicate signers only count once.
+        assert!(matches!(
+            ThresholdVerifier::new(signers().chain(signers()), 4),
+            Err(ThresholdError::InvalidThreshold {
+                threshold: 4,
+                signers: 3
+            })
+        ));
+
+        assert!(ThresholdVerifier::new(signers(), 3).is_ok());
+    }
+
+    #[test]
+    fn sorts_and_dedups_signers() {
+        let proof = ThresholdProof::new(
+            &message(),
+            [sign(&key(2)), sign(&key(0)), sign(&key(1)), sign(&key(0))],
+        )
+        .unwrap();
+
+        let signers = proof
+            .signatures()
+            .iter()
+            .map(|(signature, recovery_id)| {
+                message().recover_signer(signature, *recovery_id).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut expected = (0..3).map(|i| signer(&key(i))).collect::<Vec<_>>();
+        expected.sort();
+
+        assert_eq!(signers, expected);
+    }
+
+    #[test]
+    fn encoding() {
+        let proof = proof(2);
+        let bytes = proof.encode();
+
+        assert_eq!(bytes[..4], Keccak256::digest(THRESHOLD_VERIFIER_NAME)[..4]);
+        assert_eq!(bytes[4..9], [4, 1, 0, 0, 0]);
+        assert_eq!(bytes[9], 2);
+        assert_eq!(bytes.len(), 10 + 2 * SIGNATURE_LEN);
+
+        for (chunk, (signature, recovery_id)) in
+            bytes[10..].chunks(SIGNATURE_LEN).zip(proof.signatures())
+        {
+            assert_eq!(chunk[0], recovery_id + 27);
+            assert_eq!(chunk[1..], signature.to_bytes()[..]);
+        }
+
+        assert_eq!(ThresholdProof::decode(&bytes).unwrap(), proof);
+    }
+
+    #[test]
+    fn decode_rejects_malformed_proofs() {
+        let bytes = proof(2).encode();
+
+        let mut wrong_selector = bytes.clone();
+        wrong_selector[0] ^= 1;
+
+        let mut wrong_version_len = bytes.clone();
+        wrong_version_len[4] = 3;
+
+        let mut wrong_count = bytes.clone();
+        wrong_count[9] = 3;
+
+        let mut wrong_recovery_id = bytes.clone();
+        wrong_recovery_id[10] = 29;
+
+        for malformed in [
+            &bytes[..bytes.len() - 1],
+            &bytes[..9],
+            &wrong_selector,
+            &wrong_version_len,
+            &wrong_count,
+            &wrong_recovery_id,
+        ] {
+            assert!(matches!(
+                ThresholdProof::decode(malformed),
+                Err(ThresholdError::Malformed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_more_signatures_than_the_count_holds() {
+        let signatures = (0..=ThresholdProof::MAX_SIGNATURES as u16)
+            .map(|i| sign(&key(i)))
+            .collect::<Vec<_>>();
+
+        assert!(matches!(
+            ThresholdProof::new(&message(), signatures.iter().copied()),
+            Err(ThresholdError::TooManySignatures(256))
+        ));
+
+        let proof = ThresholdProof::new(&message(), signatures[1..].iter().copied()).unwrap();
+        let bytes = proof.encode();
+
+        assert_eq!(bytes[9], u8::MAX);
+        assert_eq!(ThresholdProof::decode(&bytes).unwrap(), proof);
+    }
+
+    #[test]
+    fn verifies_k_of_n() {
+        let verifier = verifier(3, 2);
+
+        let signers = verifier
+            .verify_proof(&VKEY, PUBLIC_VALUES, &proof(2).encode())
+            .unwrap();
+        assert_eq!(signers.len(), 2);
+
+        assert_eq!(
+            verifier
+                .verify_proof(&VKEY, PUBLIC_VALUES, &proof(3).encode())
+                .unwrap()
+                .len(),
+            3
+        );
+
+        // Another result.
+        assert!(verifier
+            .verify_proof(&VKEY, b"other", &proof(2).encode())
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_proofs_below_the_threshold() {
+        assert!(matches!(
+            verifier(3, 2).verify(&message(), &proof(1)),
+            Err(ThresholdError::BelowThreshold {
+                threshold: 2,
+                found: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_duplicate_signers() {
+        let signature = sign(&key(0));
+        let proof = ThresholdProof {
+            tee_version: 1,
+            signatures: vec![signature, signature],
+        };
+
+        assert!(matches!(
+            verifier(3, 2).verify(&message(), &proof),
+            Err(ThresholdError::UnsortedSigners)
+        ));
+    }
+
+    #[test]
+    fn rejects_unsorted_signers() {
+        let mut signatures = proof(2).signatures().to_vec();
+        signatures.reverse();
+
+        let proof = ThresholdProof {
+            tee_version: 1,
+            signatures,
+        };
+
+        assert!(matches!(
+            verifier(3, 2).verify(&message(), &proof),
+            Err(ThresholdError::UnsortedSigners)
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_signers() {
+        assert!(matches!(
+            verifier(2, 2).verify(&message(), &proof(3)),
+            Err(ThresholdError::UnknownSigner(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let message = SignedMessage {
+            tee_version: 2,
+            ..message()
+        };
+
+        assert!(matches!(
+            verifier(3, 2).verify(&message, &proof(2)),
+            Err(ThresholdError::VersionMismatch)
+        ));
+    }
+}
@@ -0,0 +1,258 @@
+use std::{fmt, io, net::SocketAddr, os::unix::fs::FileTypeExt, path::PathBuf, str::FromStr};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio_vsock::{VsockAddr, VsockListener, VsockStream as VsockStreamRaw};
+
+/// A bi-directional byte stream that frames can be sent over.
+///
+/// This is implemented for any tokio stream, so the framing in [`crate::VsockStream`] is shared
+/// across all the transports listed in [`TransportAddr`].
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T> Transport for T where T: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+/// The address of the enclave, for any of the supported transports.
+///
+/// Parsed from (and displayed as) a URI of the form:
+/// - `vsock://<cid>:<port>`
+/// - `unix://<path>`
+/// - `tcp://<ip>:<port>`
+///
+/// Only vsock is available inside a Nitro Enclave, the others allow running the host and enclave
+/// against each other on an ordinary Linux machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportAddr {
+    Vsock { cid: u32, port: u32 },
+    Unix(PathBuf),
+    Tcp(SocketAddr),
+}
+
+impl TransportAddr {
+    /// The default address of the enclave, as seen from the host.
+    pub const fn vsock(cid: u32) -> Self {
+        Self::Vsock {
+            cid,
+            port: crate::ENCLAVE_PORT as u32,
+        }
+    }
+
+    /// Returns true if this address is a vsock address, i.e. the enclave is running in Nitro.
+    pub fn is_vsock(&self) -> bool {
+        matches!(self, Self::Vsock { .. })
+    }
+
+    /// Connect to the listener at this address.
+    pub async fn connect(&self) -> io::Result<Box<dyn Transport>> {
+        match self {
+            Self::Vsock { cid, port } => {
+                let stream = VsockStreamRaw::connect(VsockAddr::new(*cid, *port)).await?;
+
+                Ok(Box::new(stream))
+            }
+            Self::Unix(path) => Ok(Box::new(UnixStream::connect(path).await?)),
+            Self::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).await?;
+                stream.set_nodelay(true)?;
+
+                Ok(Box::new(stream))
+            }
+        }
+    }
+
+    /// Bind a listener to this address.
+    ///
+    /// For Unix sockets, a stale socket file left over from a previous run is removed first.
+    pub async fn bind(&self) -> io::Result<TransportListener> {
+        match self {
+            Self::Vsock { cid, port } => Ok(TransportListener::Vsock(VsockListener::bind(
+                VsockAddr::new(*cid, *port),
+            )?)),
+            Self::Unix(path) => {
+                if let Ok(metadata) = std::fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        std::fs::remove_file(path)?;
+                    }
+                }
+
+                Ok(TransportListener::Unix(UnixListener::bind(path)?))
+            }
+            Self::Tcp(addr) => Ok(TransportListener::Tcp(TcpListener::bind(addr).await?)),
+        }
+    }
+}
+
+/// A listener for incoming connections on any of the supported transports.
+pub enum TransportListener {
+    Vsock(VsockListener),
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+impl TransportListener {
+    /// Accept a new incoming connection.
+    pub async fn accept(&self) -> io::Result<Box<dyn Transport>> {
+        match self {
+            Self::Vsock(listener) => Ok(Box::new(listener.accept().await?.0)),
+            Self::Unix(listener) => Ok(Box::new(listener.accept().await?.0)),
+            Self::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+
+                Ok(Box::new(stream))
+            }
+        }
+    }
+}
+
+impl fmt::Display for TransportAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Vsock { cid, port } => write!(f, "vsock://{}:{}", cid, port),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+            Self::Tcp(addr) => write!(f, "tcp://{}", addr),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransportAddrParseError {
+    #[error("Missing scheme, expected one of vsock://, unix:// or tcp://, found {0}")]
+    MissingScheme(String),
+
+    #[error("Unknown transport scheme: {0}")]
+    UnknownScheme(String),
+
+    #[error("Invalid vsock address, expected vsock://<cid>:<port>, found {0}")]
+    InvalidVsockAddr(String),
+
+    #[error("Invalid tcp address: {0}")]
+    InvalidTcpAddr(#[from] std::net::AddrParseError),
+
+    #[error("Empty unix socket path")]
+    EmptyUnixPath,
+}
+
+impl FromStr for TransportAddr {
+    type Err = TransportAddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or_else(|| TransportAddrParseError::MissingScheme(s.to_string()))?;
+
+        match scheme {
+            "vsock" => {
+                let (cid, port) = rest
+                    .split_once(':')
+                    .ok_or_else(|| TransportAddrParseError::InvalidVsockAddr(s.to_string()))?;
+
+                let cid = cid
+                    .parse()
+                    .map_err(|_| TransportAddrParseError::InvalidVsockAddr(s.to_string()))?;
+                let port = port
+                    .parse()
+                    .map_err(|_| TransportAddrParseError::InvalidVsockAddr(s.to_string()))?;
+
+                Ok(Self::Vsock { cid, port })
+            }
+            "unix" => {
+                if rest.is_empty() {
+                    return Err(TransportAddrParseError::EmptyUnixPath);
+                }
+
+                Ok(Self::Unix(PathBuf::from(rest)))
+            }
+            "tcp" => Ok(Self::Tcp(rest.parse()?)),
+            other => Err(TransportAddrParseError::UnknownScheme(other.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_transport() {
+        assert_eq!(
+            "vsock://16:5005".parse::<TransportAddr>().unwrap(),
+            TransportAddr::Vsock {
+                cid: 16,
+                port: 5005
+            }
+        );
+        assert_eq!(
+            "unix:///tmp/enclave.sock".parse::<TransportAddr>().unwrap(),
+            TransportAddr::Unix(PathBuf::from("/tmp/enclave.sock"))
+        );
+        assert_eq!(
+            "tcp://127.0.0.1:5005".parse::<TransportAddr>().unwrap(),
+            TransportAddr::Tcp("127.0.0.1:5005".parse().unwrap())
+        );
+        assert_eq!(
+            "tcp://[::1]:5005".parse::<TransportAddr>().unwrap(),
+            TransportAddr::Tcp("[::1]:5005".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for addr in [
+            TransportAddr::vsock(16),
+            TransportAddr::Unix(PathBuf::from("/tmp/enclave.sock")),
+            TransportAddr::Unix(PathBuf::from("relative/enclave.sock")),
+            TransportAddr::Tcp("127.0.0.1:5005".parse().unwrap()),
+            TransportAddr::Tcp("[::1]:5005".parse().unwrap()),
+        ] {
+            assert_eq!(addr.to_string().parse::<TransportAddr>().unwrap(), addr);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_addresses() {
+        let parse = |s: &str| s.parse::<TransportAddr>().unwrap_err();
+
+        assert!(matches!(
+            parse("16:5005"),
+            TransportAddrParseError::MissingScheme(_)
+        ));
+        assert!(matches!(
+            parse("http://127.0.0.1:5005"),
+            TransportAddrParseError::UnknownScheme(scheme) if scheme == "http"
+        ));
+
+        // A missing port.
+        assert!(matches!(
+            parse("vsock://16"),
+            TransportAddrParseError::InvalidVsockAddr(_)
+        ));
+        assert!(matches!(
+            parse("vsock://16:"),
+            TransportAddrParseError::InvalidVsockAddr(_)
+        ));
+        assert!(matches!(
+            parse("tcp://127.0.0.1"),
+            TransportAddrParseError::InvalidTcpAddr(_)
+        ));
+
+        // A non-numeric CID, or one out of range.
+        assert!(matches!(
+            parse("vsock://host:5005"),
+            TransportAddrParseError::InvalidVsockAddr(_)
+        ));
+        assert!(matches!(
+            parse("vsock://4294967296:5005"),
+            TransportAddrParseError::InvalidVsockAddr(_)
+        ));
+
+        // Host names are not resolved.
+        assert!(matches!(
+            parse("tcp://localhost:5005"),
+            TransportAddrParseError::InvalidTcpAddr(_)
+        ));
+        assert!(matches!(
+            parse("unix://"),
+            TransportAddrParseError::EmptyUnixPath
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+
+/// The ID of an upload, assigned by the enclave.
+pub type UploadId = u64;
+
+/// The size of the chunks an upload is split into.
+///
+/// Each chunk is sent as its own payload frame, so this must be well below the payload frame limit.
+pub const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// What is being uploaded to the enclave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UploadKind {
+    /// The raw ELF bytes of a program.
+    Program,
+    /// A bincode encoded [`sp1_sdk::SP1Stdin`].
+    Stdin,
+}
+
+/// Incrementally hashes the bytes of an upload.
+///
+/// Both the host and enclave hash the upload as it is streamed,
+/// and the enclave rejects the upload on commit if the digests differ.
+#[derive(Debug, Clone, Default)]
+pub struct UploadHasher(Keccak256);
+
+impl UploadHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finalize(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+/// The hash of a program, used to identify it in the enclave's program cache.
+///
+/// This is the same as the digest of the program when uploaded.
+pub fn program_hash(program: &[u8]) -> [u8; 32] {
+    let mut hasher = UploadHasher::new();
+    hasher.update(program);
+
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_hash_is_keccak256() {
+        assert_eq!(
+            program_hash(b""),
+            hex_literal("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470")
+        );
+        assert_eq!(
+            program_hash(b"elf"),
+            <[u8; 32]>::from(Keccak256::digest(b"elf"))
+        );
+    }
+
+    #[test]
+    fn chunked_digest_matches_program_hash() {
+        let program = (0..=255u8).cycle().take(3 * 1000 + 17).collect::<Vec<_>>();
+
+        let mut hasher = UploadHasher::new();
+        for chunk in program.chunks(1000) {
+            hasher.update(chunk);
+        }
+
+        assert_eq!(hasher.finalize(), program_hash(&program));
+    }
+
+    fn hex_literal(hex: &str) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).unwrap();
+        }
+
+        bytes
+    }
+}
@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+
+/// The current revision of [`AttestationUserData`].
+pub const USER_DATA_VERSION: u32 = 2;
+
+/// The revision of the message the enclave signs.
+///
+/// 1. `keccak(keccak(tee_version) || vkey || keccak(public_values))`, with the cycle count
+///    appended as a big endian `u64` when the request sets `sign_cycles`.
+/// 2. Adds opt-in domain separation, binding the chain ID, verifier, request ID and expiry,
+///    see [`crate::SignedMessage`]. Requests without a domain are still signed as in format 1.
+/// 3. Adds the opt-in [`crate::InputCommitment`], appended when the request sets `sign_inputs`.
+pub const SIGNATURE_FORMAT: u32 = 3;
+
+/// The `user_data` of the enclave's attestation documents, describing how the enclave was built
+/// and the policy it signs under.
+///
+/// Encoded as a CBOR map, so fields can be added without breaking older verifiers.
+/// Enclaves before [`USER_DATA_VERSION`] 1 only attested to the TEE version, as 4 little endian bytes,
+/// those decode with every other field set to `None`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestationUserData {
+    /// The revision of this structure, 0 for the legacy encoding.
+    pub version: u32,
+    /// The `SP1_TEE_VERSION` the enclave signs with.
+    pub tee_version: u32,
+    /// The git revision the enclave was built from, or `unknown`.
+    #[serde(default)]
+    pub git_revision: Option<String>,
+    /// The hex encoded keccak256 hash of the `Cargo.lock` the enclave was built with.
+    #[serde(default)]
+    pub cargo_lock_hash: Option<String>,
+    /// The maximum cycle limit the enclave accepts for an execution.
+    #[serde(default)]
+    pub max_allowed_cycles: Option<u64>,
+    /// The cycle limit of unconstrained blocks, `None` if the executor's default is used.
+    #[serde(default)]
+    pub unconstrained_cycle_limit: Option<u64>,
+    /// The [`SIGNATURE_FORMAT`] of the enclave's signatures.
+    #[serde(default)]
+    pub signature_format: Option<u32>,
+    /// How long (in seconds) an execution may run before the enclave stops it, since version 2.
+    #[serde(default)]
+    pub max_execution_secs: Option<u64>,
+}
+
+impl AttestationUserData {
+    /// The length of the legacy encoding, the TEE version as little endian bytes.
+    const LEGACY_LEN: usize = std::mem::size_of::<u32>();
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(self, &mut bytes)
+            .expect("Failed to serialize user data, this is a bug.");
+
+        bytes
+    }
+
+    /// Decodes the user data of an attestation document, in either encoding.
+    ///
+    /// # Errors
+    /// - [`UserDataError::Cbor`] - The user data is neither the legacy encoding nor valid CBOR.
+    pub fn decode(bytes: &[u8]) -> Result<Self, UserDataError> {
+        if let Ok(tee_version) = <[u8; Self::LEGACY_LEN]>::try_from(bytes) {
+            return Ok(Self {
+                version: 0,
+                tee_version: u32::from_le_bytes(tee_version),
+                git_revision: None,
+                cargo_lock_hash: None,
+                max_allowed_cycles: None,
+                unconstrained_cycle_limit: None,
+                signature_format: None,
+                max_execution_secs: None,
+            });
+        }
+
+        Ok(ciborium::from_reader(bytes)?)
+    }
+}
+
+impl std::fmt::Display for AttestationUserData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn or_unknown<T: std::fmt::Display>(value: &Option<T>) -> String {
+            value
+                .as_ref()
+                .map_or_else(|| "unknown".to_string(), ToString::to_string)
+        }
+
+        writeln!(f, "user data version: {}", self.version)?;
+        writeln!(f, "tee version: {}", self.tee_version)?;
+        writeln!(f, "git revision: {}", or_unknown(&self.git_revision))?;
+        writeln!(f, "Cargo.lock hash: {}", or_unknown(&self.cargo_lock_hash))?;
+        writeln!(
+            f,
+            "max allowed cycles: {}",
+            or_unknown(&self.max_allowed_cycles)
+        )?;
+        writeln!(
+            f,
+            "unconstrained cycle limit: {}",
+            or_unknown(&self.unconstrained_cycle_limit)
+        )?;
+        writeln!(
+            f,
+            "signature format: {}",
+            or_unknown(&self.signature_format)
+        )?;
+        write!(
+            f,
+            "max execution secs: {}",
+            or_unknown(&self.max_execution_secs)
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UserDataError {
+    #[error("Failed to decode user data: {0}")]
+    Cbor(#[from] ciborium::de::Error<std::io::Error>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ciborium::Value;
+
+    /// The encoding of [`user_data`], as the enclave produced it before the CBOR library changed.
+    const ENCODED: &str =
+        "a86776657273696f6e026b7465655f76657273696f6e036c6769745f7265766973696f6e63616263\
+        6f636172676f5f6c6f636b5f686173686430306666726d61785f616c6c6f7765645f6379636c65731a000f4240\
+        7819756e636f6e73747261696e65645f6379636c655f6c696d6974f6707369676e61747572655f666f726d6174\
+        03726d61785f657865637574696f6e5f73656373183c";
+
+    fn user_data() -> AttestationUserData {
+        AttestationUserData {
+            version: 2,
+            tee_version: 3,
+            git_revision: Some("abc".to_string()),
+            cargo_lock_hash: Some("00ff".to_string()),
+            max_allowed_cycles: Some(1_000_000),
+            unconstrained_cycle_limit: None,
+            signature_format: Some(3),
+            max_execution_secs: Some(60),
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn cbor(value: Value) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&value, &mut bytes).unwrap();
+
+        bytes
+    }
+
+    /// A CBOR map of the fields, as text keys.
+    fn map(fields: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            fields
+                .into_iter()
+                .map(|(key, value)| (Value::Text(key.to_string()), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn decodes_the_legacy_encoding() {
+        let decoded = AttestationUserData::decode(&7u32.to_le_bytes()).unwrap();
+
+        assert_eq!(decoded.version, 0);
+        assert_eq!(decoded.tee_version, 7);
+        assert_eq!(decoded.git_revision, None);
+        assert_eq!(decoded.cargo_lock_hash, None);
+        assert_eq!(decoded.max_allowed_cycles, None);
+        assert_eq!(decoded.unconstrained_cycle_limit, None);
+        assert_eq!(decoded.signature_format, None);
+        assert_eq!(decoded.max_execution_secs, None);
+    }
+
+    #[test]
+    fn round_trips() {
+        let encoded = user_data().encode();
+
+        assert_eq!(hex(&encoded), ENCODED);
+        assert_eq!(AttestationUserData::decode(&encoded).unwrap(), user_data());
+    }
+
+    #[test]
+    fn decodes_version_1() {
+        // Version 1 has no `max_execution_secs`.
+        let encoded = cbor(map(vec![
+            ("version", Value::from(1)),
+            ("tee_version", Value::from(3)),
+            ("git_revision", Value::Text("abc".to_string())),
+            ("cargo_lock_hash", Value::Null),
+            ("max_allowed_cycles", Value::from(1_000_000)),
+            ("unconstrained_cycle_limit", Value::from(2_000)),
+            ("signature_format", Value::from(1)),
+        ]));
+
+        let decoded = AttestationUserData::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.version, 1);
+        assert_eq!(decoded.tee_version, 3);
+        assert_eq!(decoded.git_revision.as_deref(), Some("abc"));
+        assert_eq!(decoded.cargo_lock_hash, None);
+        assert_eq!(decoded.max_allowed_cycles, Some(1_000_000));
+        assert_eq!(decoded.unconstrained_cycle_limit, Some(2_000));
+        assert_eq!(decoded.signature_format, Some(1));
+        assert_eq!(decoded.max_execution_secs, None);
+    }
+
+    #[test]
+    fn ignores_unknown_fields() {
+        let encoded = cbor(map(vec![
+            ("version", Value::from(3)),
+            ("tee_version", Value::from(3)),
+            ("max_execution_secs", Value::from(60)),
+            ("added_later", Value::Array(vec![Value::from(1)])),
+        ]));
+
+        let decoded = AttestationUserData::decode(&encoded).unwrap();
+
+        assert_eq!(decoded.version, 3);
+        assert_eq!(decoded.max_execution_secs, Some(60));
+        assert_eq!(decoded.git_revision, None);
+    }
+
+    #[test]
+    fn rejects_invalid_user_data() {
+        // Neither the legacy length, nor a map with the required fields.
+        assert!(AttestationUserData::decode(&[]).is_err());
+        assert!(AttestationUserData::decode(&[0; 5]).is_err());
+        assert!(
+            AttestationUserData::decode(&cbor(map(vec![("version", Value::from(2))]))).is_err()
+        );
+        assert!(AttestationUserData::decode(&user_data().encode()[..10]).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::signature::recover_address;
+use crate::{SignatureDomain, SignatureError};
+
+use k256::ecdsa::Signature;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Keccak256};
+
+/// Prefixes the message of a proof verdict, so it can never be mistaken for the signature of a result.
+pub const PROOF_VERDICT_TAG: &[u8] = b"SP1TeeProofVerdict";
+
+/// The kind of SP1 proof the enclave verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum ProofKind {
+    Compressed = 1,
+    Plonk = 2,
+    Groth16 = 3,
+}
+
+/// Attests that a proof of a program with its public values is valid, signed by the enclave like a result.
+///
+/// Only valid proofs are signed, an invalid proof is rejected with [`crate::EnclaveError::InvalidProof`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProofVerdict {
+    /// The `SP1_TEE_VERSION` of the enclave.
+    pub tee_version: u32,
+    pub kind: ProofKind,
+    pub vkey: [u8; 32],
+    pub public_values: Vec<u8>,
+    /// The domain the verdict is bound to, if the request set one.
+    pub domain: Option<SignatureDomain>,
+}
+
+impl ProofVerdict {
+    /// Encodes the message, before hashing:
+    ///
+    /// ```text
+    /// keccak(PROOF_VERDICT_TAG) || keccak(tee_version) || vkey || keccak(public_values)
+    ///     || kind [|| chain_id || verifier || request_id || expires_at]
+    /// ```
+    ///
+    /// The kind is a single byte, the TEE version is little endian and the other integers are big endian `u64`s,
+    /// as in [`crate::SignedMessage`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut message = Vec::new();
+
+        message.extend_from_slice(&Keccak256::digest(PROOF_VERDICT_TAG));
+        message.extend_from_slice(&Keccak256::digest(self.tee_version.to_le_bytes()));
+        message.extend_from_slice(&self.vkey);
+        message.extend_from_slice(&Keccak256::digest(&self.public_values));
+        message.push(self.kind as u8);
+
+        if let Some(domain) = &self.domain {
+            domain.encode_into(&mut message);
+        }
+
+        message
+    }
+
+    /// The hasher over the encoded message, ready to be signed or recovered from.
+    pub fn digest(&self) -> Keccak256 {
+        Keccak256::new_with_prefix(self.encode())
+    }
+}
+
+/// A [`ProofVerdict`] and the enclave's signature over it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedProofVerdict {
+    pub verdict: ProofVerdict,
+    pub signature: Signature,
+    /// Either `0` or `1`, as returned by the enclave.
+    pub recovery_id: u8,
+}
+
+impl SignedProofVerdict {
+    /// Recovers the Ethereum address that signed the verdict, which must be checked against the registered signers.
+    ///
+    /// # Errors
+    /// - [`SignatureError::InvalidSignature`] - No public key could be recovered from the signature.
+    pub fn recover_signer(&self) -> Result<[u8; 20], SignatureError> {
+        recover_address(self.verdict.digest(), &self.signature, self.recovery_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signature::tests::{address, domain, signing_key};
+    use k256::ecdsa::SigningKey;
+
+    fn verdict(kind: ProofKind, domain: Option<SignatureDomain>) -> ProofVerdict {
+        ProofVerdict {
+            tee_version: 1,
+            kind,
+            vkey: [0xab; 32],
+            public_values: vec![1, 2, 3],
+            domain,
+        }
+    }
+
+    fn sign(key: &SigningKey, verdict: ProofVerdict) -> SignedProofVerdict {
+        let (signature, recovery_id) = key.sign_digest_recoverable(verdict.digest()).unwrap();
+
+        SignedProofVerdict {
+            verdict,
+            signature,
+            recovery_id: recovery_id.to_byte(),
+        }
+    }
+
+    #[test]
+    fn verdict_encoding() {
+        let expected = [
+            &Keccak256::digest(PROOF_VERDICT_TAG)[..],
+            &Keccak256::digest(1u32.to_le_bytes()),
+            &[0xab; 32],
+            &Keccak256::digest([1, 2, 3]),
+            &[3],
+        ]
+        .concat();
+
+        assert_eq!(verdict(ProofKind::Groth16, None).encode(), expected);
+    }
+
+    #[test]
+    fn kinds_are_signed() {
+        let compressed = verdict(ProofKind::Compressed, None).encode();
+        let plonk = verdict(ProofKind::Plonk, None).encode();
+
+        assert_eq!(compressed.last(), Some(&1));
+        assert_eq!(plonk.last(), Some(&2));
+        assert_eq!(compressed[..compressed.len() - 1], plonk[..plonk.len() - 1]);
+    }
+
+    #[test]
+    fn domain_is_appended() {
+        let domain = domain(0x22);
+        let mut expected = verdict(ProofKind::Plonk, None).encode();
+        domain.encode_into(&mut expected);
+
+        assert_eq!(verdict(ProofKind::Plonk, Some(domain)).encode(), expected);
+    }
+
+    #[test]
+    fn verdicts_are_not_result_signatures_or_receipts() {
+        let message = crate::SignedMessage {
+            tee_version: 1,
+            vkey: &[0xab; 32],
+            public_values: &[1, 2, 3],
+            cycles: None,
+            domain: None,
+            inputs: None,
+        };
+        let encoded = verdict(ProofKind::Compressed, None).encode();
+
+        assert_ne!(encoded[..32], message.encode()[..32]);
+        assert_ne!(
+            encoded[..32],
+            Keccak256::digest(crate::FAILURE_RECEIPT_TAG)[..]
+        );
+    }
+
+    #[test]
+    fn recovers_the_signer() {
+        let key = signing_key();
+        let signed = sign(&key, verdict(ProofKind::Compressed, None));
+        let address = address(&key);
+
+        assert_eq!(signed.recover_signer().unwrap(), address);
+
+        // A verdict for another kind of proof recovers another signer, or none at all.
+        let mut tampered = signed;
+        tampered.verdict.kind = ProofKind::Plonk;
+        assert!(tampered
+            .recover_signer()
+            .map_or(true, |signer| signer != address));
+    }
+}
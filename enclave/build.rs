@@ -1,4 +1,55 @@
+use sha3::Digest;
+
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // Embed the git revision in the binary, so the enclave can report it to the host.
+    //
+    // HEAD only changes when switching branches, the ref it points to changes on every commit.
+    println!("cargo:rerun-if-env-changed=SP1_TEE_GIT_REVISION");
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    if let Some(head_ref) = std::fs::read_to_string("../.git/HEAD")
+        .ok()
+        .and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+    {
+        println!("cargo:rerun-if-changed=../.git/{}", head_ref);
+    }
+
+    let git_revision = std::env::var("SP1_TEE_GIT_REVISION")
+        .ok()
+        .or_else(|| {
+            std::process::Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|revision| revision.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=SP1_TEE_GIT_REVISION={}", git_revision);
+
+    // The revision is committed to in every attestation, so a release enclave must know it.
+    if git_revision == "unknown" {
+        let release = std::env::var("PROFILE").is_ok_and(|profile| profile == "release");
+        let debug_mode = std::env::var_os("CARGO_FEATURE_DEBUG_MODE").is_some();
+
+        if release && !debug_mode {
+            panic!(
+                "The git revision is unknown, set SP1_TEE_GIT_REVISION when building outside of a git checkout"
+            );
+        }
+
+        println!("cargo:warning=The git revision is unknown, set SP1_TEE_GIT_REVISION to embed it");
+    }
+
+    // Embed the hash of the lockfile, so the attestation commits to the exact dependencies.
+    println!("cargo:rerun-if-changed=../Cargo.lock");
+    let cargo_lock_hash = std::fs::read("../Cargo.lock")
+        .map(|lock| hex(&sha3::Keccak256::digest(lock)))
+        .unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=SP1_TEE_CARGO_LOCK_HASH={}", cargo_lock_hash);
+
     // Nitro Enclaves are only supported on linux, so dont bother building if we are on macos.
     #[cfg(not(target_os = "macos"))]
     {
@@ -26,3 +77,7 @@ fn main() {
         println!("cargo:rustc-link-lib=static=crypto");
     }
 }
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
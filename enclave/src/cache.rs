@@ -0,0 +1,157 @@
+use lru::LruCache;
+use parking_lot::Mutex;
+use sp1_sdk::SP1VerifyingKey;
+use std::sync::Arc;
+
+/// A program that has been set up, ready to be executed.
+pub struct CachedProgram {
+    /// The ELF of the program.
+    pub elf: Vec<u8>,
+    /// The verifying key of the program, computed by setup.
+    pub vk: SP1VerifyingKey,
+}
+
+impl CachedProgram {
+    /// The approximate memory used by this program.
+    fn size(&self) -> usize {
+        self.elf.len() + std::mem::size_of::<Self>()
+    }
+}
+
+/// A least recently used cache of programs, keyed by [`sp1_tee_common::program_hash`].
+///
+/// The cache is bounded by the total size of the programs it holds, rather than their count,
+/// as the enclave has a fixed amount of memory.
+pub struct ProgramCache {
+    /// The maximum number of bytes the cached programs may use.
+    max_bytes: usize,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    programs: LruCache<[u8; 32], Arc<CachedProgram>>,
+    /// The number of bytes used by the cached programs.
+    used_bytes: usize,
+}
+
+impl ProgramCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            inner: Mutex::new(Inner {
+                programs: LruCache::unbounded(),
+                used_bytes: 0,
+            }),
+        }
+    }
+
+    /// Get a program, marking it as recently used.
+    pub fn get(&self, program_hash: &[u8; 32]) -> Option<Arc<CachedProgram>> {
+        self.inner.lock().programs.get(program_hash).cloned()
+    }
+
+    /// Insert a program, evicting the least recently used programs to make room for it.
+    ///
+    /// Programs larger than the whole cache are not cached.
+    pub fn insert(&self, program_hash: [u8; 32], program: Arc<CachedProgram>) {
+        let size = program.size();
+        if size > self.max_bytes {
+            return;
+        }
+
+        let mut inner = self.inner.lock();
+
+        if let Some(old) = inner.programs.pop(&program_hash) {
+            inner.used_bytes -= old.size();
+        }
+
+        while inner.used_bytes + size > self.max_bytes {
+            let Some((_, evicted)) = inner.programs.pop_lru() else {
+                break;
+            };
+
+            inner.used_bytes -= evicted.size();
+        }
+
+        inner.programs.put(program_hash, program);
+        inner.used_bytes += size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp1_sdk::{CpuProver, Prover};
+
+    fn vk() -> SP1VerifyingKey {
+        CpuProver::new().setup(crate::selftest::FIXTURE_ELF).1
+    }
+
+    fn program(vk: &SP1VerifyingKey, len: usize) -> Arc<CachedProgram> {
+        Arc::new(CachedProgram {
+            elf: vec![0; len],
+            vk: vk.clone(),
+        })
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let vk = vk();
+        let size = program(&vk, 100).size();
+        let cache = ProgramCache::new(2 * size);
+
+        cache.insert([1; 32], program(&vk, 100));
+        cache.insert([2; 32], program(&vk, 100));
+
+        // Using the first program makes the second the least recently used.
+        assert!(cache.get(&[1; 32]).is_some());
+        cache.insert([3; 32], program(&vk, 100));
+
+        assert!(cache.get(&[1; 32]).is_some());
+        assert!(cache.get(&[2; 32]).is_none());
+        assert!(cache.get(&[3; 32]).is_some());
+    }
+
+    #[test]
+    fn evicts_until_the_program_fits() {
+        let vk = vk();
+        let small = program(&vk, 10).size();
+        let large = program(&vk, 100).size();
+        let cache = ProgramCache::new(large + small);
+
+        cache.insert([1; 32], program(&vk, 10));
+        cache.insert([2; 32], program(&vk, 10));
+        cache.insert([3; 32], program(&vk, 100));
+
+        assert!(cache.get(&[1; 32]).is_none());
+        assert!(cache.get(&[2; 32]).is_some());
+        assert!(cache.get(&[3; 32]).is_some());
+    }
+
+    #[test]
+    fn skips_programs_larger_than_the_cache() {
+        let vk = vk();
+        let cache = ProgramCache::new(program(&vk, 100).size());
+
+        cache.insert([1; 32], program(&vk, 100));
+        cache.insert([2; 32], program(&vk, 101));
+
+        assert!(cache.get(&[1; 32]).is_some());
+        assert!(cache.get(&[2; 32]).is_none());
+    }
+
+    #[test]
+    fn replacing_a_program_frees_its_size() {
+        let vk = vk();
+        let size = program(&vk, 100).size();
+        let cache = ProgramCache::new(2 * size);
+
+        cache.insert([1; 32], program(&vk, 100));
+        cache.insert([1; 32], program(&vk, 100));
+        cache.insert([2; 32], program(&vk, 100));
+
+        assert!(cache.get(&[1; 32]).is_some());
+        assert!(cache.get(&[2; 32]).is_some());
+        assert_eq!(cache.inner.lock().used_bytes, 2 * size);
+    }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use sp1_core_executor::{ExecutionError, Executor, ExecutorMode, Program, SP1Context};
+use sp1_sdk::{CpuProver, Prover, SP1PublicValues, SP1Stdin};
+use sp1_stark::SP1CoreOpts;
+use sp1_tee_common::EnclaveError;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Set by the host to stop an in-flight request.
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// The result of a successful execution.
+#[derive(Serialize, Deserialize)]
+pub struct Execution {
+    pub public_values: SP1PublicValues,
+    /// The number of cycles the program ran for.
+    pub cycles: u64,
+    /// The number of times each syscall was invoked, by name.
+    pub syscall_counts: BTreeMap<String, u64>,
+}
+
+/// An execution that failed, along with the cycles it ran for.
+#[derive(Serialize, Deserialize)]
+pub struct ExecutionFailure {
+    pub error: EnclaveError,
+    /// The number of cycles the program ran for before failing, zero if it never started.
+    pub cycles: u64,
+}
+
+impl From<EnclaveError> for ExecutionFailure {
+    fn from(error: EnclaveError) -> Self {
+        Self { error, cycles: 0 }
+    }
+}
+
+/// Executes a program, checking for cancellation and the `time_limit` between each batch of cycles.
+///
+/// This mirrors [`CpuProver::execute`], which runs to completion and cannot be interrupted.
+/// The `prover` verifies the deferred proofs in `stdin`, and is only needed if there are any.
+pub fn execute(
+    prover: Option<&CpuProver>,
+    program: &[u8],
+    stdin: &SP1Stdin,
+    cycle_limit: u64,
+    time_limit: Duration,
+    cancel: &CancelFlag,
+) -> Result<Execution, ExecutionFailure> {
+    let start = Instant::now();
+
+    let program = Program::from(program)
+        .map_err(|e| EnclaveError::InvalidRequest(format!("Failed to load program: {}", e)))?;
+
+    // Deferred proofs are verified by the prover, as they are in the SDK.
+    let mut context = SP1Context::builder();
+    context.max_cycles(cycle_limit);
+    if let Some(prover) = prover {
+        context.subproof_verifier(prover.inner());
+    }
+    let context = context.build();
+
+    let mut runtime = Executor::with_context(program, SP1CoreOpts::default(), context);
+    runtime.executor_mode = ExecutorMode::Simple;
+
+    runtime.write_vecs(&stdin.buffer);
+    for (proof, vkey) in stdin.proofs.iter() {
+        runtime.write_proof(proof.clone(), vkey.clone());
+    }
+
+    loop {
+        let error = if cancel.is_cancelled() {
+            EnclaveError::Cancelled
+        } else if start.elapsed() > time_limit {
+            EnclaveError::ExecutionTimeLimitExceeded(time_limit.as_secs())
+        } else {
+            match runtime.execute() {
+                Ok(true) => break,
+                Ok(false) => continue,
+                Err(ExecutionError::ExceededCycleLimit(limit)) => {
+                    EnclaveError::CycleLimitExceeded(limit)
+                }
+                Err(e) => EnclaveError::ExecutionFailed(e.to_string()),
+            }
+        };
+
+        return Err(ExecutionFailure {
+            error,
+            cycles: runtime.state.global_clk,
+        });
+    }
+
+    let syscall_counts = runtime
+        .report
+        .syscall_counts
+        .iter()
+        .filter(|(_, count)| **count > 0)
+        .map(|(syscall, count)| (format!("{:?}", syscall), *count))
+        .collect();
+
+    Ok(Execution {
+        public_values: SP1PublicValues::from(&runtime.state.public_values_stream),
+        cycles: runtime.state.global_clk,
+        syscall_counts,
+    })
+}
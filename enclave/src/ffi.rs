@@ -1,7 +1,10 @@
 mod bytebuf;
-use bytebuf::AWSByteBuf;
+pub use bytebuf::AWSByteBuf;
 
 mod string;
+pub use string::{AWSString, OwnedAWSString};
+
+pub mod kms;
 
 #[repr(C)]
 pub struct AWSAllocator {
@@ -22,4 +25,22 @@ extern "C" {
 // Common functions.
 extern "C" {
     pub fn aws_byte_buf_clean_up(buf: *mut AWSByteBuf);
+
+    pub fn aws_byte_buf_clean_up_secure(buf: *mut AWSByteBuf);
+
+    pub fn aws_string_new_from_array(
+        allocator: *mut AWSAllocator,
+        bytes: *const u8,
+        len: usize,
+    ) -> *mut AWSString;
+
+    pub fn aws_string_destroy_secure(string: *mut AWSString);
+}
+
+/// Initializes the Nitro Enclaves SDK, this is a no-op after the first call.
+pub fn init() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+
+    // SAFETY: A null allocator selects the default allocator, and `Once` ensures this only runs once.
+    INIT.call_once(|| unsafe { aws_nitro_enclaves_library_init(std::ptr::null_mut()) });
 }
@@ -1,4 +1,4 @@
-use super::{aws_byte_buf_clean_up, AWSAllocator};
+use super::{aws_byte_buf_clean_up_secure, AWSAllocator};
 
 use std::alloc::Layout;
 
@@ -15,6 +15,27 @@ pub struct AWSByteBuf {
     allocator: *mut AWSAllocator,
 }
 
+impl AWSByteBuf {
+    /// An empty buffer, to be initialized by a C function that writes its output into it.
+    pub fn empty() -> Self {
+        AWSByteBuf {
+            len: 0,
+            buffer: std::ptr::null_mut(),
+            capacity: 0,
+            allocator: std::ptr::null_mut(),
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        if self.buffer.is_null() {
+            return &[];
+        }
+
+        // SAFETY: The buffer is non-null, and `len` bytes of it are initialized.
+        unsafe { std::slice::from_raw_parts(self.buffer, self.len) }
+    }
+}
+
 impl From<Vec<u8>> for AWSByteBuf {
     fn from(value: Vec<u8>) -> Self {
         Self::from(value.as_slice())
@@ -28,6 +49,11 @@ impl From<&[u8]> for AWSByteBuf {
         // For simplicity, we only allocate exactly the amount of memory needed.
         let len = value.len();
 
+        // Allocating zero bytes is undefined behavior.
+        if len == 0 {
+            return Self::empty();
+        }
+
         let buffer = unsafe {
             // SAFTEY:
             // - Align comes from the `u8` type, and is therefore valid.
@@ -58,6 +84,11 @@ impl From<&[u8]> for AWSByteBuf {
 
 impl Drop for AWSByteBuf {
     fn drop(&mut self) {
+        if self.buffer.is_null() {
+            // Nothing was ever allocated.
+            return;
+        }
+
         if self.allocator.is_null() {
             // SAFETY:
             // - By the invariants of this type, if the allocator is null,
@@ -68,7 +99,11 @@ impl Drop for AWSByteBuf {
             // - The buffer is assumed to be properly aligned, since its created by Rust code.
             //
             // - `u8` has noop destructor, no need to call drop.
+            //
+            // The buffer may hold a signing key, so it is zeroed before being freed.
             unsafe {
+                std::ptr::write_bytes(self.buffer, 0, self.len);
+
                 let layout =
                     Layout::from_size_align_unchecked(self.len, std::mem::align_of::<u8>());
 
@@ -77,8 +112,29 @@ impl Drop for AWSByteBuf {
         } else {
             // SAFTEY: The buffer was allocated by the AWSAllocator as its nonnull.
             unsafe {
-                aws_byte_buf_clean_up(self);
+                aws_byte_buf_clean_up_secure(self);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_slices_are_not_allocated() {
+        let buf = AWSByteBuf::from(&[][..]);
+
+        assert!(buf.buffer.is_null());
+        assert_eq!(buf.as_slice(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn copies_the_slice() {
+        let buf = AWSByteBuf::from(vec![1, 2, 3]);
+
+        assert_eq!(buf.as_slice(), &[1, 2, 3]);
+        assert_eq!(buf.capacity, 3);
+    }
+}
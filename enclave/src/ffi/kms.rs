@@ -0,0 +1,86 @@
+use super::{AWSByteBuf, AWSString};
+
+use std::os::raw::{c_char, c_int};
+
+/// The maximum length of a socket address, including the null terminator.
+///
+/// On Linux this is the size of `sockaddr_un::sun_path`.
+pub const AWS_ADDRESS_MAX_LEN: usize = 108;
+
+/// The `AWS_SOCKET_VSOCK` variant of `aws_socket_domain`.
+pub const AWS_SOCKET_VSOCK: c_int = 3;
+
+/// The `AWS_OP_SUCCESS` return code.
+pub const AWS_OP_SUCCESS: c_int = 0;
+
+/// An address and port to connect to.
+///
+/// This type is a direct mapping of the `aws_socket_endpoint` type in the AWS C IO library.
+#[repr(C)]
+pub struct AWSSocketEndpoint {
+    pub address: [c_char; AWS_ADDRESS_MAX_LEN],
+    pub port: u32,
+}
+
+impl AWSSocketEndpoint {
+    /// An endpoint on the parent instance, i.e. the vsock-proxy forwarding to KMS.
+    pub fn parent(port: u32) -> Self {
+        // The parent instance always has CID 3.
+        let mut address = [0; AWS_ADDRESS_MAX_LEN];
+        address[0] = b'3' as c_char;
+
+        Self { address, port }
+    }
+}
+
+#[repr(C)]
+pub struct KmsClientConfiguration {
+    _unused: [u8; 0],
+}
+
+#[repr(C)]
+pub struct KmsClient {
+    _unused: [u8; 0],
+}
+
+// KMS client functions.
+//
+// <https://github.com/aws/aws-nitro-enclaves-sdk-c/blob/main/include/aws/nitro_enclaves/kms.h>
+extern "C" {
+    /// NOTE: The configuration borrows `region`, it must outlive any client created from it.
+    pub fn aws_nitro_enclaves_kms_client_config_default(
+        region: *mut AWSString,
+        endpoint: *mut AWSSocketEndpoint,
+        domain: c_int,
+        access_key_id: *mut AWSString,
+        secret_access_key: *mut AWSString,
+        session_token: *mut AWSString,
+    ) -> *mut KmsClientConfiguration;
+
+    pub fn aws_nitro_enclaves_kms_client_config_destroy(config: *mut KmsClientConfiguration);
+
+    pub fn aws_nitro_enclaves_kms_client_new(config: *mut KmsClientConfiguration)
+        -> *mut KmsClient;
+
+    pub fn aws_nitro_enclaves_kms_client_destroy(client: *mut KmsClient);
+
+    /// Encrypts `plaintext` under `key_id`, writing the ciphertext blob into `ciphertext`.
+    pub fn aws_kms_encrypt_blocking(
+        client: *mut KmsClient,
+        key_id: *const AWSString,
+        plaintext: *const AWSByteBuf,
+        ciphertext: *mut AWSByteBuf,
+    ) -> c_int;
+
+    /// Decrypts `ciphertext`, writing the plaintext into `plaintext`.
+    ///
+    /// The request includes an attestation document of this enclave, so KMS can enforce
+    /// the key policy on its measurements, and the plaintext is only ever visible to the enclave.
+    pub fn aws_kms_decrypt_blocking(
+        client: *mut KmsClient,
+        key_id: *const AWSString,
+        encryption_algorithm: *const AWSString,
+        ciphertext: *const AWSByteBuf,
+        plaintext: *mut AWSByteBuf,
+    ) -> c_int;
+}
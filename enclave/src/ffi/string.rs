@@ -1 +1,54 @@
+use super::{
+    aws_nitro_enclaves_get_allocator, aws_string_destroy_secure, aws_string_new_from_array,
+};
 
+/// An immutable string, allocated by the AWS allocator.
+///
+/// This type is an opaque mapping of the `aws_string` type in the AWS C Common library.
+///
+/// <https://github.com/awslabs/aws-c-common/blob/9fd58f977d5779f8a695dd963e75cf3abee8231e/include/aws/common/string.h>
+#[repr(C)]
+pub struct AWSString {
+    #[doc(hidden)]
+    _unused: [u8; 0],
+}
+
+/// An owned pointer to an [`AWSString`], destroyed on drop.
+pub struct OwnedAWSString(*mut AWSString);
+
+impl OwnedAWSString {
+    /// Copies `value` into a new [`AWSString`].
+    ///
+    /// NOTE: [`super::aws_nitro_enclaves_library_init`] must have been called first.
+    pub fn new(value: &str) -> Self {
+        // SAFETY:
+        // - The pointer and length come from the same slice.
+        // - The library is initialized, so the allocator is valid.
+        let ptr = unsafe {
+            aws_string_new_from_array(
+                aws_nitro_enclaves_get_allocator(),
+                value.as_ptr(),
+                value.len(),
+            )
+        };
+
+        assert!(!ptr.is_null(), "Failed to allocate aws_string");
+
+        Self(ptr)
+    }
+
+    pub fn as_ptr(&self) -> *mut AWSString {
+        self.0
+    }
+}
+
+impl Drop for OwnedAWSString {
+    fn drop(&mut self) {
+        // SAFTEY: The string was allocated by `aws_string_new_from_array`, and is only freed here.
+        //
+        // These strings may hold credentials, so they are zeroed before being freed.
+        unsafe {
+            aws_string_destroy_secure(self.0);
+        }
+    }
+}
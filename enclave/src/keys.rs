@@ -0,0 +1,267 @@
+use k256::ecdsa::SigningKey;
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use sp1_tee_common::EnclaveError;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The signing keys of the enclave.
+///
+/// After a rotation, the old key keeps signing until the grace period is over,
+/// giving the host time to register the next key before it is used.
+pub struct SigningKeys {
+    /// The key executions are signed with.
+    active: SigningKey,
+    /// The key that replaces `active` once its grace period is over.
+    next: Option<NextKey>,
+}
+
+struct NextKey {
+    key: SigningKey,
+    activates_at: Instant,
+}
+
+/// The keys as they are sealed or migrated, see [`SigningKeys::to_sealed`].
+///
+/// `Instant`s do not survive a restart, so the activation time is stored as a unix timestamp.
+#[derive(Serialize, Deserialize)]
+struct SealedKeys {
+    active: Vec<u8>,
+    next: Option<(Vec<u8>, u64)>,
+}
+
+impl SigningKeys {
+    pub fn new(key: SigningKey) -> Self {
+        Self {
+            active: key,
+            next: None,
+        }
+    }
+
+    /// The key to sign with, activating the next key if its grace period is over.
+    pub fn active(&mut self) -> &SigningKey {
+        if self
+            .next
+            .as_ref()
+            .is_some_and(|next| next.activates_at <= Instant::now())
+        {
+            let next = self.next.take().expect("Checked above");
+            self.active = next.key;
+        }
+
+        &self.active
+    }
+
+    /// The next key, and how long until it becomes active.
+    pub fn next_key(&mut self) -> Option<(&SigningKey, Duration)> {
+        // Activate the next key first, if its due.
+        self.active();
+
+        self.next.as_ref().map(|next| {
+            (
+                &next.key,
+                next.activates_at.saturating_duration_since(Instant::now()),
+            )
+        })
+    }
+
+    /// Generates a new key, that becomes active after `grace_period`.
+    ///
+    /// If a rotation is already in progress, its next key is replaced.
+    ///
+    /// # Errors
+    /// - [`EnclaveError::InvalidRequest`] - The grace period is too long to be represented.
+    pub fn rotate(&mut self, grace_period: Duration) -> Result<(), EnclaveError> {
+        let activates_at = Instant::now().checked_add(grace_period).ok_or_else(|| {
+            EnclaveError::InvalidRequest(format!(
+                "Grace period of {}s is out of range",
+                grace_period.as_secs()
+            ))
+        })?;
+
+        self.active();
+
+        self.next = Some(NextKey {
+            key: SigningKey::random(&mut OsRng),
+            activates_at,
+        });
+
+        Ok(())
+    }
+
+    /// Replaces all the keys, cancelling any rotation in progress.
+    pub fn set(&mut self, key: SigningKey) {
+        self.active = key;
+        self.next = None;
+    }
+
+    /// Returns true if `key` is the active or the next key.
+    pub fn contains(&self, key: &SigningKey) -> bool {
+        let key = key.verifying_key();
+
+        self.active.verifying_key() == key
+            || self
+                .next
+                .as_ref()
+                .is_some_and(|next| next.key.verifying_key() == key)
+    }
+
+    /// Encodes the keys to be sealed or migrated, including a rotation in progress,
+    /// so a restored enclave does not activate the next key before its grace period is over.
+    pub fn to_sealed(&mut self) -> Vec<u8> {
+        self.active();
+
+        let sealed = SealedKeys {
+            active: self.active.to_bytes().to_vec(),
+            next: self.next.as_ref().map(|next| {
+                let activates_at =
+                    SystemTime::now() + next.activates_at.saturating_duration_since(Instant::now());
+
+                (
+                    next.key.to_bytes().to_vec(),
+                    activates_at
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs(),
+                )
+            }),
+        };
+
+        bincode::serialize(&sealed).expect("Failed to serialize the signing keys")
+    }
+
+    /// Decodes the output of [`SigningKeys::to_sealed`].
+    ///
+    /// A bare 32 byte key is accepted as the active key, as sealed by earlier versions of the enclave.
+    ///
+    /// Returns `None` if the bytes are not valid keys.
+    pub fn from_sealed(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() == 32 {
+            return SigningKey::from_slice(bytes).ok().map(Self::new);
+        }
+
+        let sealed: SealedKeys = bincode::deserialize(bytes).ok()?;
+
+        let next = match sealed.next {
+            Some((key, activates_at)) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+
+                Some(NextKey {
+                    key: SigningKey::from_slice(&key).ok()?,
+                    activates_at: Instant::now()
+                        .checked_add(Duration::from_secs(activates_at.saturating_sub(now)))?,
+                })
+            }
+            None => None,
+        };
+
+        Some(Self {
+            active: SigningKey::from_slice(&sealed.active).ok()?,
+            next,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
+    fn public_key(key: &SigningKey) -> k256::EncodedPoint {
+        key.verifying_key().to_encoded_point(false)
+    }
+
+    #[test]
+    fn rotation_activates_after_the_grace_period() {
+        let mut keys = SigningKeys::new(SigningKey::random(&mut OsRng));
+        let old = public_key(keys.active());
+
+        keys.rotate(GRACE_PERIOD).unwrap();
+        let (next, remaining) = keys.next_key().unwrap();
+        let next = public_key(next);
+
+        assert!(remaining <= GRACE_PERIOD);
+        assert_eq!(public_key(keys.active()), old);
+
+        // The next key is replaced, and activates immediately.
+        keys.rotate(Duration::ZERO).unwrap();
+        let active = public_key(keys.active());
+
+        assert_ne!(active, old);
+        assert_ne!(active, next);
+        assert!(keys.next_key().is_none());
+    }
+
+    #[test]
+    fn sealing_keeps_a_rotation_in_progress() {
+        let mut keys = SigningKeys::new(SigningKey::random(&mut OsRng));
+        keys.rotate(GRACE_PERIOD).unwrap();
+
+        let active = public_key(keys.active());
+        let next = public_key(keys.next_key().unwrap().0);
+
+        let mut restored = SigningKeys::from_sealed(&keys.to_sealed()).unwrap();
+
+        assert_eq!(public_key(restored.active()), active);
+
+        let (restored_next, remaining) = restored.next_key().unwrap();
+        assert_eq!(public_key(restored_next), next);
+        assert!(remaining > GRACE_PERIOD - Duration::from_secs(5));
+        assert!(remaining <= GRACE_PERIOD);
+    }
+
+    #[test]
+    fn sealing_without_a_rotation() {
+        let mut keys = SigningKeys::new(SigningKey::random(&mut OsRng));
+        let active = public_key(keys.active());
+
+        let mut restored = SigningKeys::from_sealed(&keys.to_sealed()).unwrap();
+
+        assert_eq!(public_key(restored.active()), active);
+        assert!(restored.next_key().is_none());
+    }
+
+    #[test]
+    fn unseals_bare_keys() {
+        let key = SigningKey::random(&mut OsRng);
+
+        let mut restored = SigningKeys::from_sealed(&key.to_bytes()).unwrap();
+
+        assert_eq!(public_key(restored.active()), public_key(&key));
+        assert!(restored.next_key().is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_keys() {
+        assert!(SigningKeys::from_sealed(&[0; 32]).is_none());
+        assert!(SigningKeys::from_sealed(&[1; 40]).is_none());
+    }
+
+    #[test]
+    fn rejects_grace_periods_out_of_range() {
+        let mut keys = SigningKeys::new(SigningKey::random(&mut OsRng));
+        let active = public_key(keys.active());
+
+        assert!(matches!(
+            keys.rotate(Duration::from_secs(u64::MAX)),
+            Err(EnclaveError::InvalidRequest(_))
+        ));
+        assert!(keys.next_key().is_none());
+        assert_eq!(public_key(keys.active()), active);
+    }
+
+    #[test]
+    fn contains_the_active_and_next_keys() {
+        let active = SigningKey::random(&mut OsRng);
+        let mut keys = SigningKeys::new(active.clone());
+        keys.rotate(GRACE_PERIOD).unwrap();
+        let next = keys.next_key().unwrap().0.clone();
+
+        assert!(keys.contains(&active));
+        assert!(keys.contains(&next));
+        assert!(!keys.contains(&SigningKey::random(&mut OsRng)));
+    }
+}
@@ -1,6 +1,25 @@
 use clap::Parser;
+use sealing::SealerConfig;
+use sp1_tee_common::{
+    EnclaveConfig, FrameLimits, TransportAddr, DEFAULT_REQUEST_FRAME_LIMITS,
+    DEFAULT_RESPONSE_FRAME_LIMITS,
+};
+use std::path::PathBuf;
 
+pub mod cache;
+pub mod executor;
+pub mod keys;
+pub mod memory;
+pub mod migration;
+pub mod nsm;
+pub mod scheduler;
+pub mod sealing;
+pub mod selftest;
 pub mod server;
+pub mod session;
+pub mod upload;
+pub mod verifier;
+pub mod worker;
 
 #[allow(unused)]
 pub mod ffi;
@@ -9,20 +28,149 @@ pub const VSOCK_PORT: u32 = 5005;
 
 #[derive(clap::Parser)]
 pub struct EnclaveArgs {
-    /// The ARN of the KMS key used for sealing.
+    /// How the signing key is sealed, so it survives a restart.
+    ///
+    /// Either the ARN of a KMS key, `file://<path>` to store the key in plaintext (for testing only),
+    /// or `none` to disable sealing.
     #[clap(short, long)]
-    enc_key_arn: String,
+    enc_key_arn: SealerConfig,
+
+    /// The vsock port of the proxy to KMS on the parent instance.
+    #[clap(long, default_value_t = 8000)]
+    kms_proxy_port: u32,
 
     /// The CID of the enclave.
     #[clap(short, long)]
     cid: Option<u32>,
+
+    /// The address to listen on, defaults to `vsock://<cid>:5005`.
+    ///
+    /// Use `unix://<path>` or `tcp://<addr>` to run the enclave outside of Nitro.
+    #[clap(short, long)]
+    listen: Option<TransportAddr>,
+
+    /// Use a software NSM instead of the Nitro driver, to run the enclave on an ordinary Linux machine.
+    ///
+    /// WARNING: Attestations from the mock NSM are signed by a test CA, this is for testing only.
+    #[clap(long)]
+    mock_nsm: bool,
+
+    /// Where to write the DER encoded root certificate of the mock NSM's test CA.
+    ///
+    /// Point the host's `SP1_TEE_TEST_ROOT_CERT` at this file, to verify the mock NSM's attestations
+    /// with a host built with the `test-root` feature.
+    #[clap(long, requires = "mock_nsm")]
+    mock_nsm_root_cert: Option<PathBuf>,
+
+    /// A directory holding the root of the mock NSM's test CA, which is generated if the directory has none.
+    ///
+    /// Enclaves sharing a directory attest under the same root, so they can migrate keys between each other,
+    /// and the host can verify all of them with one `SP1_TEE_TEST_ROOT_CERT`, i.e. `<dir>/root.der`.
+    #[clap(long, requires = "mock_nsm")]
+    mock_nsm_ca_dir: Option<PathBuf>,
+
+    /// The maximum size (in bytes) of a request frame carrying a program and stdin.
+    #[clap(long, default_value_t = DEFAULT_REQUEST_FRAME_LIMITS.payload)]
+    max_payload_frame_size: u32,
+
+    /// The maximum size (in bytes) of any other request frame.
+    #[clap(long, default_value_t = DEFAULT_REQUEST_FRAME_LIMITS.control)]
+    max_control_frame_size: u32,
+
+    /// The maximum size (in bytes) of a response frame carrying a result, i.e. public values.
+    ///
+    /// This should match the value the host was started with.
+    #[clap(long, default_value_t = DEFAULT_RESPONSE_FRAME_LIMITS.payload)]
+    max_response_payload_frame_size: u32,
+
+    /// The maximum size (in bytes) of any other response frame.
+    ///
+    /// This should match the value the host was started with.
+    #[clap(long, default_value_t = DEFAULT_RESPONSE_FRAME_LIMITS.control)]
+    max_response_control_frame_size: u32,
+
+    /// The maximum number of bytes held by in-progress uploads, across all connections.
+    #[clap(long, default_value_t = 1024 * 1024 * 1024)]
+    max_upload_memory: u64,
+
+    /// The memory (in bytes) available to executions, which run in parallel while their estimated memory fits.
+    ///
+    /// An execution estimated to need more than this runs on its own.
+    #[clap(long, default_value_t = 6 * 1024 * 1024 * 1024)]
+    execution_memory_budget: u64,
+
+    /// The maximum number of bytes used to cache programs that have been set up.
+    #[clap(long, default_value_t = 256 * 1024 * 1024)]
+    program_cache_size: usize,
+
+    /// The maximum cycle limit the enclave accepts for an execution.
+    #[clap(long, default_value_t = u32::MAX as u64)]
+    max_cycles: u64,
+
+    /// The total number of cycles an execution may spend in unconstrained blocks.
+    #[clap(long, default_value_t = 1_000_000_000)]
+    unconstrained_cycle_limit: u64,
+
+    /// How long (in seconds) an execution may run before the enclave stops it.
+    #[clap(long, default_value_t = 1800)]
+    max_execution_secs: u64,
+
+    /// The memory (in bytes) an execution worker may use on top of the estimate of its execution,
+    /// for the prover and the worker itself. It is reserved from `--execution-memory-budget` with the estimate.
+    #[clap(long, default_value_t = 512 * 1024 * 1024)]
+    worker_memory_overhead: u64,
 }
 
-#[tokio::main]
-async fn main() {
+impl EnclaveArgs {
+    /// The limits applied to frames received from the host.
+    pub fn request_frame_limits(&self) -> FrameLimits {
+        FrameLimits {
+            control: self.max_control_frame_size,
+            payload: self.max_payload_frame_size,
+        }
+    }
+
+    /// The limits applied to frames sent to the host.
+    pub fn response_frame_limits(&self) -> FrameLimits {
+        FrameLimits {
+            control: self.max_response_control_frame_size,
+            payload: self.max_response_payload_frame_size,
+        }
+    }
+
+    /// The execution policy, reported to the host and committed to in attestations.
+    pub fn config(&self) -> EnclaveConfig {
+        EnclaveConfig {
+            max_cycles: self.max_cycles,
+            unconstrained_cycle_limit: self.unconstrained_cycle_limit,
+            max_execution_secs: self.max_execution_secs,
+        }
+    }
+}
+
+fn main() {
+    // Executions run in a child process of the server, which never holds the signing key.
+    if std::env::args().nth(1).as_deref() == Some(worker::WORKER_ARG) {
+        worker::run();
+
+        return;
+    }
+
     // Parse the command line arguments.
     let args = EnclaveArgs::parse();
 
+    // The executor reads the unconstrained cycle limit from the environment, this is set
+    // before the runtime starts any threads, and is inherited by the workers.
+    std::env::set_var(
+        "UNCONSTRAINED_CYCLE_LIMIT",
+        args.unconstrained_cycle_limit.to_string(),
+    );
+
+    run_server(args);
+}
+
+#[tokio::main]
+async fn run_server(args: EnclaveArgs) {
     // Initialize the server.
     let server = server::Server::new(args);
 
@@ -0,0 +1,420 @@
+//! Migrating the signing key from a running enclave (the donor) to a freshly booted one (the requester).
+//!
+//! 1. The requester generates an ephemeral X25519 key, and attests to it.
+//! 2. The donor checks the requester runs the same enclave image (PCR0) and TEE version,
+//!    then encrypts its signing key to the ephemeral key, and attests to its own ephemeral key,
+//!    using the requester's ephemeral key as the nonce.
+//! 3. The requester checks the donor in the same way, and decrypts the signing key.
+//!
+//! The hosts only relay attestation documents and ciphertext, so they never see the key.
+
+use crate::nsm::Nsm;
+
+use aws_nitro_enclaves_nsm_api::api::AttestationDoc;
+use ring::{aead, agreement, hkdf, rand::SystemRandom};
+use sp1_sdk::network::tee::SP1_TEE_VERSION;
+use sp1_tee_common::{AttestationUserData, EnclaveError};
+
+/// The context the encryption key is derived with.
+const MIGRATION_INFO: &[u8] = b"sp1-tee signing key migration";
+
+/// A migration started by the requester, waiting for the donor's response.
+pub struct PendingMigration {
+    private_key: agreement::EphemeralPrivateKey,
+    public_key: Vec<u8>,
+}
+
+/// Starts a migration, returning the pending state and the attestation to send to the donor.
+pub fn begin(
+    nsm: &dyn Nsm,
+    user_data: &AttestationUserData,
+) -> Result<(PendingMigration, Vec<u8>), MigrationError> {
+    let rng = SystemRandom::new();
+
+    let private_key = agreement::EphemeralPrivateKey::generate(&agreement::X25519, &rng)
+        .map_err(|_| MigrationError::Crypto)?;
+    let public_key = private_key
+        .compute_public_key()
+        .map_err(|_| MigrationError::Crypto)?
+        .as_ref()
+        .to_vec();
+
+    let attestation = nsm.attest(Some(user_data.encode()), None, Some(public_key.clone()))?;
+
+    Ok((
+        PendingMigration {
+            private_key,
+            public_key,
+        },
+        attestation,
+    ))
+}
+
+/// Encrypts `signing_key` to the requester of `attestation`, returning the donor's attestation and the ciphertext.
+pub fn export(
+    nsm: &dyn Nsm,
+    user_data: &AttestationUserData,
+    signing_key: &[u8],
+    attestation: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), MigrationError> {
+    let requester_public_key = verify_peer(nsm, attestation)?;
+
+    let rng = SystemRandom::new();
+
+    let private_key = agreement::EphemeralPrivateKey::generate(&agreement::X25519, &rng)
+        .map_err(|_| MigrationError::Crypto)?;
+    let public_key = private_key
+        .compute_public_key()
+        .map_err(|_| MigrationError::Crypto)?
+        .as_ref()
+        .to_vec();
+
+    let key = agree(private_key, &requester_public_key, &public_key)?;
+
+    let mut ciphertext = signing_key.to_vec();
+    key.seal_in_place_append_tag(
+        // Each key is only used once, so a fixed nonce is safe.
+        aead::Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
+        aead::Aad::empty(),
+        &mut ciphertext,
+    )
+    .map_err(|_| MigrationError::Crypto)?;
+
+    // Bind the response to the request, so it cannot be replayed to another requester.
+    let attestation = nsm.attest(
+        Some(user_data.encode()),
+        Some(requester_public_key),
+        Some(public_key),
+    )?;
+
+    Ok((attestation, ciphertext))
+}
+
+/// Decrypts the signing key sent by the donor of `attestation`.
+pub fn import(
+    nsm: &dyn Nsm,
+    pending: PendingMigration,
+    attestation: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, MigrationError> {
+    let doc = nsm.verify(attestation)?;
+    let donor_public_key = check_peer(nsm, &doc)?;
+
+    if doc.nonce.as_ref().map(|nonce| nonce.as_slice()) != Some(pending.public_key.as_slice()) {
+        return Err(MigrationError::NonceMismatch);
+    }
+
+    let key = agree(pending.private_key, &donor_public_key, &donor_public_key)?;
+
+    let mut plaintext = ciphertext.to_vec();
+    let signing_key = key
+        .open_in_place(
+            aead::Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
+            aead::Aad::empty(),
+            &mut plaintext,
+        )
+        .map_err(|_| MigrationError::Crypto)?;
+
+    Ok(signing_key.to_vec())
+}
+
+/// Verifies the attestation of the other enclave, returning its ephemeral public key.
+fn verify_peer(nsm: &dyn Nsm, attestation: &[u8]) -> Result<Vec<u8>, MigrationError> {
+    let doc = nsm.verify(attestation)?;
+
+    check_peer(nsm, &doc)
+}
+
+/// Checks the other enclave runs the same image and TEE version, returning its ephemeral public key.
+fn check_peer(nsm: &dyn Nsm, doc: &AttestationDoc) -> Result<Vec<u8>, MigrationError> {
+    let pcr0 = nsm.describe_pcr(0)?;
+
+    if doc.pcrs.get(&0).map(|pcr| pcr.as_slice()) != Some(pcr0.as_slice()) {
+        return Err(MigrationError::Pcr0Mismatch);
+    }
+
+    let tee_version = doc
+        .user_data
+        .as_ref()
+        .and_then(|bytes| AttestationUserData::decode(bytes).ok())
+        .map(|user_data| user_data.tee_version);
+
+    if tee_version != Some(SP1_TEE_VERSION) {
+        return Err(MigrationError::VersionMismatch);
+    }
+
+    doc.public_key
+        .as_ref()
+        .map(|public_key| public_key.to_vec())
+        .ok_or(MigrationError::MissingPublicKey)
+}
+
+/// Derives the encryption key from an X25519 agreement.
+///
+/// The salt is the donor's ephemeral public key, which both sides know.
+fn agree(
+    private_key: agreement::EphemeralPrivateKey,
+    peer_public_key: &[u8],
+    donor_public_key: &[u8],
+) -> Result<aead::LessSafeKey, MigrationError> {
+    let peer_public_key = agreement::UnparsedPublicKey::new(&agreement::X25519, peer_public_key);
+
+    agreement::agree_ephemeral(private_key, &peer_public_key, |shared_secret| {
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, donor_public_key).extract(shared_secret);
+        let key = prk
+            .expand(&[MIGRATION_INFO], &aead::CHACHA20_POLY1305)
+            .map_err(|_| MigrationError::Crypto)?;
+
+        Ok(aead::LessSafeKey::new(aead::UnboundKey::from(key)))
+    })
+    .map_err(|_| MigrationError::Crypto)?
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error(transparent)]
+    Nsm(#[from] EnclaveError),
+
+    #[error("The other enclave has a different PCR0")]
+    Pcr0Mismatch,
+
+    #[error("The other enclave has a different TEE version")]
+    VersionMismatch,
+
+    #[error("The attestation is missing the ephemeral public key")]
+    MissingPublicKey,
+
+    #[error("The donor's attestation is not bound to this migration")]
+    NonceMismatch,
+
+    #[error("No migration is in progress")]
+    NotStarted,
+
+    #[error("Key agreement or decryption failed")]
+    Crypto,
+}
+
+impl From<MigrationError> for EnclaveError {
+    fn from(e: MigrationError) -> Self {
+        match e {
+            MigrationError::Nsm(e) => e,
+            _ => EnclaveError::InvalidRequest(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsm::MockNsm;
+    use sp1_tee_common::EnclaveConfig;
+
+    const SIGNING_KEY: [u8; 32] = [7; 32];
+
+    fn user_data() -> AttestationUserData {
+        crate::server::user_data(&EnclaveConfig {
+            max_cycles: 1_000_000,
+            unconstrained_cycle_limit: 1_000_000,
+            max_execution_secs: 60,
+        })
+    }
+
+    /// A [`MockNsm`] that reports a different PCR0 for itself, as an enclave running another image would.
+    struct OtherImage(MockNsm);
+
+    impl Nsm for OtherImage {
+        fn attest(
+            &self,
+            user_data: Option<Vec<u8>>,
+            nonce: Option<Vec<u8>>,
+            public_key: Option<Vec<u8>>,
+        ) -> Result<Vec<u8>, EnclaveError> {
+            self.0.attest(user_data, nonce, public_key)
+        }
+
+        fn describe_pcr(&self, _: u16) -> Result<Vec<u8>, EnclaveError> {
+            Ok(vec![1; 48])
+        }
+
+        fn verify(&self, document: &[u8]) -> Result<AttestationDoc, EnclaveError> {
+            self.0.verify(document)
+        }
+    }
+
+    #[test]
+    fn migrates_the_signing_key() {
+        let nsm = MockNsm::new(None, None).unwrap();
+
+        let (pending, request) = begin(&nsm, &user_data()).unwrap();
+        let (response, ciphertext) = export(&nsm, &user_data(), &SIGNING_KEY, &request).unwrap();
+
+        // The key is never sent in the clear.
+        assert!(!ciphertext
+            .windows(SIGNING_KEY.len())
+            .any(|window| window == SIGNING_KEY));
+
+        let key = import(&nsm, pending, &response, &ciphertext).unwrap();
+
+        assert_eq!(key, SIGNING_KEY);
+    }
+
+    #[test]
+    fn migrates_between_enclaves_sharing_a_test_ca() {
+        let dir = std::env::temp_dir().join(format!("sp1-tee-migration-{}", std::process::id()));
+
+        let requester = MockNsm::new(None, Some(&dir)).unwrap();
+        let donor = MockNsm::new(None, Some(&dir)).unwrap();
+
+        let (pending, request) = begin(&requester, &user_data()).unwrap();
+        let (response, ciphertext) = export(&donor, &user_data(), &SIGNING_KEY, &request).unwrap();
+        let key = import(&requester, pending, &response, &ciphertext).unwrap();
+
+        assert_eq!(key, SIGNING_KEY);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_enclaves_of_another_test_ca() {
+        let requester = MockNsm::new(None, None).unwrap();
+        let donor = MockNsm::new(None, None).unwrap();
+
+        let (_, request) = begin(&requester, &user_data()).unwrap();
+
+        assert!(matches!(
+            export(&donor, &user_data(), &SIGNING_KEY, &request),
+            Err(MigrationError::Nsm(EnclaveError::InvalidRequest(_)))
+        ));
+    }
+
+    #[test]
+    fn donor_rejects_other_images() {
+        let nsm = MockNsm::new(None, None).unwrap();
+        let (_, request) = begin(&nsm, &user_data()).unwrap();
+
+        let donor = OtherImage(nsm);
+
+        assert!(matches!(
+            export(&donor, &user_data(), &SIGNING_KEY, &request),
+            Err(MigrationError::Pcr0Mismatch)
+        ));
+    }
+
+    #[test]
+    fn requester_rejects_other_images() {
+        let requester = OtherImage(MockNsm::new(None, None).unwrap());
+
+        let (pending, request) = begin(&requester, &user_data()).unwrap();
+        let (response, ciphertext) =
+            export(&requester.0, &user_data(), &SIGNING_KEY, &request).unwrap();
+
+        assert!(matches!(
+            import(&requester, pending, &response, &ciphertext),
+            Err(MigrationError::Pcr0Mismatch)
+        ));
+    }
+
+    #[test]
+    fn rejects_other_tee_versions() {
+        let nsm = MockNsm::new(None, None).unwrap();
+
+        let mut other_version = user_data();
+        other_version.tee_version += 1;
+
+        // The requester runs another TEE version.
+        let (_, request) = begin(&nsm, &other_version).unwrap();
+        assert!(matches!(
+            export(&nsm, &user_data(), &SIGNING_KEY, &request),
+            Err(MigrationError::VersionMismatch)
+        ));
+
+        // The donor runs another TEE version.
+        let (pending, request) = begin(&nsm, &user_data()).unwrap();
+        let (response, ciphertext) = export(&nsm, &other_version, &SIGNING_KEY, &request).unwrap();
+        assert!(matches!(
+            import(&nsm, pending, &response, &ciphertext),
+            Err(MigrationError::VersionMismatch)
+        ));
+    }
+
+    #[test]
+    fn rejects_responses_to_another_migration() {
+        let nsm = MockNsm::new(None, None).unwrap();
+
+        let (_, request) = begin(&nsm, &user_data()).unwrap();
+        let (response, ciphertext) = export(&nsm, &user_data(), &SIGNING_KEY, &request).unwrap();
+
+        // The donor's response is bound to the first migration's ephemeral key.
+        let (pending, _) = begin(&nsm, &user_data()).unwrap();
+
+        assert!(matches!(
+            import(&nsm, pending, &response, &ciphertext),
+            Err(MigrationError::NonceMismatch)
+        ));
+    }
+
+    #[test]
+    fn rejects_the_wrong_donor_key() {
+        let nsm = MockNsm::new(None, None).unwrap();
+
+        let (pending, request) = begin(&nsm, &user_data()).unwrap();
+        let (_, ciphertext) = export(&nsm, &user_data(), &SIGNING_KEY, &request).unwrap();
+
+        // A response bound to this migration, but attesting to an ephemeral key the ciphertext was not made with.
+        let (other, _) = begin(&nsm, &user_data()).unwrap();
+        let response = nsm
+            .attest(
+                Some(user_data().encode()),
+                Some(pending.public_key.clone()),
+                Some(other.public_key),
+            )
+            .unwrap();
+
+        assert!(matches!(
+            import(&nsm, pending, &response, &ciphertext),
+            Err(MigrationError::Crypto)
+        ));
+    }
+
+    #[test]
+    fn rejects_missing_ephemeral_keys() {
+        let nsm = MockNsm::new(None, None).unwrap();
+
+        let request = nsm.attest(Some(user_data().encode()), None, None).unwrap();
+
+        assert!(matches!(
+            export(&nsm, &user_data(), &SIGNING_KEY, &request),
+            Err(MigrationError::MissingPublicKey)
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_ciphertext() {
+        let nsm = MockNsm::new(None, None).unwrap();
+
+        let (pending, request) = begin(&nsm, &user_data()).unwrap();
+        let (response, mut ciphertext) =
+            export(&nsm, &user_data(), &SIGNING_KEY, &request).unwrap();
+
+        ciphertext[0] ^= 1;
+
+        assert!(matches!(
+            import(&nsm, pending, &response, &ciphertext),
+            Err(MigrationError::Crypto)
+        ));
+    }
+
+    #[test]
+    fn rejects_tampered_attestations() {
+        let nsm = MockNsm::new(None, None).unwrap();
+
+        let (_, mut request) = begin(&nsm, &user_data()).unwrap();
+        let last = request.len() - 1;
+        request[last] ^= 1;
+
+        assert!(matches!(
+            export(&nsm, &user_data(), &SIGNING_KEY, &request),
+            Err(MigrationError::Nsm(EnclaveError::InvalidRequest(_)))
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+use aws_nitro_enclaves_nsm_api::{
+    api::{AttestationDoc, Digest, Request, Response},
+    driver::{nsm_exit, nsm_init, nsm_process_request},
+};
+use sp1_tee_common::test_ca::TestCa;
+use sp1_tee_common::{cose, EnclaveError};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The Nitro Secure Module, which attests to the measurements of the enclave.
+///
+/// This is a trait so the enclave can run outside of Nitro, see [`MockNsm`].
+pub trait Nsm: Send + Sync {
+    /// Requests an attestation document, binding the given fields.
+    fn attest(
+        &self,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+        public_key: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, EnclaveError>;
+
+    /// Returns the value of a PCR of this enclave.
+    fn describe_pcr(&self, index: u16) -> Result<Vec<u8>, EnclaveError>;
+
+    /// Verifies an attestation document from another enclave, returning its contents.
+    ///
+    /// The document must have been produced by the same kind of [`Nsm`].
+    fn verify(&self, document: &[u8]) -> Result<AttestationDoc, EnclaveError>;
+}
+
+/// The NSM of a Nitro Enclave, accessed through its driver.
+pub struct NitroNsm;
+
+impl NitroNsm {
+    fn request(&self, request: Request) -> Result<Response, EnclaveError> {
+        let fd = nsm_init();
+
+        if fd < 0 {
+            return Err(EnclaveError::AttestationFailed(
+                "Failed to initialize NSM".to_string(),
+            ));
+        }
+
+        let response = nsm_process_request(fd, request);
+
+        nsm_exit(fd);
+
+        match response {
+            Response::Error(code) => Err(EnclaveError::AttestationFailed(format!(
+                "NSM returned an error: {:?}",
+                code
+            ))),
+            response => Ok(response),
+        }
+    }
+}
+
+impl Nsm for NitroNsm {
+    fn attest(
+        &self,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+        public_key: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, EnclaveError> {
+        let request = Request::Attestation {
+            user_data: user_data.map(Into::into),
+            nonce: nonce.map(Into::into),
+            public_key: public_key.map(Into::into),
+        };
+
+        match self.request(request)? {
+            Response::Attestation { document } => Ok(document),
+            _ => Err(EnclaveError::AttestationFailed(
+                "Unexpected response type from NSM, this is a bug.".to_string(),
+            )),
+        }
+    }
+
+    fn describe_pcr(&self, index: u16) -> Result<Vec<u8>, EnclaveError> {
+        match self.request(Request::DescribePCR { index })? {
+            Response::DescribePCR { data, .. } => Ok(data),
+            _ => Err(EnclaveError::AttestationFailed(
+                "Unexpected response type from NSM, this is a bug.".to_string(),
+            )),
+        }
+    }
+
+    fn verify(&self, document: &[u8]) -> Result<AttestationDoc, EnclaveError> {
+        attestation_doc_validation::validate_and_parse_attestation_doc(document).map_err(|e| {
+            EnclaveError::InvalidRequest(format!("Invalid attestation document: {}", e))
+        })
+    }
+}
+
+/// A software stand in for the NSM, for running the enclave on an ordinary Linux machine.
+///
+/// Documents are signed by a [`TestCa`], and every PCR is zero,
+/// as they are for a Nitro Enclave in debug mode.
+///
+/// WARNING: This attests to nothing, it exists for testing only.
+pub struct MockNsm {
+    ca: TestCa,
+}
+
+impl MockNsm {
+    /// The length of a SHA384 PCR.
+    const PCR_LEN: usize = 48;
+
+    /// Creates the test CA, writing its root certificate to `root_cert_path` for verifiers to trust.
+    ///
+    /// If `ca_dir` is set, the root is shared with every mock NSM using the same directory,
+    /// see [`TestCa::load_or_generate`], otherwise a new root is generated.
+    pub fn new(root_cert_path: Option<&Path>, ca_dir: Option<&Path>) -> Result<Self, EnclaveError> {
+        let ca = match ca_dir {
+            Some(dir) => TestCa::load_or_generate(dir)?,
+            None => TestCa::generate()?,
+        };
+
+        if let Some(path) = root_cert_path {
+            std::fs::write(path, ca.root_cert()).map_err(|e| {
+                EnclaveError::Internal(format!("Failed to write the test root certificate: {}", e))
+            })?;
+        }
+
+        Ok(Self { ca })
+    }
+}
+
+impl Nsm for MockNsm {
+    fn attest(
+        &self,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+        public_key: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, EnclaveError> {
+        let pcrs = (0..3)
+            .map(|index| (index, vec![0; Self::PCR_LEN]))
+            .collect::<BTreeMap<_, _>>();
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        let doc = AttestationDoc::new(
+            "mock-nsm".to_string(),
+            Digest::SHA384,
+            timestamp,
+            pcrs,
+            self.ca.leaf_cert().to_vec(),
+            vec![self.ca.root_cert().to_vec()],
+            user_data,
+            nonce,
+            public_key,
+        );
+
+        cose::sign(&doc, self.ca.leaf_key())
+            .map_err(|e| EnclaveError::AttestationFailed(e.to_string()))
+    }
+
+    fn describe_pcr(&self, _: u16) -> Result<Vec<u8>, EnclaveError> {
+        Ok(vec![0; Self::PCR_LEN])
+    }
+
+    fn verify(&self, document: &[u8]) -> Result<AttestationDoc, EnclaveError> {
+        cose::verify(document, self.ca.root_cert()).map_err(|e| {
+            EnclaveError::InvalidRequest(format!("Invalid attestation document: {}", e))
+        })
+    }
+}
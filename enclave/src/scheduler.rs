@@ -0,0 +1,289 @@
+use crate::executor::CancelFlag;
+
+use parking_lot::{Condvar, Mutex};
+use sp1_tee_common::{EnclaveError, SchedulerStatus};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The memory every execution needs regardless of its inputs, for the executor and the response.
+const BASE_EXECUTION_MEMORY: u64 = 64 * 1024 * 1024;
+
+/// Setup and the executor each hold the program, and its memory image is larger than the ELF.
+const ELF_MEMORY_FACTOR: u64 = 8;
+
+/// The executor holds the stdin buffers, and the program reads them into its own memory.
+const STDIN_MEMORY_FACTOR: u64 = 2;
+
+/// Each cycle touches at most one new word, which the executor stores along with its metadata.
+const BYTES_PER_CYCLE: u64 = 8;
+
+/// The verifier holds the proof, and decodes it into its own representation.
+const PROOF_MEMORY_FACTOR: u64 = 4;
+
+/// Estimates the memory an execution needs, from the size of its inputs and its cycle limit.
+///
+/// This is deliberately pessimistic, the cycle limit bounds how much memory the program can touch.
+pub fn estimate_memory(elf_len: usize, stdin_len: usize, cycle_limit: u64) -> u64 {
+    BASE_EXECUTION_MEMORY
+        .saturating_add((elf_len as u64).saturating_mul(ELF_MEMORY_FACTOR))
+        .saturating_add((stdin_len as u64).saturating_mul(STDIN_MEMORY_FACTOR))
+        .saturating_add(cycle_limit.saturating_mul(BYTES_PER_CYCLE))
+}
+
+/// Estimates the memory verifying a proof needs, from its encoded size.
+pub fn estimate_verify_memory(proof_len: u64) -> u64 {
+    BASE_EXECUTION_MEMORY.saturating_add(proof_len.saturating_mul(PROOF_MEMORY_FACTOR))
+}
+
+/// Admits executions while their estimated memory fits in the budget, so small executions run in parallel.
+///
+/// Executions are admitted in order, so a large execution waiting for the enclave to drain is not starved
+/// by smaller ones. An execution estimated to need more than the whole budget runs on its own.
+pub struct ExecutionScheduler {
+    /// The memory available to executions, in bytes.
+    budget: u64,
+    state: Mutex<SchedulerState>,
+    /// Notified whenever memory is released, or the head of the queue changes.
+    changed: Condvar,
+}
+
+struct SchedulerState {
+    /// The memory reserved by running executions.
+    reserved: u64,
+    /// The number of running executions.
+    running: u32,
+    /// The tickets of waiting executions, in the order they arrived.
+    queue: VecDeque<u64>,
+    next_ticket: u64,
+}
+
+impl ExecutionScheduler {
+    pub fn new(budget: u64) -> Arc<Self> {
+        Arc::new(Self {
+            budget,
+            state: Mutex::new(SchedulerState {
+                reserved: 0,
+                running: 0,
+                queue: VecDeque::new(),
+                next_ticket: 0,
+            }),
+            changed: Condvar::new(),
+        })
+    }
+
+    /// Waits until `memory` bytes can be reserved, and all earlier executions have been admitted.
+    ///
+    /// The memory is released when the [`ExecutionPermit`] is dropped.
+    ///
+    /// # Errors
+    /// - [`EnclaveError::Cancelled`] - The request was cancelled while waiting.
+    pub fn admit(
+        self: &Arc<Self>,
+        memory: u64,
+        cancel: &CancelFlag,
+    ) -> Result<ExecutionPermit, EnclaveError> {
+        // Oversized executions wait for the enclave to drain, then run alone.
+        let memory = memory.min(self.budget);
+
+        let mut state = self.state.lock();
+
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.queue.push_back(ticket);
+
+        loop {
+            if state.queue.front() == Some(&ticket) && state.reserved + memory <= self.budget {
+                state.queue.pop_front();
+                state.reserved += memory;
+                state.running += 1;
+
+                // The next execution in the queue may fit as well.
+                self.changed.notify_all();
+
+                return Ok(ExecutionPermit {
+                    scheduler: self.clone(),
+                    memory,
+                });
+            }
+
+            if cancel.is_cancelled() {
+                state.queue.retain(|queued| *queued != ticket);
+                self.changed.notify_all();
+
+                return Err(EnclaveError::Cancelled);
+            }
+
+            // Wake up periodically, so a request cancelled while waiting does not hold up the queue.
+            self.changed
+                .wait_for(&mut state, Duration::from_millis(100));
+        }
+    }
+
+    pub fn status(&self) -> SchedulerStatus {
+        let state = self.state.lock();
+
+        SchedulerStatus {
+            memory_budget: self.budget,
+            reserved_memory: state.reserved,
+            running: state.running,
+            queued: state.queue.len() as u32,
+        }
+    }
+}
+
+/// Memory reserved for a running execution, released on drop.
+pub struct ExecutionPermit {
+    scheduler: Arc<ExecutionScheduler>,
+    memory: u64,
+}
+
+impl ExecutionPermit {
+    /// The memory reserved for the execution, in bytes.
+    pub fn memory(&self) -> u64 {
+        self.memory
+    }
+}
+
+impl Drop for ExecutionPermit {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock();
+
+        state.reserved -= self.memory;
+        state.running -= 1;
+
+        self.scheduler.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const BUDGET: u64 = 100;
+
+    /// Waits until `n` executions are queued.
+    fn wait_for_queued(scheduler: &ExecutionScheduler, n: u32) {
+        while scheduler.status().queued < n {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn estimates_grow_with_inputs() {
+        let base = estimate_memory(0, 0, 0);
+        assert_eq!(base, BASE_EXECUTION_MEMORY);
+
+        assert_eq!(estimate_memory(1, 0, 0), base + ELF_MEMORY_FACTOR);
+        assert_eq!(estimate_memory(0, 1, 0), base + STDIN_MEMORY_FACTOR);
+        assert_eq!(estimate_memory(0, 0, 1), base + BYTES_PER_CYCLE);
+        assert_eq!(estimate_memory(0, 0, u64::MAX), u64::MAX);
+
+        assert_eq!(estimate_verify_memory(1), base + PROOF_MEMORY_FACTOR);
+        assert_eq!(estimate_verify_memory(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn admits_executions_that_fit_in_parallel() {
+        let scheduler = ExecutionScheduler::new(BUDGET);
+        let cancel = CancelFlag::default();
+
+        let first = scheduler.admit(40, &cancel).unwrap();
+        let second = scheduler.admit(60, &cancel).unwrap();
+
+        assert_eq!(
+            scheduler.status(),
+            SchedulerStatus {
+                memory_budget: BUDGET,
+                reserved_memory: 100,
+                running: 2,
+                queued: 0,
+            }
+        );
+
+        drop(first);
+        drop(second);
+
+        assert_eq!(scheduler.status().reserved_memory, 0);
+        assert_eq!(scheduler.status().running, 0);
+    }
+
+    #[test]
+    fn oversized_executions_run_alone() {
+        let scheduler = ExecutionScheduler::new(BUDGET);
+        let cancel = CancelFlag::default();
+
+        let permit = scheduler.admit(BUDGET * 2, &cancel).unwrap();
+
+        assert_eq!(permit.memory(), BUDGET);
+        assert_eq!(scheduler.status().reserved_memory, BUDGET);
+    }
+
+    #[test]
+    fn waits_for_memory_in_order() {
+        let scheduler = ExecutionScheduler::new(BUDGET);
+        let running = scheduler.admit(60, &CancelFlag::default()).unwrap();
+
+        // The large execution arrives first, and must not be overtaken by the small one, even though it fits.
+        let large = thread::spawn({
+            let scheduler = scheduler.clone();
+
+            move || scheduler.admit(80, &CancelFlag::default()).unwrap()
+        });
+        wait_for_queued(&scheduler, 1);
+
+        let small = thread::spawn({
+            let scheduler = scheduler.clone();
+
+            move || scheduler.admit(10, &CancelFlag::default()).unwrap()
+        });
+        wait_for_queued(&scheduler, 2);
+
+        // Longer than the interval waiting executions wake up at.
+        thread::sleep(Duration::from_millis(250));
+
+        let status = scheduler.status();
+        assert_eq!(status.running, 1);
+        assert_eq!(status.queued, 2);
+        assert_eq!(status.reserved_memory, 60);
+
+        drop(running);
+
+        let large = large.join().unwrap();
+        let small = small.join().unwrap();
+
+        assert_eq!(scheduler.status().reserved_memory, 90);
+        assert_eq!(scheduler.status().running, 2);
+
+        drop((large, small));
+        assert_eq!(scheduler.status().reserved_memory, 0);
+    }
+
+    #[test]
+    fn cancelled_executions_leave_the_queue() {
+        let scheduler = ExecutionScheduler::new(BUDGET);
+        let running = scheduler.admit(BUDGET, &CancelFlag::default()).unwrap();
+
+        let cancel = CancelFlag::default();
+        let waiting = thread::spawn({
+            let scheduler = scheduler.clone();
+            let cancel = cancel.clone();
+
+            move || scheduler.admit(10, &cancel).map(|_| ())
+        });
+        wait_for_queued(&scheduler, 1);
+
+        cancel.cancel();
+
+        assert!(matches!(
+            waiting.join().unwrap(),
+            Err(EnclaveError::Cancelled)
+        ));
+        assert_eq!(scheduler.status().queued, 0);
+
+        // The queue is not held up by the cancelled execution.
+        drop(running);
+        scheduler.admit(10, &CancelFlag::default()).unwrap();
+    }
+}
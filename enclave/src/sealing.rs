@@ -0,0 +1,275 @@
+use crate::ffi::{
+    self,
+    kms::{
+        aws_kms_decrypt_blocking, aws_kms_encrypt_blocking,
+        aws_nitro_enclaves_kms_client_config_default, aws_nitro_enclaves_kms_client_config_destroy,
+        aws_nitro_enclaves_kms_client_destroy, aws_nitro_enclaves_kms_client_new,
+        AWSSocketEndpoint, KmsClient, AWS_OP_SUCCESS, AWS_SOCKET_VSOCK,
+    },
+    AWSByteBuf, OwnedAWSString,
+};
+
+use sha3::Digest;
+use sp1_tee_common::{AwsCredentials, EnclaveError};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Seals the enclave's signing key, so it survives a restart of the enclave.
+///
+/// The sealed key is handed to the host for safe keeping, it must be useless to anyone
+/// but an enclave that is allowed to unseal it.
+pub trait KeySealer: Send + Sync {
+    /// Seals the raw bytes of a signing key.
+    fn seal(&self, key: &[u8], credentials: Option<&AwsCredentials>) -> Result<Vec<u8>, SealError>;
+
+    /// Recovers the raw bytes of a signing key from the output of [`KeySealer::seal`].
+    fn unseal(
+        &self,
+        sealed: &[u8],
+        credentials: Option<&AwsCredentials>,
+    ) -> Result<Vec<u8>, SealError>;
+}
+
+/// How the enclave seals its signing key, parsed from `--enc-key-arn`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SealerConfig {
+    /// `none`, the signing key is never sealed.
+    Disabled,
+    /// `file://<path>`, see [`FileSealer`].
+    File(PathBuf),
+    /// The ARN of a KMS key, see [`KmsSealer`].
+    Kms { key_arn: String, region: String },
+}
+
+impl SealerConfig {
+    /// Creates the sealer, returns `None` if sealing is disabled.
+    ///
+    /// `kms_proxy_port` is the vsock port of the proxy to KMS on the parent instance.
+    pub fn into_sealer(self, kms_proxy_port: u32) -> Option<Box<dyn KeySealer>> {
+        match self {
+            SealerConfig::Disabled => None,
+            SealerConfig::File(path) => Some(Box::new(FileSealer { path })),
+            SealerConfig::Kms { key_arn, region } => Some(Box::new(KmsSealer {
+                key_arn,
+                region,
+                proxy_port: kms_proxy_port,
+            })),
+        }
+    }
+}
+
+impl FromStr for SealerConfig {
+    type Err = SealError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "none" {
+            return Ok(SealerConfig::Disabled);
+        }
+
+        if let Some(path) = s.strip_prefix("file://") {
+            return Ok(SealerConfig::File(PathBuf::from(path)));
+        }
+
+        // arn:<partition>:kms:<region>:<account>:key/<id>
+        let parts = s.splitn(6, ':').collect::<Vec<_>>();
+        match parts.as_slice() {
+            ["arn", _, "kms", region, _, resource]
+                if !region.is_empty() && resource.starts_with("key/") =>
+            {
+                Ok(SealerConfig::Kms {
+                    key_arn: s.to_string(),
+                    region: region.to_string(),
+                })
+            }
+            _ => Err(SealError::InvalidConfig(s.to_string())),
+        }
+    }
+}
+
+/// Seals the signing key with AWS KMS.
+///
+/// The key is encrypted under a KMS key, and decrypted with an attested request,
+/// so the key policy can restrict decryption to enclaves with specific measurements.
+///
+/// The enclave has no network access, requests are sent through a vsock-proxy on the parent instance.
+pub struct KmsSealer {
+    key_arn: String,
+    region: String,
+    proxy_port: u32,
+}
+
+impl KmsSealer {
+    /// Runs `f` with a KMS client, authenticated with `credentials`.
+    fn with_client<R>(
+        &self,
+        credentials: Option<&AwsCredentials>,
+        f: impl FnOnce(*mut KmsClient) -> R,
+    ) -> Result<R, SealError> {
+        let credentials = credentials.ok_or(SealError::MissingCredentials)?;
+
+        ffi::init();
+
+        // These must outlive the client.
+        let region = OwnedAWSString::new(&self.region);
+        let access_key_id = OwnedAWSString::new(&credentials.access_key_id);
+        let secret_access_key = OwnedAWSString::new(&credentials.secret_access_key);
+        let session_token = credentials
+            .session_token
+            .as_deref()
+            .map(OwnedAWSString::new);
+        let mut endpoint = AWSSocketEndpoint::parent(self.proxy_port);
+
+        // SAFETY:
+        // - All the strings and the endpoint are valid, and outlive the client.
+        // - The client and configuration are destroyed exactly once, after their last use.
+        unsafe {
+            let config = aws_nitro_enclaves_kms_client_config_default(
+                region.as_ptr(),
+                &mut endpoint,
+                AWS_SOCKET_VSOCK,
+                access_key_id.as_ptr(),
+                secret_access_key.as_ptr(),
+                session_token
+                    .as_ref()
+                    .map_or(std::ptr::null_mut(), |token| token.as_ptr()),
+            );
+
+            if config.is_null() {
+                return Err(SealError::Kms("Failed to configure KMS client"));
+            }
+
+            let client = aws_nitro_enclaves_kms_client_new(config);
+
+            if client.is_null() {
+                aws_nitro_enclaves_kms_client_config_destroy(config);
+
+                return Err(SealError::Kms("Failed to create KMS client"));
+            }
+
+            let result = f(client);
+
+            aws_nitro_enclaves_kms_client_destroy(client);
+            aws_nitro_enclaves_kms_client_config_destroy(config);
+
+            Ok(result)
+        }
+    }
+}
+
+impl KeySealer for KmsSealer {
+    fn seal(&self, key: &[u8], credentials: Option<&AwsCredentials>) -> Result<Vec<u8>, SealError> {
+        let plaintext = AWSByteBuf::from(key);
+        let mut ciphertext = AWSByteBuf::empty();
+
+        let code = self.with_client(credentials, |client| {
+            let key_id = OwnedAWSString::new(&self.key_arn);
+
+            // SAFETY: The client is valid, and the buffers are valid for the duration of the call.
+            unsafe {
+                aws_kms_encrypt_blocking(client, key_id.as_ptr(), &plaintext, &mut ciphertext)
+            }
+        })?;
+
+        if code != AWS_OP_SUCCESS {
+            return Err(SealError::Kms("Encrypt request failed"));
+        }
+
+        Ok(ciphertext.as_slice().to_vec())
+    }
+
+    fn unseal(
+        &self,
+        sealed: &[u8],
+        credentials: Option<&AwsCredentials>,
+    ) -> Result<Vec<u8>, SealError> {
+        let ciphertext = AWSByteBuf::from(sealed);
+        let mut plaintext = AWSByteBuf::empty();
+
+        let code = self.with_client(credentials, |client| {
+            let key_id = OwnedAWSString::new(&self.key_arn);
+            let algorithm = OwnedAWSString::new("SYMMETRIC_DEFAULT");
+
+            // SAFETY: The client is valid, and the buffers are valid for the duration of the call.
+            unsafe {
+                aws_kms_decrypt_blocking(
+                    client,
+                    key_id.as_ptr(),
+                    algorithm.as_ptr(),
+                    &ciphertext,
+                    &mut plaintext,
+                )
+            }
+        })?;
+
+        if code != AWS_OP_SUCCESS {
+            return Err(SealError::Kms("Decrypt request failed"));
+        }
+
+        Ok(plaintext.as_slice().to_vec())
+    }
+}
+
+/// Stores the signing key in a local file, the sealed key is a commitment to it.
+///
+/// WARNING: This is NOT secure, the key is stored in plaintext.
+/// It exists for tests, and for running the enclave outside of Nitro.
+pub struct FileSealer {
+    path: PathBuf,
+}
+
+impl KeySealer for FileSealer {
+    fn seal(&self, key: &[u8], _: Option<&AwsCredentials>) -> Result<Vec<u8>, SealError> {
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)?;
+
+        file.write_all(key)?;
+        file.sync_all()?;
+
+        Ok(sha3::Keccak256::digest(key).to_vec())
+    }
+
+    fn unseal(&self, sealed: &[u8], _: Option<&AwsCredentials>) -> Result<Vec<u8>, SealError> {
+        let key = std::fs::read(&self.path)?;
+
+        if sha3::Keccak256::digest(&key)[..] != *sealed {
+            return Err(SealError::KeyMismatch);
+        }
+
+        Ok(key)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SealError {
+    #[error("Invalid --enc-key-arn {0:?}, expected `none`, `file://<path>` or a KMS key ARN")]
+    InvalidConfig(String),
+
+    #[error("AWS credentials are required to seal with KMS")]
+    MissingCredentials,
+
+    #[error("KMS error: {0}")]
+    Kms(&'static str),
+
+    #[error("Io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("The sealed key does not match the stored key")]
+    KeyMismatch,
+}
+
+impl From<SealError> for EnclaveError {
+    fn from(e: SealError) -> Self {
+        match e {
+            SealError::MissingCredentials | SealError::KeyMismatch => {
+                EnclaveError::InvalidRequest(e.to_string())
+            }
+            _ => EnclaveError::SealingFailed(e.to_string()),
+        }
+    }
+}
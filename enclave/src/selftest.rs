@@ -0,0 +1,118 @@
+//! The known-answer test the enclave runs before it advertises its signing key.
+//!
+//! A fixture is executed like any other program, and its vkey and public values are checked against values
+//! computed outside the enclave. The signature is then checked against the enclave's own key, so a broken
+//! executor or signer is caught before the key is registered with a verifier.
+
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use sp1_sdk::SP1Stdin;
+use sp1_tee_common::SignedMessage;
+
+/// The fibonacci program, as used by the examples.
+pub const FIXTURE_ELF: &[u8] = include_bytes!("../../fixtures/fibonacci.elf");
+
+/// The number of fibonacci numbers the fixture computes.
+const FIXTURE_N: u32 = 20;
+
+/// The cycle limit the fixture runs under, it needs fewer than 2,000 cycles.
+///
+/// This keeps the memory the scheduler reserves for the self-test small.
+pub const FIXTURE_CYCLE_LIMIT: u64 = 1_000_000;
+
+/// The verifying key of the fixture, as `vk.bytes32()`, computed with SP1 v5.0.2, the version in `Cargo.lock`.
+///
+/// NOTE: This changes with the SP1 version, and must be updated along with it.
+const EXPECTED_VKEY: &str = "0x0052e1b1bcf7d9fa2f0fdee0033004f195e89663f4c023d936987d6b03e22513";
+
+/// The hex encoded public values of the fixture, `n || fib(n) || fib(n + 1)` modulo 7919 as little endian `u32`s.
+const EXPECTED_PUBLIC_VALUES: &str = "140000006d1a0000d30b0000";
+
+/// The stdin the fixture is executed with.
+pub fn stdin() -> SP1Stdin {
+    let mut stdin = SP1Stdin::new();
+    stdin.write(&FIXTURE_N);
+
+    stdin
+}
+
+/// Checks the vkey and public values of the fixture against the known answer.
+pub fn check_output(vkey: &[u8; 32], public_values: &[u8]) -> Result<(), String> {
+    let vkey = format!("0x{}", hex::encode(vkey));
+
+    if vkey != EXPECTED_VKEY {
+        return Err(format!(
+            "Unexpected vkey, expected {}, found {}",
+            EXPECTED_VKEY, vkey
+        ));
+    }
+
+    let public_values = hex::encode(public_values);
+
+    if public_values != EXPECTED_PUBLIC_VALUES {
+        return Err(format!(
+            "Unexpected public values, expected 0x{}, found 0x{}",
+            EXPECTED_PUBLIC_VALUES, public_values
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks that `signature` over `message` recovers to `expected`, as a verifier would.
+pub fn check_signature(
+    message: &SignedMessage<'_>,
+    signature: &Signature,
+    recovery_id: u8,
+    expected: &VerifyingKey,
+) -> Result<(), String> {
+    let recovery_id = RecoveryId::from_byte(recovery_id)
+        .ok_or_else(|| format!("Invalid recovery ID: {}", recovery_id))?;
+
+    let key = VerifyingKey::recover_from_digest(message.digest(), signature, recovery_id)
+        .map_err(|e| format!("Failed to recover the signer: {}", e))?;
+
+    if &key != expected {
+        return Err("The signature does not recover to the enclave's signing key".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{execute, CancelFlag};
+    use sp1_sdk::{CpuProver, HashableKey, Prover};
+    use std::time::Duration;
+
+    #[test]
+    fn fixture_matches_the_known_answer() {
+        let (_, vk) = CpuProver::new().setup(FIXTURE_ELF);
+
+        let execution = execute(
+            None,
+            FIXTURE_ELF,
+            &stdin(),
+            FIXTURE_CYCLE_LIMIT,
+            Duration::from_secs(60),
+            &CancelFlag::default(),
+        )
+        .map_err(|failure| failure.error)
+        .unwrap();
+
+        check_output(&vk.bytes32_raw(), execution.public_values.as_slice()).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_outputs() {
+        let vkey: [u8; 32] = hex::decode(&EXPECTED_VKEY[2..])
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let public_values = hex::decode(EXPECTED_PUBLIC_VALUES).unwrap();
+
+        assert!(check_output(&vkey, &public_values).is_ok());
+        assert!(check_output(&[0; 32], &public_values).is_err());
+        assert!(check_output(&vkey, &public_values[..8]).is_err());
+    }
+}
@@ -1,24 +1,35 @@
+use crate::cache::{CachedProgram, ProgramCache};
+use crate::executor::CancelFlag;
+use crate::keys::SigningKeys;
+use crate::migration::{self, MigrationError, PendingMigration};
+use crate::nsm::{MockNsm, NitroNsm, Nsm};
+use crate::scheduler::{self, ExecutionScheduler};
+use crate::sealing::KeySealer;
+use crate::selftest;
+use crate::session::Session;
+use crate::upload::UploadBudget;
+use crate::verifier;
+use crate::worker::{self, WorkerLimits};
 use crate::EnclaveArgs;
 
-use aws_nitro_enclaves_nsm_api::{
-    api::{Request, Response},
-    driver::{nsm_exit, nsm_init, nsm_process_request},
-};
 use k256::ecdsa::SigningKey;
 use parking_lot::Mutex;
 use rand_core::OsRng;
-use sha3::Digest;
-use sp1_sdk::{network::tee::SP1_TEE_VERSION, CpuProver, HashableKey, Prover, SP1Stdin};
-use sp1_tee_common::{EnclaveRequest, EnclaveResponse, VsockStream};
+use sp1_sdk::{
+    network::tee::SP1_TEE_VERSION, CpuProver, HashableKey, SP1ProofWithPublicValues, SP1Stdin,
+    SP1VerifyingKey,
+};
+use sp1_tee_common::{
+    program_hash, stdin_hash, AttestationUserData, AttestedKey, AttestedKeys, AwsCredentials,
+    BuildInfo, CommunicationError, EnclaveConfig, EnclaveError, EnclaveInfo, EnclaveRequest,
+    EnclaveResponse, ExecutionReport, FailureKind, FailureReceipt, InputCommitment, NextKey,
+    ProofVerdict, RequestId, SelfTestReport, SignatureDomain, SignedFailureReceipt, SignedMessage,
+    SignedProofVerdict, Transport, TransportAddr, UploadKind, VsockStream, MAX_NONCE_LEN,
+    PROTOCOL_VERSION, SIGNATURE_FORMAT, USER_DATA_VERSION,
+};
 use std::sync::Arc;
-use tokio_vsock::{VsockAddr, VsockListener, VsockStream as VsockStreamRaw, VMADDR_CID_ANY};
-
-const MAX_ALLOWED_CYCLES: u64 = u32::MAX as u64;
-
-enum ConnectionState {
-    Continue,
-    Close,
-}
+use std::time::{Duration, Instant};
+use tokio_vsock::VMADDR_CID_ANY;
 
 /// Macro for printing debug messages.
 ///
@@ -30,21 +41,99 @@ macro_rules! debug_print {
     };
 }
 
+/// Information about this enclave, sent to the host during the handshake.
+fn enclave_info() -> EnclaveInfo {
+    EnclaveInfo {
+        protocol_version: PROTOCOL_VERSION,
+        tee_version: SP1_TEE_VERSION,
+        build: BuildInfo {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            git_revision: env!("SP1_TEE_GIT_REVISION").to_string(),
+            debug_mode: cfg!(feature = "debug-mode"),
+        },
+    }
+}
+
+/// The `user_data` of this enclave's attestation documents, committing to the execution policy in `config`.
+pub fn user_data(config: &EnclaveConfig) -> AttestationUserData {
+    AttestationUserData {
+        version: USER_DATA_VERSION,
+        tee_version: SP1_TEE_VERSION,
+        git_revision: Some(env!("SP1_TEE_GIT_REVISION").to_string()),
+        cargo_lock_hash: Some(env!("SP1_TEE_CARGO_LOCK_HASH").to_string()),
+        max_allowed_cycles: Some(config.max_cycles),
+        unconstrained_cycle_limit: Some(config.unconstrained_cycle_limit),
+        signature_format: Some(SIGNATURE_FORMAT),
+        max_execution_secs: Some(config.max_execution_secs),
+    }
+}
+
+/// Checks that a signature domain has not expired, by the enclave's clock.
+fn check_domain(domain: Option<&SignatureDomain>) -> Result<(), EnclaveError> {
+    let Some(domain) = domain else {
+        return Ok(());
+    };
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    if domain.expires_at <= now {
+        return Err(EnclaveError::InvalidRequest(format!(
+            "Signature domain expired at {}, the enclave time is {}",
+            domain.expires_at, now
+        )));
+    }
+
+    Ok(())
+}
+
 pub struct Server {
     /// The arguments passed to the enclave at startup.
     args: EnclaveArgs,
-    /// The signing key for the enclave.
+    /// The signing keys for the enclave.
     ///
-    /// Wrapped in a [`parking_lot::Mutex`] as the host may change it.
-    signing_key: Mutex<SigningKey>,
-    /// Note: Only one execution can be running at a time, as it allocates a significant amount of memory.
+    /// Wrapped in a [`parking_lot::Mutex`] as the host may change or rotate them.
+    signing_keys: Mutex<SigningKeys>,
+    /// Seals the signing key for the host to persist, `None` if sealing is disabled.
+    sealer: Option<Box<dyn KeySealer>>,
+    /// The NSM, used to attest to the signing key.
+    nsm: Box<dyn Nsm>,
+    /// The ephemeral key of a migration from a donor enclave, waiting for the donor's response.
+    pending_migration: Mutex<Option<PendingMigration>>,
+    /// Admits executions against a memory budget.
     ///
     /// In the enclave, memory MUST be specified up front, so extra consideration is required to ensure we dont OOM.
-    ///
-    /// This is a [`parking_lot::Mutex`] to avoid priority inversion.
-    execution_guard: Mutex<()>,
+    scheduler: Arc<ExecutionScheduler>,
     /// The prover instance to use.
     prover: Arc<CpuProver>,
+    /// The memory available to in-progress uploads, shared by all connections.
+    upload_budget: Arc<UploadBudget>,
+    /// Programs that have already been set up, so repeated requests can skip setup.
+    program_cache: ProgramCache,
+    /// The report of the latest self-test, `None` if none has run.
+    ///
+    /// Once a self-test passes, its report is kept and the test is not run again.
+    self_test: Mutex<Option<SelfTestReport>>,
+}
+
+/// What the signature of an execution request commits to, besides the vkey and public values.
+struct SigningOptions {
+    /// Append the cycle count to the signed message.
+    sign_cycles: bool,
+    /// Append the [`InputCommitment`] to the signed message.
+    sign_inputs: bool,
+    /// Bind the signature to a verifier and request.
+    domain: Option<SignatureDomain>,
+}
+
+/// The program of an execution request.
+enum ProgramSource {
+    /// The ELF of the program, which may or may not be cached.
+    Elf(Vec<u8>),
+    /// The [`program_hash`] of a program that should be in the cache.
+    Cached([u8; 32]),
 }
 
 impl Server {
@@ -57,9 +146,25 @@ impl Server {
         );
 
         Self {
-            signing_key: Mutex::new(signing_key),
+            signing_keys: Mutex::new(SigningKeys::new(signing_key)),
+            sealer: args.enc_key_arn.clone().into_sealer(args.kms_proxy_port),
+            nsm: if args.mock_nsm {
+                Box::new(
+                    MockNsm::new(
+                        args.mock_nsm_root_cert.as_deref(),
+                        args.mock_nsm_ca_dir.as_deref(),
+                    )
+                    .expect("Failed to start the mock NSM"),
+                )
+            } else {
+                Box::new(NitroNsm)
+            },
+            pending_migration: Mutex::new(None),
+            upload_budget: UploadBudget::new(args.max_upload_memory),
+            program_cache: ProgramCache::new(args.program_cache_size),
+            scheduler: ExecutionScheduler::new(args.execution_memory_budget),
+            self_test: Mutex::new(None),
             args,
-            execution_guard: Mutex::new(()),
             prover: Arc::new(CpuProver::new()),
         }
     }
@@ -67,15 +172,18 @@ impl Server {
     pub async fn run(self) {
         let this = Arc::new(self);
 
-        let addr = VsockAddr::new(
-            this.args.cid.unwrap_or(VMADDR_CID_ANY),
-            sp1_tee_common::ENCLAVE_PORT as u32,
-        );
+        let addr = this
+            .args
+            .listen
+            .clone()
+            .unwrap_or_else(|| TransportAddr::vsock(this.args.cid.unwrap_or(VMADDR_CID_ANY)));
+
+        let listener = addr.bind().await.expect("Failed to bind listener");
 
-        let listener = VsockListener::bind(addr).expect("Failed to bind to vsock");
+        debug_print!("Listening on {}", addr);
 
         loop {
-            let (stream, _) = listener
+            let stream = listener
                 .accept()
                 .await
                 .expect("Failed to accept connection");
@@ -95,220 +203,832 @@ impl Server {
 
     /// Handles a connection from the host.
     ///
-    /// NOTE: unwraps are used here on recv as this is only ran in a spawned thread.
-    async fn handle_connection(self: Arc<Self>, stream: VsockStreamRaw) {
-        let mut stream = VsockStream::<EnclaveRequest, EnclaveResponse>::new(stream);
+    /// Each request is handled in its own task, and responses are sent as soon as they are ready,
+    /// tagged with the ID of the request they answer.
+    ///
+    /// The connection is dropped if the host disconnects or sends a malformed frame.
+    async fn handle_connection(self: Arc<Self>, stream: Box<dyn Transport>) {
+        let mut stream = VsockStream::<EnclaveRequest, EnclaveResponse>::from_boxed(stream)
+            .with_recv_limits(self.args.request_frame_limits())
+            .with_send_limits(self.args.response_frame_limits());
+
+        // Agree on the framing before reading any frame.
+        match stream.exchange_preamble().await {
+            Ok(PROTOCOL_VERSION) => {}
+            Ok(_protocol_version) => {
+                debug_print!(
+                    "Protocol version mismatch, host: {}, enclave: {}",
+                    _protocol_version,
+                    PROTOCOL_VERSION
+                );
+
+                return;
+            }
+            Err(_e) => {
+                debug_print!("Failed to exchange the protocol preamble: {}", _e);
+
+                return;
+            }
+        }
+
+        // The first message on every connection must be a handshake.
+        match stream.recv().await {
+            Ok((
+                id,
+                EnclaveRequest::Hello {
+                    protocol_version, ..
+                },
+            )) => {
+                // Always reply, so the host can report exactly what it is talking to.
+                if stream
+                    .send(id, EnclaveResponse::Hello(enclave_info()))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+
+                if protocol_version != PROTOCOL_VERSION {
+                    debug_print!(
+                        "Protocol version mismatch, host: {}, enclave: {}",
+                        protocol_version,
+                        PROTOCOL_VERSION
+                    );
+
+                    return;
+                }
+            }
+            Ok((id, message)) => {
+                let _ = stream
+                    .send(
+                        id,
+                        EnclaveResponse::Error(EnclaveError::InvalidRequest(format!(
+                            "Expected Hello as the first message, got {}",
+                            message.type_of()
+                        ))),
+                    )
+                    .await;
+
+                return;
+            }
+            Err(_e) => {
+                debug_print!("Failed to receive handshake: {}", _e);
+
+                return;
+            }
+        }
+
+        let (mut reader, mut writer) = stream.into_split();
+
+        // Uploads and in-flight requests are scoped to the connection.
+        let session = Arc::new(Session::new(self.upload_budget.clone()));
+
+        // Responses are funneled through a single task that owns the write half.
+        let (response_tx, mut response_rx) =
+            tokio::sync::mpsc::unbounded_channel::<(RequestId, EnclaveResponse)>();
+
+        tokio::task::spawn(async move {
+            while let Some((id, response)) = response_rx.recv().await {
+                if let Err(_e) = writer.send(id, response).await {
+                    debug_print!("Failed to send response: {}", _e);
+                    break;
+                }
+            }
+        });
 
         loop {
-            let message = stream.recv().await.unwrap();
+            let (id, message) = match reader.recv().await {
+                Ok(message) => message,
+                Err(CommunicationError::FrameTooLarge {
+                    id, size, limit, ..
+                }) => {
+                    // The frame was discarded, so the stream is still usable.
+                    debug_print!("Rejected frame of {} bytes, limit: {}", size, limit);
 
-            debug_print!("Received message: {:?}", message.type_of());
+                    let _ = response_tx.send((
+                        id,
+                        EnclaveResponse::Error(EnclaveError::RequestTooLarge { size, limit }),
+                    ));
 
-            match self.clone().handle_message(message, &mut stream).await {
-                ConnectionState::Continue => {}
-                ConnectionState::Close => {
-                    debug_print!("Connection closed.");
+                    continue;
+                }
+                Err(_e) => {
+                    debug_print!("Failed to receive message, closing connection: {}", _e);
                     break;
                 }
+            };
+
+            debug_print!("Received message {}: {:?}", id, message.type_of());
+
+            if let EnclaveRequest::CloseSession = message {
+                debug_print!("Connection closed.");
+                break;
             }
+
+            let cancel = session.start(id);
+
+            tokio::task::spawn({
+                let this = self.clone();
+                let response_tx = response_tx.clone();
+                let session = session.clone();
+
+                async move {
+                    let response = this.handle_message(message, &session, cancel).await;
+                    session.finish(id);
+
+                    // If the connection is gone, there is no one to send the response to.
+                    let _ = response_tx.send((id, response));
+                }
+            });
         }
+
+        // Stop any executions, the host is no longer waiting for them.
+        session.cancel_all();
     }
 
-    /// Handles a message from the host.
+    /// Handles a message from the host, returning the response to send back.
     ///
-    /// Returns false if the connection should be closed.
-    ///
-    /// NOTE: unwraps are used here on sends as this is only ran in a spawned thread.
+    /// Long running requests should stop early if `cancel` is set.
     async fn handle_message(
         self: Arc<Self>,
         message: EnclaveRequest,
-        stream: &mut VsockStream<EnclaveRequest, EnclaveResponse>,
-    ) -> ConnectionState {
+        session: &Session,
+        cancel: CancelFlag,
+    ) -> EnclaveResponse {
+        let uploads = &session.uploads;
+
         match message {
+            EnclaveRequest::Hello { .. } => EnclaveResponse::Hello(enclave_info()),
             #[cfg(feature = "debug-mode")]
             EnclaveRequest::Print(message) => {
                 debug_print!("{}", message);
 
-                let _ = stream.send(EnclaveResponse::Ack);
+                EnclaveResponse::Ack
             }
             #[cfg(not(feature = "debug-mode"))]
             EnclaveRequest::Print(_) => {
                 // Outside of debug mode the console cannot be accessed.
 
-                stream.send(EnclaveResponse::Ack).await.unwrap();
+                EnclaveResponse::Ack
             }
-            EnclaveRequest::GetPublicKey => {
-                let public_key = self.get_public_key();
+            EnclaveRequest::GetPublicKey => match self.check_ready() {
+                Ok(()) => EnclaveResponse::PublicKey(self.get_public_key()),
+                Err(e) => EnclaveResponse::Error(e),
+            },
+            EnclaveRequest::AttestSigningKey { nonce } => {
+                if let Err(e) = self.check_ready() {
+                    return EnclaveResponse::Error(e);
+                }
 
-                stream
-                    .send(EnclaveResponse::PublicKey(public_key))
-                    .await
-                    .unwrap();
-            }
-            EnclaveRequest::AttestSigningKey => {
-                match tokio::task::spawn_blocking(move || self.attest_signing_key()).await {
-                    Ok(response) => {
-                        stream.send(response).await.unwrap();
-                    }
-                    Err(e) => {
-                        stream
-                            .send(EnclaveResponse::Error(format!(
-                                "Join error when attesting signing key: {:?}",
-                                e
-                            )))
-                            .await
-                            .unwrap();
-                    }
+                match tokio::task::spawn_blocking(move || self.attest_signing_key(nonce)).await {
+                    Ok(response) => response,
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when attesting signing key: {:?}",
+                        e
+                    ))),
                 }
             }
             EnclaveRequest::Execute {
                 stdin,
                 program,
                 cycle_limit,
+                sign_cycles,
+                sign_inputs,
+                domain,
             } => {
-                match tokio::task::spawn_blocking(move || self.execute(stdin, program, cycle_limit))
-                    .await
+                match tokio::task::spawn_blocking(move || {
+                    self.execute(
+                        stdin,
+                        ProgramSource::Elf(program),
+                        cycle_limit,
+                        SigningOptions {
+                            sign_cycles,
+                            sign_inputs,
+                            domain,
+                        },
+                        &cancel,
+                    )
+                })
+                .await
                 {
-                    Ok(response) => {
-                        stream.send(response).await.unwrap();
-                    }
-                    Err(e) => {
-                        stream
-                            .send(EnclaveResponse::Error(format!(
-                                "Join error when executing program: {}",
+                    Ok(response) => response,
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when executing program: {}",
+                        e
+                    ))),
+                }
+            }
+            EnclaveRequest::ExecuteCached {
+                program_hash,
+                stdin,
+                cycle_limit,
+                sign_cycles,
+                sign_inputs,
+                domain,
+            } => {
+                match tokio::task::spawn_blocking(move || {
+                    self.execute(
+                        stdin,
+                        ProgramSource::Cached(program_hash),
+                        cycle_limit,
+                        SigningOptions {
+                            sign_cycles,
+                            sign_inputs,
+                            domain,
+                        },
+                        &cancel,
+                    )
+                })
+                .await
+                {
+                    Ok(response) => response,
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when executing program: {}",
+                        e
+                    ))),
+                }
+            }
+            EnclaveRequest::BeginUpload { kind, total_len } => {
+                match uploads.begin(kind, total_len) {
+                    Ok(upload_id) => EnclaveResponse::UploadStarted { upload_id },
+                    Err(e) => EnclaveResponse::Error(e.into()),
+                }
+            }
+            EnclaveRequest::UploadChunk {
+                upload_id,
+                offset,
+                data,
+            } => match uploads.chunk(upload_id, offset, &data) {
+                Ok(received) => EnclaveResponse::UploadProgress {
+                    upload_id,
+                    received,
+                },
+                Err(e) => EnclaveResponse::Error(e.into()),
+            },
+            EnclaveRequest::CommitUpload { upload_id, digest } => {
+                match uploads.commit(upload_id, digest) {
+                    Ok(digest) => EnclaveResponse::UploadCommitted { upload_id, digest },
+                    Err(e) => EnclaveResponse::Error(e.into()),
+                }
+            }
+            EnclaveRequest::AbortUpload { upload_id } => match uploads.abort(upload_id) {
+                Ok(()) => EnclaveResponse::Ack,
+                Err(e) => EnclaveResponse::Error(e.into()),
+            },
+            EnclaveRequest::ExecuteUploaded {
+                program,
+                stdin,
+                cycle_limit,
+                sign_cycles,
+                sign_inputs,
+                domain,
+            } => {
+                // Both uploads are consumed, even if the other one is invalid.
+                let program = uploads.take(program, UploadKind::Program);
+                let stdin = uploads.take(stdin, UploadKind::Stdin);
+
+                let (program, stdin) = match (program, stdin) {
+                    (Ok(program), Ok(stdin)) => (program, stdin),
+                    (Err(e), _) | (_, Err(e)) => return EnclaveResponse::Error(e.into()),
+                };
+
+                match tokio::task::spawn_blocking(move || {
+                    let stdin = match bincode::deserialize::<SP1Stdin>(&stdin.data) {
+                        Ok(stdin) => stdin,
+                        Err(e) => {
+                            return EnclaveResponse::Error(EnclaveError::InvalidRequest(format!(
+                                "Failed to decode uploaded stdin: {}",
                                 e
                             )))
-                            .await
-                            .unwrap();
-                    }
+                        }
+                    };
+
+                    self.execute(
+                        stdin,
+                        ProgramSource::Elf(program.data),
+                        cycle_limit,
+                        SigningOptions {
+                            sign_cycles,
+                            sign_inputs,
+                            domain,
+                        },
+                        &cancel,
+                    )
+                })
+                .await
+                {
+                    Ok(response) => response,
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when executing program: {}",
+                        e
+                    ))),
                 }
             }
-            EnclaveRequest::GetEncryptedSigningKey => {
-                stream
-                    .send(EnclaveResponse::Error("Not implemented".to_string()))
-                    .await
-                    .unwrap();
+            EnclaveRequest::GetEncryptedSigningKey { credentials } => {
+                match tokio::task::spawn_blocking(move || self.get_signing_key(credentials)).await {
+                    Ok(Ok(sealed_key)) => EnclaveResponse::EncryptedSigningKey(sealed_key),
+                    Ok(Err(e)) => EnclaveResponse::Error(e),
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when sealing signing key: {:?}",
+                        e
+                    ))),
+                }
+            }
+            EnclaveRequest::SetSigningKey {
+                sealed_key,
+                credentials,
+            } => {
+                match tokio::task::spawn_blocking(move || {
+                    self.set_signing_key(sealed_key, credentials)
+                })
+                .await
+                {
+                    Ok(Ok(public_key)) => EnclaveResponse::PublicKey(public_key),
+                    Ok(Err(e)) => EnclaveResponse::Error(e),
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when unsealing signing key: {:?}",
+                        e
+                    ))),
+                }
             }
-            EnclaveRequest::SetSigningKey(_) => {
-                stream
-                    .send(EnclaveResponse::Error("Not implemented".to_string()))
+            EnclaveRequest::BeginKeyMigration => {
+                match tokio::task::spawn_blocking(move || self.begin_key_migration()).await {
+                    Ok(Ok(attestation)) => EnclaveResponse::KeyMigrationRequest { attestation },
+                    Ok(Err(e)) => EnclaveResponse::Error(e),
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when beginning key migration: {:?}",
+                        e
+                    ))),
+                }
+            }
+            EnclaveRequest::ExportSigningKey { attestation } => {
+                match tokio::task::spawn_blocking(move || self.export_signing_key(attestation))
                     .await
-                    .unwrap();
+                {
+                    Ok(Ok((attestation, ciphertext))) => EnclaveResponse::ExportedSigningKey {
+                        attestation,
+                        ciphertext,
+                    },
+                    Ok(Err(e)) => EnclaveResponse::Error(e),
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when exporting signing key: {:?}",
+                        e
+                    ))),
+                }
             }
-            EnclaveRequest::CloseSession => {
-                return ConnectionState::Close;
+            EnclaveRequest::ImportSigningKey {
+                attestation,
+                ciphertext,
+            } => {
+                match tokio::task::spawn_blocking(move || {
+                    self.import_signing_key(attestation, ciphertext)
+                })
+                .await
+                {
+                    Ok(Ok(public_key)) => EnclaveResponse::PublicKey(public_key),
+                    Ok(Err(e)) => EnclaveResponse::Error(e),
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when importing signing key: {:?}",
+                        e
+                    ))),
+                }
             }
+            EnclaveRequest::RotateKey { grace_period_secs } => {
+                if let Err(e) = self.check_ready() {
+                    return EnclaveResponse::Error(e);
+                }
+
+                match tokio::task::spawn_blocking(move || {
+                    self.rotate_key(Duration::from_secs(grace_period_secs))
+                })
+                .await
+                {
+                    Ok(Ok(keys)) => EnclaveResponse::SigningKeys(keys),
+                    Ok(Err(e)) => EnclaveResponse::Error(e),
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when rotating signing key: {:?}",
+                        e
+                    ))),
+                }
+            }
+            EnclaveRequest::GetSigningKeys => {
+                if let Err(e) = self.check_ready() {
+                    return EnclaveResponse::Error(e);
+                }
+
+                match tokio::task::spawn_blocking(move || self.get_signing_keys()).await {
+                    Ok(Ok(keys)) => EnclaveResponse::SigningKeys(keys),
+                    Ok(Err(e)) => EnclaveResponse::Error(e),
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when getting signing keys: {:?}",
+                        e
+                    ))),
+                }
+            }
+            EnclaveRequest::GetSchedulerStatus => {
+                EnclaveResponse::SchedulerStatus(self.scheduler.status())
+            }
+            EnclaveRequest::GetConfig => EnclaveResponse::Config(self.args.config()),
+            EnclaveRequest::VerifyProof {
+                proof,
+                vkey,
+                domain,
+            } => {
+                match tokio::task::spawn_blocking(move || {
+                    self.verify_proof(*proof, vkey, domain, &cancel)
+                })
+                .await
+                {
+                    Ok(response) => response,
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when verifying proof: {}",
+                        e
+                    ))),
+                }
+            }
+            EnclaveRequest::SelfTest => {
+                match tokio::task::spawn_blocking(move || self.self_test(&cancel)).await {
+                    Ok(report) => EnclaveResponse::SelfTest(report),
+                    Err(e) => EnclaveResponse::Error(EnclaveError::Internal(format!(
+                        "Join error when running self-test: {}",
+                        e
+                    ))),
+                }
+            }
+            EnclaveRequest::Cancel { request_id } => {
+                if session.cancel(request_id) {
+                    debug_print!("Cancelled request {}", request_id);
+                }
+
+                EnclaveResponse::Ack
+            }
+            // Handled by the connection loop.
+            EnclaveRequest::CloseSession => EnclaveResponse::Ack,
         }
+    }
 
-        ConnectionState::Continue
+    /// The sealer, or an error if sealing is disabled.
+    fn sealer(&self) -> Result<&dyn KeySealer, EnclaveError> {
+        self.sealer.as_deref().ok_or_else(|| {
+            EnclaveError::NotImplemented(
+                "Key sealing is disabled, the enclave was started with `--enc-key-arn none`"
+                    .to_string(),
+            )
+        })
     }
 
-    /// Decrypts the signing key (using KMS) and sets it on the server.
-    #[allow(unused)]
-    fn set_signing_key(&self, ciphertext: Vec<u8>) {
-        todo!()
+    /// Unseals the signing keys and sets them on the server, returning the active public key.
+    ///
+    /// The host restores the keys on every connection, so they are only replaced if the enclave
+    /// does not already hold the sealed active key, i.e. it restarted since they were sealed.
+    /// Otherwise a reconnect would cancel, or cut short, a rotation in progress.
+    fn set_signing_key(
+        &self,
+        sealed_key: Vec<u8>,
+        credentials: Option<AwsCredentials>,
+    ) -> Result<k256::EncodedPoint, EnclaveError> {
+        if sealed_key.is_empty() {
+            return Err(EnclaveError::InvalidRequest("Empty sealed key".to_string()));
+        }
+
+        let key = self.sealer()?.unseal(&sealed_key, credentials.as_ref())?;
+
+        let mut restored = SigningKeys::from_sealed(&key).ok_or_else(|| {
+            EnclaveError::InvalidRequest("Unsealed key is not a valid signing key".to_string())
+        })?;
+
+        {
+            let mut keys = self.signing_keys.lock();
+
+            if !keys.contains(restored.active()) {
+                debug_print!(
+                    "Restored signing key with public key: {:?}",
+                    restored.active().verifying_key()
+                );
+
+                *keys = restored;
+            }
+        }
+
+        Ok(self.get_public_key())
     }
 
-    /// Encrypts the servers signing key (using KMS) and sends it to the host.
-    #[allow(unused)]
-    fn get_signing_key(&self) -> Vec<u8> {
-        todo!()
+    /// Seals the servers signing keys, so the host can restore them after a restart.
+    ///
+    /// A rotation in progress is sealed with its activation time, so it resumes once restored.
+    fn get_signing_key(
+        &self,
+        credentials: Option<AwsCredentials>,
+    ) -> Result<Vec<u8>, EnclaveError> {
+        let sealer = self.sealer()?;
+
+        // Dont hold the lock while talking to KMS.
+        let keys = self.signing_keys.lock().to_sealed();
+
+        Ok(sealer.seal(&keys, credentials.as_ref())?)
     }
 
     fn get_public_key(&self) -> k256::EncodedPoint {
-        self.signing_key
+        self.signing_keys
             .lock()
+            .active()
             .verifying_key()
             .to_encoded_point(false)
     }
 
-    /// Attests to the signing key.
-    fn attest_signing_key(&self) -> EnclaveResponse {
-        let fd = nsm_init();
+    /// Returns an error unless a self-test has passed, so the signing key can be advertised.
+    fn check_ready(&self) -> Result<(), EnclaveError> {
+        match self.self_test.lock().as_ref() {
+            Some(report) if report.passed => Ok(()),
+            Some(report) => Err(EnclaveError::NotReady(format!(
+                "The self-test failed: {}",
+                report.error.as_deref().unwrap_or("unknown error")
+            ))),
+            None => Err(EnclaveError::NotReady(
+                "The self-test has not run".to_string(),
+            )),
+        }
+    }
 
-        if fd < 0 {
-            return EnclaveResponse::Error("Failed to initialize NSM".to_string());
+    /// Attests to the signing key, binding the `nonce` if one is given.
+    fn attest_signing_key(&self, nonce: Option<Vec<u8>>) -> EnclaveResponse {
+        if let Some(nonce) = nonce.as_ref().filter(|nonce| nonce.len() > MAX_NONCE_LEN) {
+            return EnclaveResponse::Error(EnclaveError::InvalidRequest(format!(
+                "Nonce is too long, found {} bytes, the maximum is {}",
+                nonce.len(),
+                MAX_NONCE_LEN
+            )));
         }
 
+        match self.attest_public_key(&self.get_public_key(), nonce) {
+            Ok(document) => EnclaveResponse::SigningKeyAttestation(document),
+            Err(e) => EnclaveResponse::Error(e),
+        }
+    }
+
+    /// Requests an attestation document binding a public key, and optionally a nonce.
+    fn attest_public_key(
+        &self,
+        public_key: &k256::EncodedPoint,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, EnclaveError> {
         // SEC1 encoded public key.
         //
         // This is of the form [0x04 || X || Y]
-        let public_key_bytes = self.get_public_key().to_bytes().to_vec();
+        let public_key_bytes = public_key.to_bytes().to_vec();
 
-        let request = Request::Attestation {
-            user_data: Some(SP1_TEE_VERSION.to_le_bytes().to_vec().into()),
-            nonce: None,
-            public_key: Some(public_key_bytes.into()),
+        self.nsm.attest(
+            Some(user_data(&self.args.config()).encode()),
+            nonce,
+            Some(public_key_bytes),
+        )
+    }
+
+    /// Generates the next signing key, which becomes active after `grace_period`.
+    fn rotate_key(&self, grace_period: Duration) -> Result<AttestedKeys, EnclaveError> {
+        self.signing_keys.lock().rotate(grace_period)?;
+
+        debug_print!("Rotated signing key, grace period: {:?}", grace_period);
+
+        self.get_signing_keys()
+    }
+
+    /// Returns the signing keys, each with an attestation.
+    fn get_signing_keys(&self) -> Result<AttestedKeys, EnclaveError> {
+        // Dont hold the lock while talking to the NSM.
+        let (active, next) = {
+            let mut keys = self.signing_keys.lock();
+
+            let active = keys.active().verifying_key().to_encoded_point(false);
+            let next = keys
+                .next_key()
+                .map(|(key, remaining)| (key.verifying_key().to_encoded_point(false), remaining));
+
+            (active, next)
         };
 
-        let response = nsm_process_request(fd, request);
+        let next = match next {
+            Some((public_key, remaining)) => Some(NextKey {
+                key: AttestedKey {
+                    attestation: self.attest_public_key(&public_key, None)?,
+                    public_key,
+                },
+                activates_in_secs: remaining.as_secs(),
+            }),
+            None => None,
+        };
 
-        nsm_exit(fd);
+        Ok(AttestedKeys {
+            active: AttestedKey {
+                attestation: self.attest_public_key(&active, None)?,
+                public_key: active,
+            },
+            next,
+        })
+    }
 
-        match response {
-            Response::Attestation { document, .. } => {
-                EnclaveResponse::SigningKeyAttestation(document)
-            }
-            _ => EnclaveResponse::Error(
-                "Unexpected response type from NSM, this is a bug.".to_string(),
-            ),
-        }
+    /// Starts migrating the signing key from a donor enclave, returning the attestation to send to it.
+    ///
+    /// Any migration already in progress is abandoned.
+    fn begin_key_migration(&self) -> Result<Vec<u8>, EnclaveError> {
+        let (pending, attestation) =
+            migration::begin(self.nsm.as_ref(), &user_data(&self.args.config()))?;
+
+        *self.pending_migration.lock() = Some(pending);
+
+        Ok(attestation)
     }
 
-    /// Executes a program with the given stdin and program.
+    /// Encrypts the signing keys to the requester of `attestation`, as a donor.
     ///
-    /// Sends a signature over the public values (and the vkey) to the host.
-    fn execute(&self, stdin: SP1Stdin, program: Vec<u8>, cycle_limit: u64) -> EnclaveResponse {
-        if cycle_limit > MAX_ALLOWED_CYCLES {
-            return EnclaveResponse::Error(format!(
-                "Cycle limit is too high: {}, max: {}",
-                cycle_limit, MAX_ALLOWED_CYCLES
-            ));
+    /// If a rotation is in progress, it is exported with the keys, see [`SigningKeys::to_sealed`],
+    /// so the requester keeps signing with the active key until the next key's grace period is over.
+    fn export_signing_key(&self, attestation: Vec<u8>) -> Result<(Vec<u8>, Vec<u8>), EnclaveError> {
+        let keys = self.signing_keys.lock().to_sealed();
+
+        debug_print!("Exporting signing keys to another enclave");
+
+        Ok(migration::export(
+            self.nsm.as_ref(),
+            &user_data(&self.args.config()),
+            &keys,
+            &attestation,
+        )?)
+    }
+
+    /// Finishes a migration, setting the donor's signing keys on the server and returning the active public key.
+    ///
+    /// A rotation in progress on the donor carries over, with the time left in its grace period.
+    fn import_signing_key(
+        &self,
+        attestation: Vec<u8>,
+        ciphertext: Vec<u8>,
+    ) -> Result<k256::EncodedPoint, EnclaveError> {
+        let pending = self
+            .pending_migration
+            .lock()
+            .take()
+            .ok_or(MigrationError::NotStarted)?;
+
+        let keys = migration::import(self.nsm.as_ref(), pending, &attestation, &ciphertext)?;
+
+        let keys = SigningKeys::from_sealed(&keys).ok_or_else(|| {
+            EnclaveError::InvalidRequest("Migrated keys are not valid signing keys".to_string())
+        })?;
+
+        *self.signing_keys.lock() = keys;
+
+        debug_print!(
+            "Migrated signing keys, active public key: {:?}",
+            self.get_public_key()
+        );
+
+        Ok(self.get_public_key())
+    }
+
+    /// Executes a program with the given stdin and program, in a worker process.
+    ///
+    /// Sends a signature over the public values (and the vkey) to the host,
+    /// along with anything else the [`SigningOptions`] commit to.
+    ///
+    /// If the program deterministically fails, a signed [`FailureReceipt`] is sent instead.
+    ///
+    /// Stops early with [`EnclaveError::Cancelled`] if `cancel` is set, while waiting or executing.
+    fn execute(
+        &self,
+        stdin: SP1Stdin,
+        program: ProgramSource,
+        cycle_limit: u64,
+        signing: SigningOptions,
+        cancel: &CancelFlag,
+    ) -> EnclaveResponse {
+        let SigningOptions {
+            sign_cycles,
+            sign_inputs,
+            domain,
+        } = signing;
+
+        let config = self.args.config();
+
+        // Reject expired domains up front, the signature would be useless.
+        if let Err(e) = check_domain(domain.as_ref()) {
+            return EnclaveResponse::Error(e);
         }
 
-        // Take the guard to ensure only one execution can be running at a time.
-        let _guard = self.execution_guard.lock();
+        if cycle_limit > config.max_cycles {
+            return EnclaveResponse::Error(EnclaveError::CycleLimitTooHigh {
+                requested: cycle_limit,
+                max: config.max_cycles,
+            });
+        }
 
-        debug_print!("Setup start");
-        let (_, vk) = self.prover.setup(&program);
-        debug_print!("Setup complete");
+        // Look up cached programs before waiting, so a miss is reported immediately.
+        let program = match program {
+            ProgramSource::Elf(elf) => {
+                let program_hash = program_hash(&elf);
 
-        // Defaults `true` for deferred proof verification.
-        match self
-            .prover
-            .execute(&program, &stdin)
-            .cycle_limit(cycle_limit)
-            .run()
-        {
-            Ok((public_values, _)) => {
-                debug_print!("Execute complete");
+                self.program_cache
+                    .get(&program_hash)
+                    .ok_or((program_hash, elf))
+            }
+            ProgramSource::Cached(program_hash) => match self.program_cache.get(&program_hash) {
+                Some(program) => Ok(program),
+                None => {
+                    return EnclaveResponse::Error(EnclaveError::ProgramNotCached { program_hash })
+                }
+            },
+        };
+
+        let elf = match &program {
+            Ok(program) => &program.elf,
+            Err((_, elf)) => elf,
+        };
+        let stdin_len = stdin.buffer.iter().map(Vec::len).sum();
+
+        // Wait for enough memory to be available for the execution, and the worker running it.
+        let memory = scheduler::estimate_memory(elf.len(), stdin_len, cycle_limit)
+            .saturating_add(self.args.worker_memory_overhead);
+
+        let permit = match self.scheduler.admit(memory, cancel) {
+            Ok(permit) => permit,
+            Err(e) => return EnclaveResponse::Error(e),
+        };
+
+        // The worker may only use the memory reserved for it, so the workers together never exceed the budget.
+        let limits = WorkerLimits {
+            memory: permit.memory(),
+            time: Duration::from_secs(config.max_execution_secs),
+        };
+
+        let response =
+            match worker::execute(elf, &stdin, cycle_limit, program.is_err(), limits, cancel) {
+                Ok(response) => response,
+                Err(e) => {
+                    debug_print!("Execution worker failed: {}", e);
+
+                    return EnclaveResponse::Error(e);
+                }
+            };
+
+        // The worker has exited, so its memory is free.
+        drop(permit);
+
+        let program = match (program, response.vk) {
+            (Ok(program), _) => program,
+            (Err((program_hash, elf)), Some(vk)) => {
+                let program = Arc::new(CachedProgram { elf, vk });
+                self.program_cache.insert(program_hash, program.clone());
+
+                program
+            }
+            (Err(_), None) => {
+                return EnclaveResponse::Error(EnclaveError::Internal(
+                    "The worker did not set up the program, this is a bug.".to_string(),
+                ))
+            }
+        };
 
-                // Hash the public values.
-                let public_values_hash =
-                    sha3::Keccak256::new_with_prefix(public_values.as_slice()).finalize();
+        match response.result {
+            Ok(execution) => {
+                debug_print!("Execute complete");
 
-                let vkey_raw = vk.bytes32_raw();
+                let sign_start = Instant::now();
+                let public_values = execution.public_values;
 
-                // Hash the version bytes.
-                let version_bytes = SP1_TEE_VERSION.to_le_bytes().to_vec();
-                let version_bytes_hash =
-                    sha3::Keccak256::new_with_prefix(version_bytes.as_slice()).finalize();
+                let vkey_raw = program.vk.bytes32_raw();
 
-                let to_sign = [
-                    version_bytes_hash.to_vec(),
-                    vkey_raw.to_vec(),
-                    public_values_hash.to_vec(),
-                ]
-                .concat();
+                let inputs = sign_inputs.then(|| InputCommitment::new(&program.elf, &stdin));
 
-                let hasher = sha3::Keccak256::new_with_prefix(to_sign.as_slice());
+                let message = SignedMessage {
+                    tee_version: SP1_TEE_VERSION,
+                    vkey: &vkey_raw,
+                    public_values: public_values.as_slice(),
+                    cycles: sign_cycles.then_some(execution.cycles),
+                    domain: domain.as_ref(),
+                    inputs: inputs.as_ref(),
+                };
 
-                let Ok((signature, recovery_id)) =
-                    self.signing_key.lock().sign_digest_recoverable(hasher)
+                let Ok((signature, recovery_id)) = self
+                    .signing_keys
+                    .lock()
+                    .active()
+                    .sign_digest_recoverable(message.digest())
                 else {
-                    return EnclaveResponse::Error(
+                    return EnclaveResponse::Error(EnclaveError::Internal(
                         "Failed to sign public values, this is a bug.".to_string(),
-                    );
+                    ));
+                };
+
+                let report = ExecutionReport {
+                    cycles: execution.cycles,
+                    cycles_signed: sign_cycles,
+                    domain,
+                    inputs,
+                    syscall_counts: execution.syscall_counts,
+                    setup_time_ms: response.setup_time.as_millis() as u64,
+                    execute_time_ms: response.execute_time.as_millis() as u64,
+                    sign_time_ms: sign_start.elapsed().as_millis() as u64,
+                    peak_memory_bytes: response.peak_memory_bytes,
                 };
 
                 EnclaveResponse::SignedPublicValues {
@@ -316,9 +1036,339 @@ impl Server {
                     public_values: public_values.to_vec(),
                     signature,
                     recovery_id: recovery_id.into(),
+                    report,
                 }
             }
-            Err(e) => EnclaveResponse::Error(format!("Failed to execute program: {:?}", e)),
+            Err(failure) => {
+                debug_print!("Execution failed: {}", failure.error);
+
+                let Some(kind) = FailureKind::of(&failure.error) else {
+                    return EnclaveResponse::Error(failure.error);
+                };
+
+                let receipt = FailureReceipt {
+                    tee_version: SP1_TEE_VERSION,
+                    kind,
+                    vkey: program.vk.bytes32_raw(),
+                    stdin_hash: stdin_hash(&stdin),
+                    cycles: failure.cycles,
+                    cycle_limit,
+                    domain,
+                };
+
+                let Ok((signature, recovery_id)) = self
+                    .signing_keys
+                    .lock()
+                    .active()
+                    .sign_digest_recoverable(receipt.digest())
+                else {
+                    return EnclaveResponse::Error(EnclaveError::Internal(
+                        "Failed to sign failure receipt, this is a bug.".to_string(),
+                    ));
+                };
+
+                EnclaveResponse::SignedFailure {
+                    error: failure.error,
+                    receipt: SignedFailureReceipt {
+                        receipt,
+                        signature,
+                        recovery_id: recovery_id.into(),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Verifies a proof, signing a [`ProofVerdict`] if it is valid.
+    ///
+    /// Stops early with [`EnclaveError::Cancelled`] if `cancel` is set while waiting,
+    /// verification itself cannot be interrupted.
+    fn verify_proof(
+        &self,
+        proof: SP1ProofWithPublicValues,
+        vk: SP1VerifyingKey,
+        domain: Option<SignatureDomain>,
+        cancel: &CancelFlag,
+    ) -> EnclaveResponse {
+        if let Err(e) = check_domain(domain.as_ref()) {
+            return EnclaveResponse::Error(e);
+        }
+
+        let proof_len = bincode::serialized_size(&proof).unwrap_or(u64::MAX);
+
+        // Verifying a compressed proof allocates, so it shares the memory budget with executions.
+        let _permit = match self
+            .scheduler
+            .admit(scheduler::estimate_verify_memory(proof_len), cancel)
+        {
+            Ok(permit) => permit,
+            Err(e) => return EnclaveResponse::Error(e),
+        };
+
+        let kind = match verifier::verify(&self.prover, &proof, &vk) {
+            Ok(kind) => kind,
+            Err(e) => {
+                debug_print!("Proof verification failed: {}", e);
+
+                return EnclaveResponse::Error(e);
+            }
+        };
+
+        let verdict = ProofVerdict {
+            tee_version: SP1_TEE_VERSION,
+            kind,
+            vkey: vk.bytes32_raw(),
+            public_values: proof.public_values.to_vec(),
+            domain,
+        };
+
+        let Ok((signature, recovery_id)) = self
+            .signing_keys
+            .lock()
+            .active()
+            .sign_digest_recoverable(verdict.digest())
+        else {
+            return EnclaveResponse::Error(EnclaveError::Internal(
+                "Failed to sign proof verdict, this is a bug.".to_string(),
+            ));
+        };
+
+        EnclaveResponse::ProofVerified(SignedProofVerdict {
+            verdict,
+            signature,
+            recovery_id: recovery_id.into(),
+        })
+    }
+
+    /// Runs the known-answer test, see [`selftest`], returning the report of a previous pass if there is one.
+    ///
+    /// The fixture is executed like any other request, so it waits for memory and can be cancelled.
+    fn self_test(&self, cancel: &CancelFlag) -> SelfTestReport {
+        if let Some(report) = self.self_test.lock().clone().filter(|report| report.passed) {
+            return report;
         }
+
+        let start = Instant::now();
+        let mut report = SelfTestReport::default();
+
+        let response = self.execute(
+            selftest::stdin(),
+            ProgramSource::Elf(selftest::FIXTURE_ELF.to_vec()),
+            selftest::FIXTURE_CYCLE_LIMIT.min(self.args.config().max_cycles),
+            SigningOptions {
+                sign_cycles: false,
+                sign_inputs: false,
+                domain: None,
+            },
+            cancel,
+        );
+
+        let result = match response {
+            EnclaveResponse::SignedPublicValues {
+                vkey,
+                public_values,
+                signature,
+                recovery_id,
+                report: execution,
+            } => {
+                report.setup_time_ms = execution.setup_time_ms;
+                report.execute_time_ms = execution.execute_time_ms;
+
+                let check_start = Instant::now();
+
+                let message = SignedMessage {
+                    tee_version: SP1_TEE_VERSION,
+                    vkey: &vkey,
+                    public_values: &public_values,
+                    cycles: None,
+                    domain: None,
+                    inputs: None,
+                };
+
+                let signer = *self.signing_keys.lock().active().verifying_key();
+
+                let result = selftest::check_output(&vkey, &public_values).and_then(|()| {
+                    selftest::check_signature(&message, &signature, recovery_id, &signer)
+                });
+
+                report.sign_time_ms =
+                    execution.sign_time_ms + check_start.elapsed().as_millis() as u64;
+
+                result
+            }
+            EnclaveResponse::Error(e) => Err(e.to_string()),
+            response => Err(format!(
+                "Unexpected response to the fixture: {}",
+                response.type_of()
+            )),
+        };
+
+        report.passed = result.is_ok();
+        report.error = result.err();
+        report.total_time_ms = start.elapsed().as_millis() as u64;
+        report.completed_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        debug_print!("Self-test finished: {:?}", report);
+
+        // A self-test that ran alongside this one may have passed, which is never undone.
+        let mut latest = self.self_test.lock();
+        if !latest.as_ref().is_some_and(|latest| latest.passed) {
+            *latest = Some(report.clone());
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    /// A server that seals its keys to a file in the temp directory, and the path of the file.
+    fn server(name: &str) -> (Server, std::path::PathBuf) {
+        let path =
+            std::env::temp_dir().join(format!("sp1-tee-{}-{}.key", name, std::process::id()));
+
+        let server = Server::new(EnclaveArgs::parse_from([
+            "sp1-tee-enclave",
+            "--enc-key-arn",
+            &format!("file://{}", path.display()),
+            "--mock-nsm",
+        ]));
+
+        (server, path)
+    }
+
+    #[test]
+    fn reconnecting_keeps_a_rotation_in_progress() {
+        let (server, path) = server("rotation");
+        let active = server.get_public_key();
+
+        let next = server
+            .rotate_key(Duration::from_secs(3600))
+            .unwrap()
+            .next
+            .unwrap()
+            .key
+            .public_key;
+        let sealed = server.get_signing_key(None).unwrap();
+
+        // The host restores the sealed keys when it reconnects.
+        assert_eq!(server.set_signing_key(sealed, None).unwrap(), active);
+        assert_eq!(server.get_public_key(), active);
+
+        let keys = server.get_signing_keys().unwrap();
+        assert_eq!(keys.active.public_key, active);
+        assert_eq!(keys.next.unwrap().key.public_key, next);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn restarting_restores_a_rotation_in_progress() {
+        let (server, path) = server("restart");
+        let active = server.get_public_key();
+
+        server.rotate_key(Duration::from_secs(3600)).unwrap();
+        let next = server.signing_keys.lock().next_key().unwrap().0.clone();
+        let sealed = server.get_signing_key(None).unwrap();
+
+        // A restarted enclave has a fresh key.
+        server
+            .signing_keys
+            .lock()
+            .set(SigningKey::random(&mut OsRng));
+
+        assert_eq!(server.set_signing_key(sealed, None).unwrap(), active);
+        assert!(server.signing_keys.lock().contains(&next));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn migration_keeps_a_rotation_in_progress() {
+        let (server, _) = server("migration");
+        let active = server.get_public_key();
+
+        let next = server
+            .rotate_key(Duration::from_secs(3600))
+            .unwrap()
+            .next
+            .unwrap()
+            .key
+            .public_key;
+
+        // The enclave donates its keys to itself, replacing them in between.
+        let request = server.begin_key_migration().unwrap();
+        let (response, ciphertext) = server.export_signing_key(request).unwrap();
+
+        server
+            .signing_keys
+            .lock()
+            .set(SigningKey::random(&mut OsRng));
+
+        assert_eq!(
+            server.import_signing_key(response, ciphertext).unwrap(),
+            active
+        );
+
+        let keys = server.get_signing_keys().unwrap();
+        let migrated = keys.next.unwrap();
+        assert_eq!(keys.active.public_key, active);
+        assert_eq!(migrated.key.public_key, next);
+        assert!(migrated.activates_in_secs > 3500);
+    }
+
+    #[test]
+    fn rejects_grace_periods_out_of_range() {
+        let (server, _) = server("grace");
+
+        assert!(matches!(
+            server.rotate_key(Duration::from_secs(u64::MAX)),
+            Err(EnclaveError::InvalidRequest(_))
+        ));
+        assert!(server.get_signing_keys().unwrap().next.is_none());
+    }
+
+    #[test]
+    fn rejects_empty_sealed_keys() {
+        let (server, _) = server("empty");
+        let active = server.get_public_key();
+
+        assert!(matches!(
+            server.set_signing_key(Vec::new(), None),
+            Err(EnclaveError::InvalidRequest(_))
+        ));
+        assert_eq!(server.get_public_key(), active);
+    }
+
+    #[test]
+    fn rejects_expired_domains() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let domain = |expires_at| SignatureDomain {
+            chain_id: 1,
+            verifier: [0; 20],
+            request_id: [0; 32],
+            expires_at,
+        };
+
+        assert!(check_domain(None).is_ok());
+        assert!(check_domain(Some(&domain(now + 3600))).is_ok());
+        assert!(matches!(
+            check_domain(Some(&domain(now))),
+            Err(EnclaveError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            check_domain(Some(&domain(now - 1))),
+            Err(EnclaveError::InvalidRequest(_))
+        ));
     }
 }
@@ -0,0 +1,57 @@
+use crate::executor::CancelFlag;
+use crate::upload::{UploadBudget, Uploads};
+use parking_lot::Mutex;
+use sp1_tee_common::RequestId;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The state of a single connection from the host.
+///
+/// Dropped when the connection closes, releasing any uploads.
+pub struct Session {
+    /// The uploads started on this connection.
+    pub uploads: Uploads,
+    /// The requests currently being handled, by ID.
+    in_flight: Mutex<HashMap<RequestId, CancelFlag>>,
+}
+
+impl Session {
+    pub fn new(upload_budget: Arc<UploadBudget>) -> Self {
+        Self {
+            uploads: Uploads::new(upload_budget),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Track a request, returning the flag that is set if the host cancels it.
+    pub fn start(&self, id: RequestId) -> CancelFlag {
+        let flag = CancelFlag::default();
+        self.in_flight.lock().insert(id, flag.clone());
+
+        flag
+    }
+
+    /// Stop tracking a request, once its response has been sent.
+    pub fn finish(&self, id: RequestId) {
+        self.in_flight.lock().remove(&id);
+    }
+
+    /// Cancel a request, returns false if the request already finished.
+    pub fn cancel(&self, id: RequestId) -> bool {
+        match self.in_flight.lock().get(&id) {
+            Some(flag) => {
+                flag.cancel();
+
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancel every in-flight request, as there is no one to send the responses to.
+    pub fn cancel_all(&self) {
+        for flag in self.in_flight.lock().values() {
+            flag.cancel();
+        }
+    }
+}
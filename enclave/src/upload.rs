@@ -0,0 +1,436 @@
+use parking_lot::Mutex;
+use sp1_tee_common::{EnclaveError, UploadHasher, UploadId, UploadKind};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Limits the total memory reserved by in-progress uploads, across all connections.
+pub struct UploadBudget {
+    /// The maximum number of bytes that can be reserved at once.
+    max: u64,
+    /// The number of bytes currently reserved.
+    reserved: AtomicU64,
+}
+
+impl UploadBudget {
+    pub fn new(max: u64) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            reserved: AtomicU64::new(0),
+        })
+    }
+
+    /// Reserve `bytes` from the budget, returns `None` if there is not enough left.
+    ///
+    /// The bytes are released when the [`Reservation`] is dropped.
+    fn reserve(self: &Arc<Self>, bytes: u64) -> Option<Reservation> {
+        self.reserved
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |reserved| {
+                reserved
+                    .checked_add(bytes)
+                    .filter(|total| *total <= self.max)
+            })
+            .ok()?;
+
+        Some(Reservation {
+            budget: self.clone(),
+            bytes,
+        })
+    }
+
+    /// The number of bytes that can still be reserved.
+    fn available(&self) -> u64 {
+        self.max
+            .saturating_sub(self.reserved.load(Ordering::Acquire))
+    }
+}
+
+/// Bytes reserved from an [`UploadBudget`], released on drop.
+pub struct Reservation {
+    budget: Arc<UploadBudget>,
+    bytes: u64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.reserved.fetch_sub(self.bytes, Ordering::AcqRel);
+    }
+}
+
+/// The uploads started on a single connection.
+///
+/// Any uploads left over when the connection closes are dropped with it.
+pub struct Uploads {
+    budget: Arc<UploadBudget>,
+    next_id: AtomicU64,
+    uploads: Mutex<HashMap<UploadId, Upload>>,
+}
+
+struct Upload {
+    kind: UploadKind,
+    total_len: u64,
+    data: Vec<u8>,
+    hasher: UploadHasher,
+    /// Set once the upload is committed, no more chunks can be added after this.
+    digest: Option<[u8; 32]>,
+    reservation: Reservation,
+}
+
+/// A committed upload, taken out of [`Uploads`] to be used.
+pub struct CommittedUpload {
+    pub data: Vec<u8>,
+    pub digest: [u8; 32],
+    /// The memory is still accounted for until this is dropped.
+    _reservation: Reservation,
+}
+
+impl Uploads {
+    pub fn new(budget: Arc<UploadBudget>) -> Self {
+        Self {
+            budget,
+            next_id: AtomicU64::new(0),
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start a new upload, reserving `total_len` bytes of memory for it.
+    pub fn begin(&self, kind: UploadKind, total_len: u64) -> Result<UploadId, UploadError> {
+        let reservation =
+            self.budget
+                .reserve(total_len)
+                .ok_or_else(|| UploadError::BudgetExceeded {
+                    requested: total_len,
+                    available: self.budget.available(),
+                })?;
+
+        let capacity = usize::try_from(total_len).map_err(|_| UploadError::OutOfMemory)?;
+        let mut data = Vec::new();
+        data.try_reserve_exact(capacity)
+            .map_err(|_| UploadError::OutOfMemory)?;
+
+        let upload_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        self.uploads.lock().insert(
+            upload_id,
+            Upload {
+                kind,
+                total_len,
+                data,
+                hasher: UploadHasher::new(),
+                digest: None,
+                reservation,
+            },
+        );
+
+        Ok(upload_id)
+    }
+
+    /// Append a chunk to an upload, returning the number of bytes received so far.
+    pub fn chunk(&self, upload_id: UploadId, offset: u64, data: &[u8]) -> Result<u64, UploadError> {
+        let mut uploads = self.uploads.lock();
+        let upload = uploads
+            .get_mut(&upload_id)
+            .ok_or(UploadError::UnknownUpload(upload_id))?;
+
+        if upload.digest.is_some() {
+            return Err(UploadError::AlreadyCommitted(upload_id));
+        }
+
+        let received = upload.data.len() as u64;
+        if offset != received {
+            return Err(UploadError::UnexpectedOffset {
+                expected: received,
+                found: offset,
+            });
+        }
+
+        if received + data.len() as u64 > upload.total_len {
+            return Err(UploadError::Overflow {
+                total_len: upload.total_len,
+            });
+        }
+
+        upload.data.extend_from_slice(data);
+        upload.hasher.update(data);
+
+        Ok(upload.data.len() as u64)
+    }
+
+    /// Commit an upload, checking every byte was received and the digest matches.
+    ///
+    /// A failed commit discards the upload.
+    pub fn commit(&self, upload_id: UploadId, digest: [u8; 32]) -> Result<[u8; 32], UploadError> {
+        let mut uploads = self.uploads.lock();
+        let upload = uploads
+            .get_mut(&upload_id)
+            .ok_or(UploadError::UnknownUpload(upload_id))?;
+
+        if upload.digest.is_some() {
+            return Err(UploadError::AlreadyCommitted(upload_id));
+        }
+
+        let received = upload.data.len() as u64;
+        if received != upload.total_len {
+            let total_len = upload.total_len;
+            uploads.remove(&upload_id);
+
+            return Err(UploadError::Incomplete {
+                received,
+                total_len,
+            });
+        }
+
+        let actual = std::mem::take(&mut upload.hasher).finalize();
+        if actual != digest {
+            uploads.remove(&upload_id);
+
+            return Err(UploadError::DigestMismatch);
+        }
+
+        upload.digest = Some(actual);
+
+        Ok(actual)
+    }
+
+    /// Discard an upload, releasing its memory.
+    pub fn abort(&self, upload_id: UploadId) -> Result<(), UploadError> {
+        self.uploads
+            .lock()
+            .remove(&upload_id)
+            .map(|_| ())
+            .ok_or(UploadError::UnknownUpload(upload_id))
+    }
+
+    /// Take a committed upload of the given kind, so it can be used.
+    pub fn take(
+        &self,
+        upload_id: UploadId,
+        kind: UploadKind,
+    ) -> Result<CommittedUpload, UploadError> {
+        let mut uploads = self.uploads.lock();
+        let upload = uploads
+            .get(&upload_id)
+            .ok_or(UploadError::UnknownUpload(upload_id))?;
+
+        if upload.kind != kind {
+            return Err(UploadError::WrongKind {
+                expected: kind,
+                found: upload.kind,
+            });
+        }
+
+        let Some(digest) = upload.digest else {
+            return Err(UploadError::NotCommitted(upload_id));
+        };
+
+        let upload = uploads
+            .remove(&upload_id)
+            .expect("Upload was just found, this is a bug");
+
+        Ok(CommittedUpload {
+            data: upload.data,
+            digest,
+            _reservation: upload.reservation,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Unknown upload: {0}")]
+    UnknownUpload(UploadId),
+
+    #[error("Upload budget exceeded, requested {requested} bytes, {available} bytes available")]
+    BudgetExceeded { requested: u64, available: u64 },
+
+    #[error("Failed to allocate memory for upload")]
+    OutOfMemory,
+
+    #[error("Upload {0} is already committed")]
+    AlreadyCommitted(UploadId),
+
+    #[error("Upload {0} is not committed")]
+    NotCommitted(UploadId),
+
+    #[error("Unexpected chunk offset, expected {expected} found {found}")]
+    UnexpectedOffset { expected: u64, found: u64 },
+
+    #[error("Chunk exceeds the declared upload length of {total_len} bytes")]
+    Overflow { total_len: u64 },
+
+    #[error("Upload incomplete, received {received} of {total_len} bytes")]
+    Incomplete { received: u64, total_len: u64 },
+
+    #[error("Upload digest mismatch")]
+    DigestMismatch,
+
+    #[error("Wrong upload kind, expected {expected:?} found {found:?}")]
+    WrongKind {
+        expected: UploadKind,
+        found: UploadKind,
+    },
+}
+
+impl From<UploadError> for EnclaveError {
+    fn from(e: UploadError) -> Self {
+        match e {
+            UploadError::BudgetExceeded { .. } | UploadError::OutOfMemory => {
+                EnclaveError::ResourceExhausted(e.to_string())
+            }
+            _ => EnclaveError::InvalidRequest(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sp1_tee_common::program_hash;
+
+    fn uploads(max: u64) -> (Arc<UploadBudget>, Uploads) {
+        let budget = UploadBudget::new(max);
+
+        (budget.clone(), Uploads::new(budget))
+    }
+
+    #[test]
+    fn upload_round_trip() {
+        let (budget, uploads) = uploads(100);
+        let data = (0..10u8).collect::<Vec<_>>();
+
+        let id = uploads.begin(UploadKind::Program, 10).unwrap();
+        assert_eq!(budget.available(), 90);
+
+        assert_eq!(uploads.chunk(id, 0, &data[..4]).unwrap(), 4);
+        assert_eq!(uploads.chunk(id, 4, &data[4..]).unwrap(), 10);
+        assert_eq!(
+            uploads.commit(id, program_hash(&data)).unwrap(),
+            program_hash(&data)
+        );
+
+        let upload = uploads.take(id, UploadKind::Program).unwrap();
+        assert_eq!(upload.data, data);
+        assert_eq!(upload.digest, program_hash(&data));
+
+        // The memory is held until the upload is used.
+        assert_eq!(budget.available(), 90);
+        drop(upload);
+        assert_eq!(budget.available(), 100);
+
+        assert!(matches!(
+            uploads.take(id, UploadKind::Program),
+            Err(UploadError::UnknownUpload(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_uploads_over_budget() {
+        let (budget, uploads) = uploads(10);
+
+        let id = uploads.begin(UploadKind::Stdin, 6).unwrap();
+        assert!(matches!(
+            uploads.begin(UploadKind::Stdin, 5),
+            Err(UploadError::BudgetExceeded {
+                requested: 5,
+                available: 4,
+            })
+        ));
+
+        uploads.abort(id).unwrap();
+        assert_eq!(budget.available(), 10);
+        uploads.begin(UploadKind::Stdin, 10).unwrap();
+    }
+
+    #[test]
+    fn rejects_out_of_order_and_oversized_chunks() {
+        let (_, uploads) = uploads(100);
+        let id = uploads.begin(UploadKind::Program, 4).unwrap();
+
+        assert!(matches!(
+            uploads.chunk(id, 1, &[0]),
+            Err(UploadError::UnexpectedOffset {
+                expected: 0,
+                found: 1,
+            })
+        ));
+        assert!(matches!(
+            uploads.chunk(id, 0, &[0; 5]),
+            Err(UploadError::Overflow { total_len: 4 })
+        ));
+
+        // Rejected chunks are not applied.
+        assert_eq!(uploads.chunk(id, 0, &[0; 4]).unwrap(), 4);
+    }
+
+    #[test]
+    fn failed_commits_discard_the_upload() {
+        let (budget, uploads) = uploads(100);
+
+        let incomplete = uploads.begin(UploadKind::Program, 4).unwrap();
+        uploads.chunk(incomplete, 0, &[1, 2]).unwrap();
+        assert!(matches!(
+            uploads.commit(incomplete, program_hash(&[1, 2])),
+            Err(UploadError::Incomplete {
+                received: 2,
+                total_len: 4,
+            })
+        ));
+
+        let mismatch = uploads.begin(UploadKind::Program, 2).unwrap();
+        uploads.chunk(mismatch, 0, &[1, 2]).unwrap();
+        assert!(matches!(
+            uploads.commit(mismatch, program_hash(&[2, 1])),
+            Err(UploadError::DigestMismatch)
+        ));
+
+        for id in [incomplete, mismatch] {
+            assert!(matches!(
+                uploads.chunk(id, 0, &[]),
+                Err(UploadError::UnknownUpload(_))
+            ));
+        }
+        assert_eq!(budget.available(), 100);
+    }
+
+    #[test]
+    fn committed_uploads_are_immutable() {
+        let (_, uploads) = uploads(100);
+        let id = uploads.begin(UploadKind::Program, 1).unwrap();
+
+        uploads.chunk(id, 0, &[7]).unwrap();
+        uploads.commit(id, program_hash(&[7])).unwrap();
+
+        assert!(matches!(
+            uploads.chunk(id, 1, &[]),
+            Err(UploadError::AlreadyCommitted(_))
+        ));
+        assert!(matches!(
+            uploads.commit(id, program_hash(&[7])),
+            Err(UploadError::AlreadyCommitted(_))
+        ));
+    }
+
+    #[test]
+    fn take_checks_kind_and_commit() {
+        let (_, uploads) = uploads(100);
+        let id = uploads.begin(UploadKind::Stdin, 0).unwrap();
+
+        assert!(matches!(
+            uploads.take(id, UploadKind::Stdin),
+            Err(UploadError::NotCommitted(_))
+        ));
+
+        uploads.commit(id, program_hash(&[])).unwrap();
+
+        assert!(matches!(
+            uploads.take(id, UploadKind::Program),
+            Err(UploadError::WrongKind {
+                expected: UploadKind::Program,
+                found: UploadKind::Stdin,
+            })
+        ));
+
+        // A failed take leaves the upload in place.
+        assert!(uploads.take(id, UploadKind::Stdin).is_ok());
+    }
+}
@@ -0,0 +1,71 @@
+use sp1_sdk::{
+    CpuProver, HashableKey, Prover, SP1Proof, SP1ProofWithPublicValues, SP1VerifyingKey,
+};
+use sp1_tee_common::{EnclaveError, ProofKind};
+use sp1_verifier::{Groth16Verifier, PlonkVerifier, GROTH16_VK_BYTES, PLONK_VK_BYTES};
+
+/// Verifies a proof of the program with verifying key `vk`, returning its kind.
+///
+/// Compressed proofs are verified by the prover. Plonk and Groth16 proofs are verified against the
+/// circuit keys embedded in `sp1-verifier`, as the prover would download the circuit artifacts,
+/// and the enclave has no network access.
+///
+/// # Errors
+/// - [`EnclaveError::InvalidProof`] - The proof is invalid, or a core proof.
+pub fn verify(
+    prover: &CpuProver,
+    proof: &SP1ProofWithPublicValues,
+    vk: &SP1VerifyingKey,
+) -> Result<ProofKind, EnclaveError> {
+    match &proof.proof {
+        SP1Proof::Compressed(_) => {
+            prover
+                .verify(proof, vk)
+                .map_err(|e| EnclaveError::InvalidProof(e.to_string()))?;
+
+            Ok(ProofKind::Compressed)
+        }
+        SP1Proof::Plonk(plonk) => {
+            let bytes = onchain_bytes(&plonk.plonk_vkey_hash, &plonk.encoded_proof)?;
+
+            PlonkVerifier::verify(
+                &bytes,
+                proof.public_values.as_slice(),
+                &vk.bytes32(),
+                *PLONK_VK_BYTES,
+            )
+            .map_err(|e| EnclaveError::InvalidProof(e.to_string()))?;
+
+            Ok(ProofKind::Plonk)
+        }
+        SP1Proof::Groth16(groth16) => {
+            let bytes = onchain_bytes(&groth16.groth16_vkey_hash, &groth16.encoded_proof)?;
+
+            Groth16Verifier::verify(
+                &bytes,
+                proof.public_values.as_slice(),
+                &vk.bytes32(),
+                *GROTH16_VK_BYTES,
+            )
+            .map_err(|e| EnclaveError::InvalidProof(e.to_string()))?;
+
+            Ok(ProofKind::Groth16)
+        }
+        _ => Err(EnclaveError::InvalidProof(
+            "Only compressed, Plonk and Groth16 proofs can be verified".to_string(),
+        )),
+    }
+}
+
+/// The proof bytes `sp1-verifier` expects, the first 4 bytes of the circuit's vkey hash followed by the proof.
+///
+/// This mirrors [`SP1ProofWithPublicValues::bytes`], which panics if the proof is not valid hex.
+fn onchain_bytes(
+    circuit_vkey_hash: &[u8; 32],
+    encoded_proof: &str,
+) -> Result<Vec<u8>, EnclaveError> {
+    let proof = hex::decode(encoded_proof)
+        .map_err(|e| EnclaveError::InvalidProof(format!("Failed to decode proof: {}", e)))?;
+
+    Ok([&circuit_vkey_hash[..4], &proof].concat())
+}
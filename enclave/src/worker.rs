@@ -0,0 +1,409 @@
+//! Runs executions in a child process, so a program cannot crash or read the memory of the process holding the signing key.
+//!
+//! The enclave binary is started again with [`WORKER_ARG`], the request is written to its stdin,
+//! and the [`WorkerResponse`] is read from its stdout. The worker never sees the signing key,
+//! the parent signs the result.
+
+use crate::executor::{self, CancelFlag, Execution, ExecutionFailure};
+use crate::memory;
+
+use serde::{Deserialize, Serialize};
+use sp1_sdk::{CpuProver, Prover, SP1Stdin, SP1VerifyingKey};
+use sp1_tee_common::EnclaveError;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::os::fd::FromRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// The first argument of the enclave binary when it is started as a worker.
+pub const WORKER_ARG: &str = "__execution-worker";
+
+/// How often the parent checks whether the worker exited, or should be stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The time a worker gets on top of twice its time limit, to start the prover and set up the program.
+///
+/// The worker stops itself at the time limit, this only stops a worker that failed to.
+const STARTUP_GRACE: Duration = Duration::from_secs(30);
+
+/// An execution, sent from the parent to the worker.
+#[derive(Serialize, Deserialize)]
+struct WorkerRequest<'a> {
+    elf: Cow<'a, [u8]>,
+    stdin: Cow<'a, SP1Stdin>,
+    cycle_limit: u64,
+    time_limit: Duration,
+    /// Set up the program, and return its verifying key.
+    setup: bool,
+}
+
+/// The result of an execution, sent from the worker to the parent.
+#[derive(Serialize, Deserialize)]
+pub struct WorkerResponse {
+    /// The verifying key of the program, if the request asked for setup.
+    pub vk: Option<SP1VerifyingKey>,
+    pub setup_time: Duration,
+    pub execute_time: Duration,
+    /// The peak resident set size of the worker in bytes.
+    pub peak_memory_bytes: Option<u64>,
+    pub result: Result<Execution, ExecutionFailure>,
+}
+
+/// The resources a worker may use, enforced by the kernel.
+pub struct WorkerLimits {
+    /// The memory the worker may allocate, in bytes.
+    pub memory: u64,
+    /// How long the program may run for, after setup.
+    pub time: Duration,
+}
+
+/// Executes a program in a new worker process, setting it up first if `setup` is set.
+///
+/// # Errors
+/// - [`EnclaveError::Cancelled`] - `cancel` was set, the worker was killed.
+/// - [`EnclaveError::ExecutionTimeLimitExceeded`] - The worker ran for too long, and was killed.
+/// - [`EnclaveError::WorkerCrashed`] - The worker exited without a response, i.e. it panicked or ran out of memory.
+/// - [`EnclaveError::Internal`] - The worker could not be started.
+pub fn execute(
+    elf: &[u8],
+    stdin: &SP1Stdin,
+    cycle_limit: u64,
+    setup: bool,
+    limits: WorkerLimits,
+    cancel: &CancelFlag,
+) -> Result<WorkerResponse, EnclaveError> {
+    let request = WorkerRequest {
+        elf: Cow::Borrowed(elf),
+        stdin: Cow::Borrowed(stdin),
+        cycle_limit,
+        time_limit: limits.time,
+        setup,
+    };
+
+    let exe = std::env::current_exe()
+        .map_err(|e| EnclaveError::Internal(format!("Failed to find the enclave binary: {}", e)))?;
+
+    let mut command = Command::new(exe);
+    command.arg(WORKER_ARG);
+
+    let deadline = deadline(limits.time);
+
+    spawn(command, &request, limits, deadline, cancel)
+}
+
+/// How long a worker with the `time_limit` may run for, including setup.
+///
+/// Setup runs on several threads, so the worker may use more CPU time than wall clock time.
+fn deadline(time_limit: Duration) -> Duration {
+    time_limit.saturating_mul(2).saturating_add(STARTUP_GRACE)
+}
+
+/// Runs a worker with `command`, stopping it once `deadline` has passed since it started.
+///
+/// The worker's CPU time is also limited to `deadline`, and its memory to `limits.memory`.
+fn spawn(
+    mut command: Command,
+    request: &WorkerRequest<'_>,
+    limits: WorkerLimits,
+    deadline: Duration,
+    cancel: &CancelFlag,
+) -> Result<WorkerResponse, EnclaveError> {
+    let cpu_secs = deadline.as_secs();
+    let memory = limits.memory;
+
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+
+    // SAFETY: `setrlimit` is async-signal-safe, and nothing else runs between fork and exec.
+    unsafe {
+        command.pre_exec(move || {
+            let memory = libc::rlimit {
+                rlim_cur: memory,
+                rlim_max: memory,
+            };
+
+            // The worker gets `SIGXCPU` at the soft limit, and is killed a second later.
+            let cpu = libc::rlimit {
+                rlim_cur: cpu_secs,
+                rlim_max: cpu_secs + 1,
+            };
+
+            if libc::setrlimit(libc::RLIMIT_DATA, &memory) != 0
+                || libc::setrlimit(libc::RLIMIT_CPU, &cpu) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            Ok(())
+        });
+    }
+
+    let start = Instant::now();
+
+    let mut child = command
+        .spawn()
+        .map_err(|e| EnclaveError::Internal(format!("Failed to start worker: {}", e)))?;
+
+    let mut child_stdin = child.stdin.take().expect("The worker's stdin is piped");
+    let mut child_stdout = child.stdout.take().expect("The worker's stdout is piped");
+
+    // Read the response as it is written, so the worker never blocks on a full pipe.
+    let reader = std::thread::spawn(move || {
+        let mut response = Vec::new();
+        child_stdout.read_to_end(&mut response).map(|_| response)
+    });
+
+    // If the worker died before reading the whole request, its exit status says why.
+    let written = bincode::serialize_into(&mut child_stdin, request);
+    drop(child_stdin);
+
+    let (status, stopped) = loop {
+        let stopped = if cancel.is_cancelled() {
+            Some(EnclaveError::Cancelled)
+        } else if start.elapsed() > deadline {
+            Some(EnclaveError::ExecutionTimeLimitExceeded(
+                limits.time.as_secs(),
+            ))
+        } else {
+            None
+        };
+
+        if stopped.is_some() {
+            let _ = child.kill();
+        }
+
+        let status = match stopped {
+            Some(_) => child.wait().map(Some),
+            None => child.try_wait(),
+        }
+        .map_err(|e| EnclaveError::Internal(format!("Failed to wait for worker: {}", e)))?;
+
+        if let Some(status) = status {
+            break (status, stopped);
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    };
+
+    let response = reader.join().map_err(|_| {
+        EnclaveError::Internal("Worker response reader panicked, this is a bug.".to_string())
+    })?;
+
+    if let Some(error) = stopped {
+        return Err(error);
+    }
+
+    // A worker that exited cleanly always wrote a complete response.
+    match (written, response) {
+        (Ok(()), Ok(response)) if status.success() => bincode::deserialize(&response)
+            .map_err(|e| EnclaveError::Internal(format!("Invalid worker response: {}", e))),
+        _ => Err(exit_error(status, limits.time)),
+    }
+}
+
+/// The error for a worker that exited without a response.
+fn exit_error(status: ExitStatus, time_limit: Duration) -> EnclaveError {
+    match status.signal() {
+        Some(libc::SIGXCPU) => EnclaveError::ExecutionTimeLimitExceeded(time_limit.as_secs()),
+        _ => EnclaveError::WorkerCrashed(status.to_string()),
+    }
+}
+
+/// The entrypoint of a worker, runs the execution read from stdin and writes the response to stdout.
+pub fn run() {
+    // The executor prints the program's output to stdout, so keep it off the response pipe.
+    //
+    // SAFETY: These are the standard streams, no other thread is using them yet.
+    let mut output = unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        assert!(fd >= 0, "Failed to duplicate stdout");
+        assert!(
+            libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) >= 0,
+            "Failed to redirect stdout"
+        );
+
+        std::fs::File::from_raw_fd(fd)
+    };
+
+    let request: WorkerRequest<'static> =
+        bincode::deserialize_from(std::io::stdin().lock()).expect("Failed to read worker request");
+
+    // Starting a prover is expensive, so it is only started for setup or to verify deferred proofs.
+    let setup_start = Instant::now();
+    let prover = (request.setup || !request.stdin.proofs.is_empty()).then(CpuProver::new);
+    let vk = prover
+        .as_ref()
+        .filter(|_| request.setup)
+        .map(|prover| prover.setup(&request.elf).1);
+    let setup_time = setup_start.elapsed();
+
+    let execute_start = Instant::now();
+    let result = executor::execute(
+        prover.as_ref(),
+        &request.elf,
+        &request.stdin,
+        request.cycle_limit,
+        request.time_limit,
+        // The parent kills the worker to cancel it.
+        &CancelFlag::default(),
+    );
+
+    let response = WorkerResponse {
+        vk,
+        setup_time,
+        execute_time: execute_start.elapsed(),
+        peak_memory_bytes: memory::peak_memory(),
+        result,
+    };
+
+    bincode::serialize_into(&mut output, &response).expect("Failed to write worker response");
+    output.flush().expect("Failed to write worker response");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMORY: u64 = 64 * 1024 * 1024;
+
+    fn request() -> WorkerRequest<'static> {
+        WorkerRequest {
+            elf: Cow::Owned(vec![0; 16]),
+            stdin: Cow::Owned(SP1Stdin::new()),
+            cycle_limit: 1_000,
+            time_limit: Duration::from_secs(1),
+            setup: false,
+        }
+    }
+
+    fn limits() -> WorkerLimits {
+        WorkerLimits {
+            memory: MEMORY,
+            time: Duration::from_secs(1),
+        }
+    }
+
+    /// Runs a shell script as the worker.
+    fn spawn_script(
+        script: &str,
+        deadline: Duration,
+        cancel: &CancelFlag,
+    ) -> Result<WorkerResponse, EnclaveError> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+
+        spawn(command, &request(), limits(), deadline, cancel)
+    }
+
+    fn crash_reason(result: Result<WorkerResponse, EnclaveError>) -> String {
+        match result {
+            Err(EnclaveError::WorkerCrashed(reason)) => reason,
+            Err(e) => panic!("Expected the worker to crash, got {:?}", e),
+            Ok(_) => panic!("Expected the worker to crash"),
+        }
+    }
+
+    #[test]
+    fn deadline_allows_setup() {
+        assert_eq!(deadline(Duration::ZERO), STARTUP_GRACE);
+        assert_eq!(deadline(Duration::from_secs(60)), Duration::from_secs(150));
+        assert_eq!(deadline(Duration::MAX), Duration::MAX);
+    }
+
+    #[test]
+    fn limits_are_applied() {
+        // The script crashes only if both limits are set, `ulimit -d` is in KiB.
+        let script = format!(
+            "[ \"$(ulimit -d)\" = {} ] && [ \"$(ulimit -t)\" = 5 ] && kill -SEGV $$; exit 1",
+            MEMORY / 1024
+        );
+
+        let reason = crash_reason(spawn_script(
+            &script,
+            Duration::from_secs(5),
+            &CancelFlag::default(),
+        ));
+
+        assert!(reason.contains("signal: 11"), "{}", reason);
+    }
+
+    #[test]
+    fn workers_without_a_response_crashed() {
+        let reason = crash_reason(spawn_script(
+            "cat > /dev/null; exit 3",
+            Duration::from_secs(5),
+            &CancelFlag::default(),
+        ));
+        assert_eq!(reason, "exit status: 3");
+
+        // The worker died before reading the request.
+        let reason = crash_reason(spawn_script(
+            "kill -KILL $$",
+            Duration::from_secs(5),
+            &CancelFlag::default(),
+        ));
+        assert!(reason.contains("signal: 9"), "{}", reason);
+    }
+
+    #[test]
+    fn invalid_responses_are_rejected() {
+        let result = spawn_script(
+            "cat > /dev/null; echo garbage",
+            Duration::from_secs(5),
+            &CancelFlag::default(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(EnclaveError::Internal(message)) if message.starts_with("Invalid worker response")
+        ));
+    }
+
+    #[test]
+    fn cpu_limit_is_a_time_limit() {
+        let result = spawn_script(
+            "kill -XCPU $$",
+            Duration::from_secs(5),
+            &CancelFlag::default(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(EnclaveError::ExecutionTimeLimitExceeded(1))
+        ));
+    }
+
+    #[test]
+    fn workers_past_the_deadline_are_killed() {
+        let start = Instant::now();
+
+        let result = spawn_script(
+            "exec sleep 10",
+            Duration::from_millis(200),
+            &CancelFlag::default(),
+        );
+
+        assert!(matches!(
+            result,
+            Err(EnclaveError::ExecutionTimeLimitExceeded(1))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn cancelled_workers_are_killed() {
+        let cancel = CancelFlag::default();
+        cancel.cancel();
+
+        let start = Instant::now();
+
+        let result = spawn_script("exec sleep 10", Duration::from_secs(60), &cancel);
+
+        assert!(matches!(result, Err(EnclaveError::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
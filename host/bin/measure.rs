@@ -0,0 +1,117 @@
+//! Compute the PCRs of an Enclave Image File offline, and diff them against a published attestation.
+//!
+//! To check that a signer runs this repository's enclave, rebuild the image and measure it:
+//!
+//! ```text
+//! docker build -t sp1-tee .
+//! nitro-cli build-enclave --docker-uri sp1-tee:latest --output-file sp1-tee.eif
+//! cargo run --bin sp1-tee-measure -- sp1-tee.eif --signer <address>
+//! ```
+use std::path::PathBuf;
+
+use alloy::primitives::Address;
+use clap::Parser;
+
+use sp1_tee_host::eif::EifMeasurements;
+
+#[derive(Parser)]
+#[clap(about = "
+    Compute the PCRs of an EIF, as `nitro-cli describe-eif` would.
+
+    If an attestation, signer or PCR0 is given, the PCRs are compared against it,
+    and the command exits with an error if any differ.
+")]
+struct Args {
+    /// The path to the EIF, i.e. `sp1-tee.eif` as built by `scripts/enclave.sh`.
+    eif: PathBuf,
+
+    /// The path to a COSESign1 attestation document to compare against, i.e. from the `/attestation` endpoint.
+    #[clap(long, conflicts_with = "signer")]
+    attestation: Option<PathBuf>,
+
+    /// A signer whose attestation published to S3 should be compared against.
+    #[clap(long)]
+    signer: Option<Address>,
+
+    /// A hex encoded PCR0 to compare against, as passed to `sp1-tee-setup` and `validate_signers`.
+    #[clap(long)]
+    pcr0: Option<String>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+
+    let measurements = EifMeasurements::from_file(&args.eif).expect("Failed to measure the EIF");
+
+    println!("EIF version: {}", measurements.version);
+    for (index, pcr) in measurements.pcrs() {
+        println!("PCR{}: {}", index, hex::encode(pcr));
+    }
+
+    let mut matches = true;
+
+    if let Some(pcr0) = &args.pcr0 {
+        let pcr0 = hex::decode(pcr0.trim_start_matches("0x")).expect("Invalid PCR0");
+
+        println!("-----------------------------------");
+        matches &= compare(0, &measurements.pcr0, &pcr0, "--pcr0");
+    }
+
+    let attestation = match (&args.attestation, args.signer) {
+        (Some(path), _) => Some(std::fs::read(path).expect("Failed to read the attestation")),
+        (None, Some(signer)) => Some(
+            sp1_tee_host::attestations::get_raw_attestation(signer)
+                .await
+                .expect("Failed to fetch the signer's attestation"),
+        ),
+        (None, None) => None,
+    };
+
+    if let Some(attestation) = attestation {
+        // Check the root of trust, so the PCRs are the ones the NSM measured.
+        let doc = sp1_tee_host::attestations::verify_attestation(&attestation)
+            .expect("Failed to verify the attestation");
+
+        println!("-----------------------------------");
+
+        for (index, pcr) in measurements.pcrs() {
+            let Some(attested) = doc.pcrs.get(&index) else {
+                println!("PCR{}: missing from the attestation", index);
+                matches = false;
+
+                continue;
+            };
+
+            matches &= compare(index, pcr, attested.as_ref(), "attestation");
+        }
+
+        // Enclaves running in debug mode attest to zeroed PCRs.
+        if doc
+            .pcrs
+            .get(&0)
+            .is_some_and(|pcr| pcr.iter().all(|b| *b == 0))
+        {
+            println!("NOTE: The attestation is from an enclave running in debug mode.");
+        }
+    }
+
+    if !matches {
+        std::process::exit(1);
+    }
+}
+
+/// Prints whether a measured PCR matches the expected one, returning true if it does.
+fn compare(index: usize, measured: &[u8], expected: &[u8], source: &str) -> bool {
+    if measured == expected {
+        println!("PCR{}: matches the {}", index, source);
+
+        return true;
+    }
+
+    println!("PCR{}: MISMATCH", index);
+    println!("  EIF:    {}", hex::encode(measured));
+    println!("  {}: {}", source, hex::encode(expected));
+
+    false
+}
@@ -0,0 +1,80 @@
+//! Rotate the signing key of a running enclave.
+//!
+//! The current key keeps signing for the grace period, while the next key is registered on-chain.
+use std::path::PathBuf;
+
+use clap::Parser;
+use sp1_tee_common::{EnclaveRequest, EnclaveResponse, TransportAddr};
+use sp1_tee_host::{ethereum_address_from_encoded_point, server::stream::HostStream};
+
+#[derive(Parser)]
+struct Args {
+    /// The address of the enclave to rotate the key of.
+    ///
+    /// Defaults to the vsock address of an enclave managed by the server.
+    #[clap(long)]
+    enclave_addr: Option<TransportAddr>,
+
+    /// How long (in seconds) the current key keeps signing, before the next key takes over.
+    ///
+    /// This must leave enough time to register the next key on the verifier.
+    #[clap(long, default_value = "86400")]
+    grace_period_secs: u64,
+
+    /// Where the server persists the sealed signing key, if sealing is enabled.
+    ///
+    /// The rotation is sealed here, so it survives an enclave restart.
+    #[clap(long)]
+    sealed_key_path: Option<PathBuf>,
+
+    /// The S3 bucket to save the attestations to.
+    #[clap(long, default_value = sp1_tee_host::S3_BUCKET)]
+    bucket: String,
+}
+
+#[tokio::main]
+async fn main() {
+    sp1_tee_host::init_tracing();
+
+    let args = Args::parse();
+
+    let addr = args
+        .enclave_addr
+        .unwrap_or(TransportAddr::vsock(sp1_tee_common::ENCLAVE_CID));
+
+    let stream = HostStream::new(&addr)
+        .await
+        .expect("Failed to connect to enclave");
+
+    let keys = match stream
+        .request(EnclaveRequest::RotateKey {
+            grace_period_secs: args.grace_period_secs,
+        })
+        .await
+        .expect("Failed to rotate the signing key")
+    {
+        EnclaveResponse::SigningKeys(keys) => keys,
+        EnclaveResponse::Error(e) => panic!("Enclave failed to rotate the signing key: {}", e),
+        response => panic!("Unexpected response from enclave: {}", response.type_of()),
+    };
+
+    if let Some(path) = &args.sealed_key_path {
+        stream
+            .seal_signing_key(path)
+            .await
+            .expect("Failed to seal the next signing key");
+    }
+
+    sp1_tee_host::attestations::save_signing_keys(&keys, &args.bucket)
+        .await
+        .expect("Failed to save the attestations");
+
+    let next = keys.next.expect("The enclave did not report the next key");
+
+    println!(
+        "Rotated signer {:?} -> {:?}, the next signer takes over in {}s",
+        ethereum_address_from_encoded_point(&keys.active.public_key),
+        ethereum_address_from_encoded_point(&next.key.public_key),
+        next.activates_in_secs
+    );
+}
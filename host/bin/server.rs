@@ -1,27 +1,61 @@
 use axum::{
-    body::Bytes,
-    extract::{DefaultBodyLimit, State},
-    response::sse::{Event, Sse},
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
     routing::{get, post},
     Json, Router,
 };
 use clap::Parser;
+use k256::ecdsa::Signature;
 use sp1_sdk::network::tee::SP1_TEE_VERSION;
-use sp1_tee_common::{EnclaveRequest, EnclaveResponse};
-use sp1_tee_host::{
-    api::GetAddressResponse,
-    server::{Server, ServerArgs, ServerError},
+use sp1_tee_common::{
+    program_hash, CommunicationError, EnclaveConfig, EnclaveError, EnclaveRequest, EnclaveResponse,
+    ExecutionReport, SchedulerStatus, SignatureDomain, SignedProofVerdict, ThresholdProof,
+    UploadKind,
 };
+use sp1_tee_host::api::{ExecutionOutput, TEERequest, TEEResponse, VerifyProofRequest};
 use sp1_tee_host::{
-    api::{TEERequest, TEEResponse},
-    HostStream,
+    api::{AttestationQuery, GetAddressResponse, KeyMigrationResponse, ReadinessResponse},
+    server::{
+        cosign::signed_message, stream::HostStream, upload::CommittedUpload, Server, ServerArgs,
+        ServerError,
+    },
 };
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 
 use futures::stream::{self, Stream, StreamExt};
 
+/// The hex encoded ID of a streamed execution request.
+const REQUEST_ID_HEADER: &str = "x-sp1-tee-request-id";
+
+/// The cycle limit of a streamed execution request.
+const CYCLE_LIMIT_HEADER: &str = "x-sp1-tee-cycle-limit";
+
+/// The length (in bytes) of the program at the start of a streamed request body.
+const PROGRAM_LENGTH_HEADER: &str = "x-sp1-tee-program-length";
+
+/// The length (in bytes) of the bincode encoded stdin following the program.
+const STDIN_LENGTH_HEADER: &str = "x-sp1-tee-stdin-length";
+
+/// The chain ID of a domain separated signature, see [`SignatureDomain`].
+///
+/// Clients opt into domain separation by setting this, [`VERIFIER_HEADER`] and [`EXPIRES_AT_HEADER`].
+const CHAIN_ID_HEADER: &str = "x-sp1-tee-chain-id";
+
+/// The hex encoded address of the verifier a domain separated signature is for.
+const VERIFIER_HEADER: &str = "x-sp1-tee-verifier";
+
+/// The unix timestamp (in seconds) after which a domain separated signature expires.
+const EXPIRES_AT_HEADER: &str = "x-sp1-tee-expires-at";
+
+/// The hex encoded signature over the request ID, used for authentication.
+#[cfg(feature = "production")]
+const SIGNATURE_HEADER: &str = "x-sp1-tee-signature";
+
 #[tokio::main]
 async fn main() {
     sp1_tee_host::init_tracing();
@@ -31,17 +65,35 @@ async fn main() {
     // First, kill any existing enclaves.
     //
     // Just in case the server was killed uncleanly last time.
-    sp1_tee_host::server::terminate_enclaves();
+    if args.manages_enclave() {
+        sp1_tee_host::server::terminate_enclaves();
+    }
 
     // Start the server.
     //
     // This function also starts the enclave and spawns a task to save attestations to S3.
     let server = Server::new(&args);
 
+    // The request body carries the program and stdin, so it may be as large as a payload frame.
+    let body_limit = args.max_payload_frame_size as usize;
+
     let app = Router::new()
-        .route("/execute", post(execute).layer(DefaultBodyLimit::disable()))
+        .route(
+            "/execute",
+            post(execute).layer(DefaultBodyLimit::max(body_limit)),
+        )
+        .route("/execute/stream", post(execute_stream))
+        .route(
+            "/verify",
+            post(verify_proof).layer(DefaultBodyLimit::max(body_limit)),
+        )
         .route("/address", get(get_address))
         .route("/signers", get(get_signers))
+        .route("/attestation", get(get_attestation))
+        .route("/scheduler", get(get_scheduler_status))
+        .route("/config", get(get_config))
+        .route("/ready", get(get_readiness))
+        .route("/migrate", post(migrate))
         .with_state(server);
 
     let listener = TcpListener::bind((args.address.clone(), args.port))
@@ -58,9 +110,12 @@ async fn main() {
             }
         }
         _ = tokio::signal::ctrl_c() => {
-            tracing::info!("Ctrl-C received, terminating enclaves");
+            if args.manages_enclave() {
+                tracing::info!("Ctrl-C received, terminating enclaves");
+
+                sp1_tee_host::server::terminate_enclaves();
+            }
 
-            sp1_tee_host::server::terminate_enclaves();
             std::process::exit(0);
         }
     }
@@ -74,24 +129,17 @@ async fn get_signers() -> Result<Bytes, ServerError> {
 
     tracing::debug!("Found {} attestations", all_attestations.len());
 
-    let signers = all_attestations
-        .iter()
-        .filter_map(|raw| sp1_tee_host::attestations::verify_attestation(&raw.attestation).ok())
-        .filter(|doc| {
-            doc.user_data
-                .as_ref()
-                .expect("No user data found in attestation")
-                == &SP1_TEE_VERSION.to_le_bytes()
-        })
-        .map(|doc| {
-            sp1_tee_host::ethereum_address_from_sec1_bytes(
-                doc.public_key
-                    .as_ref()
-                    .expect("No public key found in attestation"),
-            )
-            .ok_or(ServerError::FailedToConvertPublicKeyToAddress)
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+    let mut signers = Vec::new();
+    for raw in &all_attestations {
+        let Ok(doc) = sp1_tee_host::attestations::verify_attestation(&raw.attestation) else {
+            continue;
+        };
+
+        let signer = sp1_tee_host::attestations::signer_for_version(&doc, SP1_TEE_VERSION)
+            .map_err(|_| ServerError::FailedToConvertPublicKeyToAddress)?;
+
+        signers.extend(signer);
+    }
 
     tracing::debug!("Found {} signers", signers.len());
 
@@ -105,33 +153,21 @@ async fn get_address(
 ) -> Result<Json<GetAddressResponse>, ServerError> {
     tracing::debug!("Handling get address request");
 
-    let mut stream = HostStream::new(server.cid, sp1_tee_common::ENCLAVE_PORT)
-        .await
-        .map_err(|e| {
-            tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
+    let stream = server.enclave().await.map_err(|e| {
+        tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
 
-            ServerError::FailedToConnectToEnclave
-        })?;
+        ServerError::FailedToConnectToEnclave
+    })?;
 
-    stream
-        .send(EnclaveRequest::GetPublicKey)
+    let response = stream
+        .request(EnclaveRequest::GetPublicKey)
         .await
         .map_err(|e| {
-            tracing::error!(alert = true, "Failed to send request to enclave: {}", e);
+            tracing::error!(alert = true, "Failed to get response from enclave: {}", e);
 
-            ServerError::FailedToSendRequestToEnclave
+            ServerError::FailedToReceiveResponseFromEnclave
         })?;
 
-    let response = stream.recv().await.map_err(|e| {
-        tracing::error!(
-            alert = true,
-            "Failed to receive response from enclave: {}",
-            e
-        );
-
-        ServerError::FailedToReceiveResponseFromEnclave
-    })?;
-
     match response {
         EnclaveResponse::PublicKey(public_key) => {
             let Some(address) = sp1_tee_host::ethereum_address_from_encoded_point(&public_key)
@@ -155,11 +191,185 @@ async fn get_address(
     }
 }
 
+/// Get the state of the enclave's execution scheduler.
+async fn get_scheduler_status(
+    State(server): State<Arc<Server>>,
+) -> Result<Json<SchedulerStatus>, ServerError> {
+    let stream = server.enclave().await.map_err(|e| {
+        tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
+
+        ServerError::FailedToConnectToEnclave
+    })?;
+
+    let response = stream
+        .request(EnclaveRequest::GetSchedulerStatus)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get response from enclave: {}", e);
+
+            ServerError::FailedToReceiveResponseFromEnclave
+        })?;
+
+    match response {
+        EnclaveResponse::SchedulerStatus(status) => Ok(Json(status)),
+        _ => {
+            tracing::error!("Unexpected response from enclave: {:?}", response);
+
+            Err(ServerError::UnexpectedResponseFromEnclave)
+        }
+    }
+}
+
+/// Get the execution policy the enclave was started with.
+async fn get_config(State(server): State<Arc<Server>>) -> Result<Json<EnclaveConfig>, ServerError> {
+    let stream = server.enclave().await.map_err(|e| {
+        tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
+
+        ServerError::FailedToConnectToEnclave
+    })?;
+
+    let response = stream
+        .request(EnclaveRequest::GetConfig)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get response from enclave: {}", e);
+
+            ServerError::FailedToReceiveResponseFromEnclave
+        })?;
+
+    match response {
+        EnclaveResponse::Config(config) => Ok(Json(config)),
+        _ => {
+            tracing::error!("Unexpected response from enclave: {:?}", response);
+
+            Err(ServerError::UnexpectedResponseFromEnclave)
+        }
+    }
+}
+
+/// Get whether the server is ready to serve traffic, with the outcome of each enclave's self-test.
+///
+/// Responds with a `503` status until the server is ready, so it can be used as a readiness probe.
+async fn get_readiness(State(server): State<Arc<Server>>) -> (StatusCode, Json<ReadinessResponse>) {
+    let readiness = server.readiness();
+
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
+
+/// Get a fresh attestation of the enclave's signing key, binding the caller's nonce.
+///
+/// The body is the COSESign1 attestation document, see
+/// [`sp1_tee_host::attestations::verify_attestation_with_nonce`] to verify it.
+async fn get_attestation(
+    State(server): State<Arc<Server>>,
+    Query(query): Query<AttestationQuery>,
+) -> Result<Bytes, ServerError> {
+    let nonce = hex::decode(query.nonce.trim_start_matches("0x"))
+        .ok()
+        .filter(|nonce| nonce.len() <= sp1_tee_common::MAX_NONCE_LEN)
+        .ok_or(ServerError::InvalidNonce)?;
+
+    tracing::debug!(
+        "Handling attestation request, nonce: {}",
+        hex::encode(&nonce)
+    );
+
+    let stream = server.enclave().await.map_err(|e| {
+        tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
+
+        ServerError::FailedToConnectToEnclave
+    })?;
+
+    let response = stream
+        .request(EnclaveRequest::AttestSigningKey { nonce: Some(nonce) })
+        .await
+        .map_err(|e| {
+            tracing::error!(alert = true, "Failed to get response from enclave: {}", e);
+
+            ServerError::FailedToReceiveResponseFromEnclave
+        })?;
+
+    match response {
+        EnclaveResponse::SigningKeyAttestation(attestation) => Ok(attestation.into()),
+        EnclaveResponse::Error(e) => {
+            tracing::error!("Enclave failed to attest signing key: {}", e);
+
+            Err(ServerError::EnclaveError(e))
+        }
+        _ => {
+            tracing::error!(
+                alert = true,
+                "Unexpected response from enclave: {:?}",
+                response
+            );
+
+            Err(ServerError::UnexpectedResponseFromEnclave)
+        }
+    }
+}
+
+/// Donate the enclave's signing key to the enclave that produced the attestation in the body.
+///
+/// The enclave checks the attestation itself, the key is encrypted to the requesting enclave.
+async fn migrate(
+    State(server): State<Arc<Server>>,
+    attestation: Bytes,
+) -> Result<Bytes, ServerError> {
+    if !server.allow_key_migration {
+        return Err(ServerError::KeyMigrationDisabled);
+    }
+
+    tracing::info!("Handling key migration request");
+
+    let stream = server.enclave().await.map_err(|e| {
+        tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
+
+        ServerError::FailedToConnectToEnclave
+    })?;
+
+    let response = stream
+        .request(EnclaveRequest::ExportSigningKey {
+            attestation: attestation.to_vec(),
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get response from enclave: {}", e);
+
+            ServerError::FailedToReceiveResponseFromEnclave
+        })?;
+
+    match response {
+        EnclaveResponse::ExportedSigningKey {
+            attestation,
+            ciphertext,
+        } => Ok(bincode::serialize(&KeyMigrationResponse {
+            attestation,
+            ciphertext,
+        })
+        .expect("Failed to serialize key migration response")
+        .into()),
+        EnclaveResponse::Error(e) => {
+            tracing::warn!("Enclave rejected key migration: {}", e);
+
+            Err(ServerError::EnclaveError(e))
+        }
+        _ => Err(ServerError::UnexpectedResponseFromEnclave),
+    }
+}
+
 /// Execute a program on the enclave.
 ///
-/// In order to avoid OOM in the enclave, we run only one program at a time.
+/// In order to avoid OOM, the enclave only admits executions while their estimated memory fits in its budget,
+/// see `/scheduler`.
 async fn execute(
     State(server): State<Arc<Server>>,
+    headers: HeaderMap,
     req: Bytes,
 ) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ServerError> {
     let request = bincode::deserialize::<TEERequest>(&req).map_err(|e| {
@@ -169,128 +379,688 @@ async fn execute(
     })?;
 
     #[cfg(feature = "production")]
-    {
-        let signer = request
-            .signature
-            .recover_address_from_msg(request.id)
-            .map_err(|_| {
-                tracing::error!(
-                    "Failed to recover signer address, request id: {}",
-                    hex::encode(request.id)
-                );
+    authenticate(&server, request.id, &request.signature).await?;
 
-                ServerError::FailedToAuthenticateRequest
-            })?;
+    // Reject requests the enclave would refuse before we start streaming the response,
+    // so the client gets a 413 status.
+    check_request_size(&server, &request)?;
 
-        match server.auth_client.is_whitelisted(signer).await {
-            Ok(true) => (),
-            Ok(false) => {
-                tracing::error!(
-                    "Failed to authenticate request by {:?}: Not whitelisted",
-                    signer
-                );
+    let domain = signature_domain(&headers, request.id)?;
+
+    let response = execute_inner(server.clone(), request, domain);
+    let response = stream::once(response)
+        .flat_map(|response| stream::iter(sp1_tee_host::api::result_to_events(response)).map(Ok));
+
+    // Keep alive events let us notice the client disconnecting, which cancels the execution.
+    Ok(Sse::new(response).keep_alive(KeepAlive::default()))
+}
+
+/// Verify a compressed, Plonk or Groth16 proof in the enclave.
+///
+/// The body is the bincode encoded [`VerifyProofRequest`], and the response is the JSON encoded
+/// [`SignedProofVerdict`] if the proof is valid. The domain headers bind the verdict, as they do for `/execute`.
+#[tracing::instrument(skip_all)]
+async fn verify_proof(
+    State(server): State<Arc<Server>>,
+    headers: HeaderMap,
+    req: Bytes,
+) -> Result<Json<SignedProofVerdict>, ServerError> {
+    let request = bincode::deserialize::<VerifyProofRequest>(&req).map_err(|e| {
+        tracing::error!("Failed to deserialize request: {}", e);
 
-                return Err(ServerError::FailedToAuthenticateRequest);
+        ServerError::FailedToDeserializeRequest(e)
+    })?;
+
+    #[cfg(feature = "production")]
+    authenticate(&server, request.id, &request.signature).await?;
+
+    let domain = signature_domain(&headers, request.id)?;
+
+    tracing::info!(
+        "Got proof verification request, id: {}",
+        hex::encode(request.id)
+    );
+
+    let stream = server.enclave().await.map_err(|e| {
+        tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
+
+        ServerError::FailedToConnectToEnclave
+    })?;
+
+    let request = EnclaveRequest::VerifyProof {
+        proof: Box::new(request.proof),
+        vkey: request.vkey,
+        domain,
+    };
+
+    // Dropping the request on timeout cancels it in the enclave.
+    let response = tokio::time::timeout(server.execution_timeout, stream.request(request))
+        .await
+        .map_err(|_| execution_timed_out(&server))?
+        .map_err(|e| match e {
+            CommunicationError::FrameTooLarge { size, .. } => {
+                tracing::warn!("Request frame too large: {}", e);
+
+                ServerError::ProofTooLarge(size as usize)
             }
-            Err(e) => {
+            CommunicationError::Disconnected => {
                 tracing::error!(
                     alert = true,
-                    "Failed to authenticate request by {:?}: {}",
-                    signer,
+                    "Failed to receive response from enclave: {:?}",
                     e
                 );
 
-                return Err(ServerError::FailedToAuthenticateRequest);
+                ServerError::FailedToReceiveResponseFromEnclave
             }
+            e => {
+                tracing::error!(alert = true, "Failed to send request to enclave: {}", e);
+
+                ServerError::FailedToSendRequestToEnclave
+            }
+        })?;
+
+    match response {
+        EnclaveResponse::ProofVerified(verdict) => Ok(Json(verdict)),
+        EnclaveResponse::Error(error) => {
+            tracing::error!(
+                alert = !error.is_user_error(),
+                "Enclave failed to verify proof: {:?}",
+                error
+            );
+
+            Err(ServerError::EnclaveError(error))
+        }
+        _ => {
+            tracing::error!(
+                alert = true,
+                "Unexpected response from enclave: {:?}",
+                response
+            );
+
+            Err(ServerError::UnexpectedResponseFromEnclave)
         }
     }
+}
 
-    let response = execute_inner(server.clone(), request);
-    let response =
-        stream::once(response).map(|response| Ok(sp1_tee_host::api::result_to_event(response)));
+/// Checks that the request was signed by a whitelisted account.
+#[cfg(feature = "production")]
+async fn authenticate(
+    server: &Server,
+    id: [u8; 32],
+    signature: &alloy::primitives::Signature,
+) -> Result<(), ServerError> {
+    let signer = signature.recover_address_from_msg(id).map_err(|_| {
+        tracing::error!(
+            "Failed to recover signer address, request id: {}",
+            hex::encode(id)
+        );
 
-    Ok(Sse::new(response))
+        ServerError::FailedToAuthenticateRequest
+    })?;
+
+    match server.auth_client.is_whitelisted(signer).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            tracing::error!(
+                "Failed to authenticate request by {:?}: Not whitelisted",
+                signer
+            );
+
+            Err(ServerError::FailedToAuthenticateRequest)
+        }
+        Err(e) => {
+            tracing::error!(
+                alert = true,
+                "Failed to authenticate request by {:?}: {}",
+                signer,
+                e
+            );
+
+            Err(ServerError::FailedToAuthenticateRequest)
+        }
+    }
+}
+
+/// Execute a program on the enclave, streaming the program and stdin from the request body.
+///
+/// The body is the raw program followed by the bincode encoded stdin, the rest of the request
+/// is passed in headers. Unlike `/execute`, the body is never fully buffered on the host,
+/// and the enclave only holds a single copy of it.
+async fn execute_stream(
+    State(server): State<Arc<Server>>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ServerError> {
+    let id: [u8; 32] = hex::decode(header(&headers, REQUEST_ID_HEADER)?)
+        .ok()
+        .and_then(|id| id.try_into().ok())
+        .ok_or(ServerError::InvalidHeader(REQUEST_ID_HEADER))?;
+    let cycle_limit = parse_header::<u64>(&headers, CYCLE_LIMIT_HEADER)?;
+    let program_len = parse_header::<u64>(&headers, PROGRAM_LENGTH_HEADER)?;
+    let stdin_len = parse_header::<u64>(&headers, STDIN_LENGTH_HEADER)?;
+    let domain = signature_domain(&headers, id)?;
+
+    // The body is uploaded to a single enclave, so it cannot be co-signed.
+    if server.cosign_threshold.is_some() {
+        return Err(ServerError::StreamingNotCosigned);
+    }
+
+    #[cfg(feature = "production")]
+    {
+        let signature = parse_header::<alloy::primitives::Signature>(&headers, SIGNATURE_HEADER)?;
+
+        authenticate(&server, id, &signature).await?;
+    }
+
+    // Uploads belong to a connection, so the same stream must be used for the execution.
+    let stream = server.enclave().await.map_err(|e| {
+        tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
+
+        ServerError::FailedToConnectToEnclave
+    })?;
+
+    // Upload before streaming the response, so the client gets a status code if it fails.
+    let (program, stdin) = upload_body(&stream, body, program_len, stdin_len).await?;
+
+    let response = execute_uploaded(
+        server.clone(),
+        stream,
+        id,
+        program,
+        stdin,
+        cycle_limit,
+        domain,
+    );
+    let response = stream::once(response)
+        .flat_map(|response| stream::iter(sp1_tee_host::api::result_to_events(response)).map(Ok));
+
+    // Keep alive events let us notice the client disconnecting, which cancels the execution.
+    Ok(Sse::new(response).keep_alive(KeepAlive::default()))
+}
+
+/// Get a header as a string.
+#[allow(clippy::result_large_err)]
+fn header<'a>(headers: &'a HeaderMap, name: &'static str) -> Result<&'a str, ServerError> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(ServerError::InvalidHeader(name))
+}
+
+/// Get a header, and parse it.
+#[allow(clippy::result_large_err)]
+fn parse_header<T: std::str::FromStr>(
+    headers: &HeaderMap,
+    name: &'static str,
+) -> Result<T, ServerError> {
+    header(headers, name)?
+        .parse()
+        .map_err(|_| ServerError::InvalidHeader(name))
+}
+
+/// Get the domain to bind the signature to, `None` if the request did not opt in.
+///
+/// The domain headers must be set together, and the domain always uses the ID of the request.
+#[allow(clippy::result_large_err)]
+fn signature_domain(
+    headers: &HeaderMap,
+    request_id: [u8; 32],
+) -> Result<Option<SignatureDomain>, ServerError> {
+    if [CHAIN_ID_HEADER, VERIFIER_HEADER, EXPIRES_AT_HEADER]
+        .iter()
+        .all(|name| !headers.contains_key(*name))
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(SignatureDomain {
+        chain_id: parse_header::<u64>(headers, CHAIN_ID_HEADER)?,
+        verifier: parse_header::<alloy::primitives::Address>(headers, VERIFIER_HEADER)?
+            .into_array(),
+        request_id,
+        expires_at: parse_header::<u64>(headers, EXPIRES_AT_HEADER)?,
+    }))
+}
+
+/// Streams the request body into the enclave, as a program upload followed by a stdin upload.
+#[tracing::instrument(skip_all)]
+async fn upload_body(
+    stream: &HostStream,
+    body: Body,
+    program_len: u64,
+    stdin_len: u64,
+) -> Result<(CommittedUpload, CommittedUpload), ServerError> {
+    let mut program = stream.upload(UploadKind::Program, program_len).await?;
+    let mut stdin = stream.upload(UploadKind::Stdin, stdin_len).await?;
+
+    let mut remaining_program = program_len;
+    let mut body = body.into_data_stream();
+
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(ServerError::FailedToReadBody)?;
+
+        // The first `program_len` bytes are the program, the rest is stdin.
+        let split = (remaining_program as usize).min(chunk.len());
+        let (program_bytes, stdin_bytes) = chunk.split_at(split);
+        remaining_program -= split as u64;
+
+        if !program_bytes.is_empty() {
+            program.write(program_bytes).await?;
+        }
+
+        if !stdin_bytes.is_empty() {
+            stdin.write(stdin_bytes).await?;
+        }
+    }
+
+    let program = program.commit().await?;
+    let stdin = stdin.commit().await?;
+
+    tracing::debug!(
+        "Uploaded program: {}, stdin: {}",
+        hex::encode(program.digest()),
+        hex::encode(stdin.digest())
+    );
+
+    Ok((program, stdin))
+}
+
+/// Checks that the program and stdin fit in a single request frame.
+///
+/// # Errors
+/// - [`ServerError::ProgramTooLarge`] - The program alone exceeds the payload frame limit.
+/// - [`ServerError::StdinTooLarge`] - The program and stdin together exceed the payload frame limit.
+#[allow(clippy::result_large_err)]
+fn check_request_size(server: &Server, request: &TEERequest) -> Result<(), ServerError> {
+    let payload_limit = server.request_frame_limits.payload as usize;
+
+    let program_size = request.program.len();
+    if program_size > payload_limit {
+        tracing::warn!("Program too large: {} bytes", program_size);
+
+        return Err(ServerError::ProgramTooLarge(program_size));
+    }
+
+    let stdin_size = bincode::serialized_size(&request.stdin)
+        .map_err(ServerError::FailedToDeserializeRequest)? as usize;
+    if program_size.saturating_add(stdin_size) > payload_limit {
+        tracing::warn!("Stdin too large: {} bytes", stdin_size);
+
+        return Err(ServerError::StdinTooLarge(stdin_size));
+    }
+
+    Ok(())
 }
 
 #[tracing::instrument(skip_all, fields(id = hex::encode(request.id)))]
 async fn execute_inner(
     server: Arc<Server>,
     request: TEERequest,
-) -> Result<TEEResponse, ServerError> {
+    domain: Option<SignatureDomain>,
+) -> Result<ExecutionOutput, ServerError> {
     tracing::info!("Got execution request");
 
-    let _guard = server.execution_mutex.lock().await;
+    // Get the shared connection to the enclave.
+    let stream = server.enclave().await.map_err(|e| {
+        tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
 
-    tracing::info!("Acquired execution gurad");
+        ServerError::FailedToConnectToEnclave
+    })?;
+
+    tracing::debug!("Successfully connected to enclave");
+
+    let execute = async {
+        match server.cosign_threshold {
+            Some(threshold) => cosign(&server, &stream, &request, domain, threshold).await,
+            None => execute_on(&server, &stream, &request, domain)
+                .await
+                .map(|response| (response, None))
+                .map_err(execution_error),
+        }
+    };
 
-    // Open a connection to the enclave.
-    let mut stream = HostStream::new(server.cid, sp1_tee_common::ENCLAVE_PORT)
+    // Send the request to the enclave, and wait for the response.
+    //
+    // Dropping the request on timeout cancels it in the enclave.
+    let execution_start = std::time::Instant::now();
+    let (response, threshold_proof) = tokio::time::timeout(server.execution_timeout, execute)
         .await
+        .map_err(|_| execution_timed_out(&server))??;
+
+    let execution_duration = execution_start.elapsed();
+    tracing::info!(
+        "Execution duration: {:?} seconds",
+        execution_duration.as_secs()
+    );
+
+    tracing::debug!("Successfully received response from enclave");
+
+    let (response, report) = signed_response(response)?;
+
+    if let Some(threshold) = server.cosign_threshold {
+        let signatures = threshold_proof
+            .as_ref()
+            .map_or(0, |proof| proof.signatures().len());
+
+        if signatures < threshold {
+            tracing::error!(
+                alert = true,
+                "Only {} of the required {} enclaves signed the result",
+                signatures,
+                threshold
+            );
+
+            return Err(ServerError::CosigningFailed {
+                threshold,
+                signatures,
+            });
+        }
+    }
+
+    Ok(ExecutionOutput {
+        response,
+        report,
+        threshold_proof,
+    })
+}
+
+/// Runs an execution request on an enclave, trying its program cache first,
+/// to avoid sending the program over vsock.
+async fn execute_on(
+    server: &Server,
+    stream: &HostStream,
+    request: &TEERequest,
+    domain: Option<SignatureDomain>,
+) -> Result<EnclaveResponse, CommunicationError> {
+    let response = stream
+        .request(EnclaveRequest::ExecuteCached {
+            program_hash: program_hash(&request.program),
+            stdin: request.stdin.clone(),
+            cycle_limit: request.cycle_limit,
+            sign_cycles: server.sign_cycles,
+            sign_inputs: server.sign_inputs,
+            domain: domain.clone(),
+        })
+        .await?;
+
+    match response {
+        EnclaveResponse::Error(EnclaveError::ProgramNotCached { .. }) => {
+            tracing::debug!("Program not cached, sending the full program");
+
+            stream
+                .request(EnclaveRequest::Execute {
+                    program: request.program.clone(),
+                    stdin: request.stdin.clone(),
+                    cycle_limit: request.cycle_limit,
+                    sign_cycles: server.sign_cycles,
+                    sign_inputs: server.sign_inputs,
+                    domain,
+                })
+                .await
+        }
+        response => Ok(response),
+    }
+}
+
+/// Runs an execution request on our enclave and every cosigner at once, until `threshold` of them
+/// signed the same message as our enclave.
+///
+/// Returns our enclave's response, and the signatures over its message if it signed a result.
+/// Dropping the remaining executions cancels them, and a cosigner that fails is only logged.
+async fn cosign(
+    server: &Server,
+    stream: &HostStream,
+    request: &TEERequest,
+    domain: Option<SignatureDomain>,
+    threshold: usize,
+) -> Result<(EnclaveResponse, Option<ThresholdProof>), ServerError> {
+    let mut executions = std::iter::once(None)
+        .chain(server.cosigners.iter().map(Some))
+        .map(|cosigner| {
+            let domain = domain.clone();
+
+            async move {
+                let result = async {
+                    let stream = match cosigner {
+                        Some(cosigner) => {
+                            cosigner
+                                .enclave(server.request_frame_limits, server.response_frame_limits)
+                                .await?
+                        }
+                        None => stream.clone(),
+                    };
+
+                    let response = execute_on(server, &stream, request, domain).await?;
+
+                    Ok((stream.enclave_info().tee_version, response))
+                }
+                .await;
+
+                (cosigner, result)
+            }
+        })
+        .collect::<stream::FuturesUnordered<_>>();
+
+    // Our enclave's response, and the digest it signed.
+    let mut ours: Option<(EnclaveResponse, u32, [u8; 32])> = None;
+    let mut signatures: HashMap<[u8; 32], Vec<(Signature, u8)>> = HashMap::new();
+
+    while let Some((cosigner, result)) = executions.next().await {
+        let (tee_version, response) = match (cosigner, result) {
+            (_, Ok(result)) => result,
+            (None, Err(e)) => return Err(execution_error(e)),
+            (Some(cosigner), Err(e)) => {
+                tracing::warn!("Cosigner {} failed: {}", cosigner.addr(), e);
+
+                continue;
+            }
+        };
+
+        let digest = signed_message(tee_version, &response).map(|message| {
+            let digest = message.hash();
+
+            if let EnclaveResponse::SignedPublicValues {
+                signature,
+                recovery_id,
+                ..
+            } = &response
+            {
+                // Only keep signatures a verifier could recover the signer of.
+                if message.recover_signer(signature, *recovery_id).is_ok() {
+                    signatures
+                        .entry(digest)
+                        .or_default()
+                        .push((*signature, *recovery_id));
+                }
+            }
+
+            digest
+        });
+
+        match (cosigner, digest) {
+            // Our enclave did not sign a result, so there is nothing to co-sign.
+            (None, None) => return Ok((response, None)),
+            (None, Some(digest)) => ours = Some((response, tee_version, digest)),
+            (Some(cosigner), None) => {
+                tracing::warn!(
+                    "Cosigner {} did not sign the result: {:?}",
+                    cosigner.addr(),
+                    response
+                );
+            }
+            (Some(_), Some(_)) => {}
+        }
+
+        if let Some((_, _, digest)) = &ours {
+            if signatures.get(digest).map_or(0, Vec::len) >= threshold {
+                break;
+            }
+        }
+    }
+
+    // Our enclave either responded, or its error was returned above.
+    let (response, tee_version, digest) =
+        ours.ok_or(ServerError::FailedToReceiveResponseFromEnclave)?;
+
+    let message =
+        signed_message(tee_version, &response).ok_or(ServerError::UnexpectedResponseFromEnclave)?;
+
+    let proof = ThresholdProof::new(&message, signatures.remove(&digest).unwrap_or_default())
         .map_err(|e| {
-            tracing::error!(alert = true, "Failed to connect to enclave: {}", e);
+            tracing::error!(alert = true, "Failed to build the threshold proof: {}", e);
 
-            ServerError::FailedToConnectToEnclave
+            ServerError::FailedToBuildThresholdProof(e)
         })?;
 
-    tracing::debug!("Successfully connected to enclave");
+    Ok((response, Some(proof)))
+}
 
-    // Setup the request.
-    let request = EnclaveRequest::Execute {
-        program: request.program,
-        stdin: request.stdin,
-        cycle_limit: request.cycle_limit,
+#[tracing::instrument(skip_all, fields(id = hex::encode(id)))]
+async fn execute_uploaded(
+    server: Arc<Server>,
+    stream: HostStream,
+    id: [u8; 32],
+    program: CommittedUpload,
+    stdin: CommittedUpload,
+    cycle_limit: u64,
+    domain: Option<SignatureDomain>,
+) -> Result<ExecutionOutput, ServerError> {
+    tracing::info!("Got streamed execution request");
+
+    let request = EnclaveRequest::ExecuteUploaded {
+        program: program.into_id(),
+        stdin: stdin.into_id(),
+        cycle_limit,
+        sign_cycles: server.sign_cycles,
+        sign_inputs: server.sign_inputs,
+        domain,
     };
 
-    // Send the request to the enclave.
     let execution_start = std::time::Instant::now();
-    stream.send(request).await.map_err(|e| {
-        tracing::error!(alert = true, "Failed to send request to enclave: {}", e);
-
-        ServerError::FailedToSendRequestToEnclave
-    })?;
-
-    tracing::debug!("Successfully sent request to enclave");
+    let response = tokio::time::timeout(server.execution_timeout, stream.request(request))
+        .await
+        .map_err(|_| execution_timed_out(&server))?
+        .map_err(|e| match e {
+            CommunicationError::Disconnected => {
+                tracing::error!(
+                    alert = true,
+                    "Failed to receive response from enclave: {:?}",
+                    e
+                );
 
-    // Receive the response from the enclave.
-    let response = stream.recv().await.map_err(|e| {
-        tracing::error!(
-            alert = true,
-            "Failed to receive response from enclave: {:?}",
-            e
-        );
+                ServerError::FailedToReceiveResponseFromEnclave
+            }
+            e => {
+                tracing::error!(alert = true, "Failed to send request to enclave: {}", e);
 
-        ServerError::FailedToReceiveResponseFromEnclave
-    })?;
+                ServerError::FailedToSendRequestToEnclave
+            }
+        })?;
 
-    let execution_duration = execution_start.elapsed();
     tracing::info!(
         "Execution duration: {:?} seconds",
-        execution_duration.as_secs()
+        execution_start.elapsed().as_secs()
     );
 
-    tracing::debug!("Successfully received response from enclave");
+    let (response, report) = signed_response(response)?;
+
+    Ok(ExecutionOutput {
+        response,
+        report,
+        threshold_proof: None,
+    })
+}
+
+/// Logs and converts the error of sending an execution request to the enclave.
+fn execution_error(e: CommunicationError) -> ServerError {
+    match e {
+        // The program was checked up front, so the stdin is what pushed the frame over the limit.
+        CommunicationError::FrameTooLarge { size, .. } => {
+            tracing::warn!("Request frame too large: {}", e);
+
+            ServerError::StdinTooLarge(size as usize)
+        }
+        CommunicationError::Disconnected => {
+            tracing::error!(
+                alert = true,
+                "Failed to receive response from enclave: {:?}",
+                e
+            );
+
+            ServerError::FailedToReceiveResponseFromEnclave
+        }
+        e => {
+            tracing::error!(alert = true, "Failed to send request to enclave: {}", e);
+
+            ServerError::FailedToSendRequestToEnclave
+        }
+    }
+}
+
+/// Logs and returns the error for an execution that ran past the server's timeout.
+fn execution_timed_out(server: &Server) -> ServerError {
+    tracing::warn!(
+        "Execution timed out after {:?}, cancelling",
+        server.execution_timeout
+    );
+
+    ServerError::ExecutionTimedOut(server.execution_timeout)
+}
 
+/// Converts the enclave's response to an execution request into a [`TEEResponse`],
+/// along with the report of the execution.
+#[allow(clippy::result_large_err)]
+fn signed_response(
+    response: EnclaveResponse,
+) -> Result<(TEEResponse, ExecutionReport), ServerError> {
     match response {
         EnclaveResponse::SignedPublicValues {
             vkey,
             public_values,
             signature,
             recovery_id,
+            report,
         } => {
-            Ok(TEEResponse {
+            tracing::info!(
+                cycles = report.cycles,
+                setup_time_ms = report.setup_time_ms,
+                execute_time_ms = report.execute_time_ms,
+                peak_memory_bytes = report.peak_memory_bytes,
+                "Execution report"
+            );
+
+            let response = TEEResponse {
                 vkey,
                 public_values,
                 signature,
                 // Add 27 to the recovery id, as this is required by Ethereum.
                 recovery_id: recovery_id + 27,
-            })
+            };
+
+            Ok((response, report))
         }
         EnclaveResponse::Error(error) => {
             // This error type is expected, it can happen if the execution fails.
-            tracing::error!("Error during execution from enclave: {:?}", error);
+            tracing::error!(
+                alert = !error.is_user_error(),
+                "Error during execution from enclave: {:?}",
+                error
+            );
 
             Err(ServerError::EnclaveError(error))
         }
+        EnclaveResponse::SignedFailure { error, receipt } => {
+            tracing::info!(
+                cycles = receipt.receipt.cycles,
+                "Program failed with a signed receipt: {:?}",
+                error
+            );
+
+            Err(ServerError::ExecutionFailedWithReceipt {
+                error,
+                receipt: Box::new(receipt),
+            })
+        }
         _ => {
             tracing::error!(
                 alert = true,
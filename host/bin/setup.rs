@@ -24,6 +24,8 @@ use tracing_subscriber::EnvFilter;
     This command will deploy the contracts if the `deploy` flag is set to true.
 
     Otherwise, it will only add the PCRs to the existing contracts, and attempt to register the known certificates.
+
+    Signers flagged by a key rotation are removed once their grace period is over.
 ")]
 struct Args {
     /// Whether or not to deploy the contracts.
@@ -185,11 +187,47 @@ async fn main() {
     let verifier = TEEVerifier::new(deployment.sp1_tee_verifier, provider);
 
     // For each attestation, verify the attestation and add the signer, optionally checking the PCR0.
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("System time is before the unix epoch")
+        .as_secs();
+
     for RawAttestation {
         address,
         attestation,
+        retire_after,
     } in attestations
     {
+        // This signer was rotated out, and its grace period is over.
+        if retire_after.is_some_and(|retire_after| retire_after <= now) {
+            let is_signer = verifier
+                .isSigner(address)
+                .call()
+                .await
+                .expect("Failed to check if signer is registered");
+
+            if !is_signer {
+                continue;
+            }
+
+            if args.register_signers {
+                verifier
+                    .removeSigner(address)
+                    .send()
+                    .await
+                    .expect("Failed send tx to remove signer")
+                    .watch()
+                    .await
+                    .expect("Failed to get confirmation of removing signer");
+
+                println!("Removed rotated signer: {:?}", address);
+            } else {
+                println!("Found rotated signer to remove: {:?}", address);
+            }
+
+            continue;
+        }
+
         // Verify the attestation.
         let doc = match sp1_tee_host::attestations::verify_attestation(&attestation) {
             Ok(doc) => doc,
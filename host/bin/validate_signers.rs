@@ -48,11 +48,13 @@ async fn main() {
             version,
             pcr0,
         } => {
-            sp1_tee_host::attestations::verify_attestation_for_signer(signer, version, &pcr0)
-                .await
-                .unwrap();
+            let user_data =
+                sp1_tee_host::attestations::verify_attestation_for_signer(signer, version, &pcr0)
+                    .await
+                    .unwrap();
 
             println!("Validated signer: {:?}", signer);
+            println!("{}", user_data);
         }
         Command::Contract {
             contract,
@@ -75,8 +77,9 @@ async fn main() {
                 )
                 .await
                 {
-                    Ok(_) => {
+                    Ok(user_data) => {
                         println!("Validated signer: {:?}", signer);
+                        println!("{}", user_data);
                     }
                     // It is expected that some signers will not be for the given version.
                     Err(AttestationVerificationError::VersionMismatch(_, _)) => {
@@ -1,7 +1,81 @@
 pub use sp1_sdk::network::tee::api::{EventPayload, GetAddressResponse, TEERequest, TEEResponse};
 
 #[cfg(feature = "server")]
-use {crate::server::ServerError, axum::response::sse::Event};
+use {
+    crate::server::ServerError,
+    axum::response::sse::Event,
+    sp1_tee_common::{ExecutionReport, SignedFailureReceipt, ThresholdProof},
+};
+
+/// The response of the `/migrate` endpoint, the donor enclave's signing key encrypted to the requester.
+///
+/// Sent as bincode, the body of the request is the requester's attestation document.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct KeyMigrationResponse {
+    /// The donor's attestation, binding its ephemeral key and the requester's.
+    pub attestation: Vec<u8>,
+    /// The signing key, encrypted to the requester's ephemeral key.
+    pub ciphertext: Vec<u8>,
+}
+
+/// The body of the `/verify` endpoint, sent as bincode.
+///
+/// The response is the JSON encoded [`sp1_tee_common::SignedProofVerdict`], if the proof is valid.
+///
+/// NOTE: This is not `Debug`, as [`sp1_sdk::SP1VerifyingKey`] is not.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct VerifyProofRequest {
+    /// The ID of the request, bound in the verdict if it is domain separated.
+    pub id: [u8; 32],
+    /// A compressed, Plonk or Groth16 proof.
+    pub proof: sp1_sdk::SP1ProofWithPublicValues,
+    /// The verifying key of the program the proof is for.
+    pub vkey: sp1_sdk::SP1VerifyingKey,
+    /// The signature over the request ID, used for authentication.
+    pub signature: alloy::primitives::Signature,
+}
+
+/// The response of the `/ready` endpoint, sent with a `503` status until the server is ready.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ReadinessResponse {
+    /// Whether the enclave's self-test passed, and enough cosigners' to meet the co-signing threshold.
+    pub ready: bool,
+    /// The latest self-test of our enclave, `None` if it has not run.
+    pub self_test: Option<sp1_tee_common::SelfTestReport>,
+    /// The latest self-test of each cosigner that has run one, by address.
+    pub cosigners: std::collections::BTreeMap<String, sp1_tee_common::SelfTestReport>,
+}
+
+/// The query of the `/attestation` endpoint.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct AttestationQuery {
+    /// The hex encoded nonce to bind in the attestation document, at most 512 bytes.
+    pub nonce: String,
+}
+
+/// The name of the SSE event carrying the JSON encoded execution report.
+///
+/// It is sent after the result, so clients that only read the first event are unaffected.
+pub const REPORT_EVENT: &str = "report";
+
+/// The name of the SSE event carrying the hex encoded [`sp1_tee_common::ThresholdProof`].
+///
+/// It is sent after the report, when the server co-signs executions with other enclaves.
+pub const THRESHOLD_PROOF_EVENT: &str = "threshold_proof";
+
+/// The name of the SSE event carrying the JSON encoded [`sp1_tee_common::SignedFailureReceipt`].
+///
+/// It is sent after the error, when the program deterministically failed.
+pub const FAILURE_RECEIPT_EVENT: &str = "failure_receipt";
+
+/// The result of a successful execution.
+#[cfg(feature = "server")]
+pub struct ExecutionOutput {
+    pub response: TEEResponse,
+    pub report: ExecutionReport,
+    /// The signatures of every enclave that signed the result, if co-signing is enabled.
+    pub threshold_proof: Option<ThresholdProof>,
+}
 
 #[cfg(feature = "server")]
 pub(crate) fn event_payload_to_event(payload: EventPayload) -> Event {
@@ -14,7 +88,8 @@ pub(crate) fn event_payload_to_event(payload: EventPayload) -> Event {
 pub(crate) fn result_to_event_payload(response: Result<TEEResponse, ServerError>) -> EventPayload {
     match response {
         Ok(response) => EventPayload::Success(response),
-        Err(error) => EventPayload::Error(error.to_string()),
+        // Prefix the message with the error code, so clients can decide whether to retry.
+        Err(error) => EventPayload::Error(format!("{}: {}", error.code(), error)),
     }
 }
 
@@ -22,3 +97,58 @@ pub(crate) fn result_to_event_payload(response: Result<TEEResponse, ServerError>
 pub fn result_to_event(response: Result<TEEResponse, ServerError>) -> Event {
     event_payload_to_event(result_to_event_payload(response))
 }
+
+#[cfg(feature = "server")]
+pub fn report_to_event(report: &ExecutionReport) -> Event {
+    Event::default()
+        .event(REPORT_EVENT)
+        .json_data(report)
+        .expect("Failed to serialize report")
+}
+
+#[cfg(feature = "server")]
+pub fn threshold_proof_to_event(proof: &ThresholdProof) -> Event {
+    Event::default()
+        .event(THRESHOLD_PROOF_EVENT)
+        .data(hex::encode(proof.encode()))
+}
+
+#[cfg(feature = "server")]
+pub fn failure_receipt_to_event(receipt: &SignedFailureReceipt) -> Event {
+    Event::default()
+        .event(FAILURE_RECEIPT_EVENT)
+        .json_data(receipt)
+        .expect("Failed to serialize failure receipt")
+}
+
+/// The events for the result of an execution, followed by its report (and threshold proof) if it succeeded,
+/// or its failure receipt if the program deterministically failed.
+#[cfg(feature = "server")]
+pub fn result_to_events(output: Result<ExecutionOutput, ServerError>) -> Vec<Event> {
+    match output {
+        Ok(output) => {
+            let mut events = vec![
+                result_to_event(Ok(output.response)),
+                report_to_event(&output.report),
+            ];
+
+            if let Some(proof) = &output.threshold_proof {
+                events.push(threshold_proof_to_event(proof));
+            }
+
+            events
+        }
+        Err(ServerError::ExecutionFailedWithReceipt { error, receipt }) => {
+            let receipt_event = failure_receipt_to_event(&receipt);
+
+            vec![
+                result_to_event(Err(ServerError::ExecutionFailedWithReceipt {
+                    error,
+                    receipt,
+                })),
+                receipt_event,
+            ]
+        }
+        Err(error) => vec![result_to_event(Err(error))],
+    }
+}
@@ -2,13 +2,16 @@ use std::time::Duration;
 
 use alloy::hex::FromHexError;
 use alloy::primitives::Address;
-use attestation_doc_validation::error::{AttestError, AttestResult};
+use attestation_doc_validation::error::AttestError;
 use aws_config::{BehaviorVersion, Region};
 use aws_sdk_s3::operation::get_object::GetObjectError;
 use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
 use aws_sdk_s3::primitives::ByteStreamError;
 use aws_sdk_s3::{error::SdkError, operation::put_object::PutObjectError};
-use sp1_tee_common::{CommunicationError, EnclaveRequest, EnclaveResponse};
+use sp1_tee_common::{
+    cose::CoseError, AttestationUserData, AttestedKey, AttestedKeys, CommunicationError,
+    EnclaveRequest, EnclaveResponse, TransportAddr, UserDataError,
+};
 
 use aws_nitro_enclaves_nsm_api::api::AttestationDoc;
 
@@ -18,6 +21,16 @@ use crate::HostStream;
 // Attestations expire every 3 hours and we update every 30 mins.
 pub const ATTESTATION_INTERVAL: Duration = Duration::from_secs(30 * 60);
 
+/// The S3 object metadata flagging a rotated out signer, the unix timestamp (in seconds)
+/// after which it no longer signs, and should be removed from the verifier.
+pub const RETIRE_AFTER_METADATA: &str = "retire-after";
+
+/// The path of a DER encoded root certificate, trusted by [`verify_attestation`] instead of the AWS Nitro root.
+///
+/// This is the root of the enclave's mock NSM, written with `--mock-nsm-root-cert`, for testing off Nitro.
+/// It is only read with the `test-root` feature, which cannot be combined with `production`.
+pub const TEST_ROOT_CERT_ENV: &str = "SP1_TEE_TEST_ROOT_CERT";
+
 /// Creates an S3 client from the environment variables.
 ///
 /// For EC2 instances, the environment variables are set automatically.
@@ -53,11 +66,8 @@ pub(crate) async fn s3_client_read_only() -> aws_sdk_s3::Client {
 
 #[derive(Debug)]
 pub struct SaveAttestationArgs {
-    /// The CID of the enclave to connect to.
-    pub cid: u32,
-
-    /// The port of the enclave to connect to.
-    pub port: u16,
+    /// The address of the enclave to connect to.
+    pub addr: TransportAddr,
 
     /// The S3 Bucket to write to
     pub bucket: String,
@@ -66,8 +76,7 @@ pub struct SaveAttestationArgs {
 impl Default for SaveAttestationArgs {
     fn default() -> Self {
         Self {
-            cid: 10,
-            port: sp1_tee_common::ENCLAVE_PORT,
+            addr: TransportAddr::vsock(sp1_tee_common::ENCLAVE_CID),
             bucket: crate::S3_BUCKET.to_string(),
         }
     }
@@ -91,54 +100,86 @@ pub enum SaveAttestationError {
 
 /// Save the attestation to S3.
 ///
-/// This function will connect to the enclave, request the signing key attestation, and save it to S3.
+/// This function will connect to the enclave, request the signing key attestations, and save them to S3.
+///
+/// If a rotation is in progress, both keys are saved, see [`save_signing_keys`].
 pub async fn save_attestation(args: SaveAttestationArgs) -> Result<(), SaveAttestationError> {
     tracing::debug!("Save attestation args: {:#?}", args);
 
-    let SaveAttestationArgs { cid, port, bucket } = args;
-
-    let s3_client = s3_client_write().await;
+    let SaveAttestationArgs { addr, bucket } = args;
 
     // Connect to the enclave.
-    let mut stream = HostStream::new(cid, port).await?;
+    let stream = HostStream::new(&addr).await?;
 
-    // Request the signing key attestation.
-    let attest_signing_key = EnclaveRequest::AttestSigningKey;
-    let get_public_key = EnclaveRequest::GetPublicKey;
+    tracing::debug!("Connected to enclave: {:?}", stream.enclave_info());
 
-    stream.send(attest_signing_key).await?;
-    stream.send(get_public_key).await?;
-
-    let attestation = match stream.recv().await? {
-        EnclaveResponse::SigningKeyAttestation(attestation) => attestation,
+    let keys = match stream.request(EnclaveRequest::GetSigningKeys).await? {
+        EnclaveResponse::SigningKeys(keys) => keys,
         msg => {
             return Err(SaveAttestationError::UnexpectedMessage(msg.type_of()));
         }
     };
 
-    let public_key = match stream.recv().await? {
-        EnclaveResponse::PublicKey(public_key) => public_key,
-        msg => {
-            return Err(SaveAttestationError::UnexpectedMessage(msg.type_of()));
-        }
-    };
+    save_signing_keys(&keys, &bucket).await
+}
+
+/// Save the attestations of the enclave's signing keys to S3.
+///
+/// If a rotation is in progress, the active key is flagged with [`RETIRE_AFTER_METADATA`],
+/// so it is removed from the verifier once the next key takes over.
+pub async fn save_signing_keys(
+    keys: &AttestedKeys,
+    bucket: &str,
+) -> Result<(), SaveAttestationError> {
+    let s3_client = s3_client_write().await;
+
+    let retire_after = keys.next.as_ref().map(|next| {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("System time is before the unix epoch");
+
+        now.as_secs() + next.activates_in_secs
+    });
 
+    put_attestation(&s3_client, bucket, &keys.active, retire_after).await?;
+
+    if let Some(next) = &keys.next {
+        put_attestation(&s3_client, bucket, &next.key, None).await?;
+    }
+
+    Ok(())
+}
+
+/// Write an attestation to S3, keyed by the address of its public key.
+async fn put_attestation(
+    s3_client: &aws_sdk_s3::Client,
+    bucket: &str,
+    key: &AttestedKey,
+    retire_after: Option<u64>,
+) -> Result<(), SaveAttestationError> {
     // The address of the enclave is the S3 bucket key.
-    let key = ethereum_address_from_encoded_point(&public_key)
+    let address = ethereum_address_from_encoded_point(&key.public_key)
         .ok_or(SaveAttestationError::BadPublicKey)?;
 
-    tracing::info!("Saving attestation to S3 for address: {}", key);
-
-    let key = key.to_string();
+    tracing::info!("Saving attestation to S3 for address: {}", address);
 
-    // Write the attestation to S3.
-    s3_client
+    let mut request = s3_client
         .put_object()
         .bucket(bucket)
-        .key(key)
-        .body(attestation.into())
-        .send()
-        .await?;
+        .key(address.to_string())
+        .body(key.attestation.clone().into());
+
+    if let Some(retire_after) = retire_after {
+        tracing::info!(
+            "Flagging address {} for removal after {}",
+            address,
+            retire_after
+        );
+
+        request = request.metadata(RETIRE_AFTER_METADATA, retire_after.to_string());
+    }
+
+    request.send().await?;
 
     Ok(())
 }
@@ -161,6 +202,8 @@ pub enum GetAttestationError {
 pub struct RawAttestation {
     pub address: Address,
     pub attestation: Vec<u8>,
+    /// The unix timestamp (in seconds) after which the signer should be removed, if it was rotated out.
+    pub retire_after: Option<u64>,
 }
 
 /// Tries to fetch all attestations from S3.
@@ -203,17 +246,41 @@ pub async fn get_raw_attestations() -> Result<Vec<RawAttestation>, GetAttestatio
             .send()
             .await?;
 
+        let retire_after = object
+            .metadata()
+            .and_then(|metadata| metadata.get(RETIRE_AFTER_METADATA))
+            .and_then(|retire_after| retire_after.parse().ok());
+
         let bytes = object.body.collect().await?.to_vec();
 
         attestations.push(RawAttestation {
             address: key_as_address,
             attestation: bytes,
+            retire_after,
         });
     }
 
     Ok(attestations)
 }
 
+/// Fetches the attestation published for `signer` from S3.
+///
+/// # Errors
+/// - [`GetAttestationError::S3GetObjectError`] - There is no attestation for the signer, or S3 failed.
+/// - [`GetAttestationError::ByteStreamError`] - Failed to collect the byte stream.
+pub async fn get_raw_attestation(signer: Address) -> Result<Vec<u8>, GetAttestationError> {
+    let client = s3_client_read_only().await;
+
+    let attestation = client
+        .get_object()
+        .bucket(crate::S3_BUCKET.to_string())
+        .key(signer.to_string())
+        .send()
+        .await?;
+
+    Ok(attestation.body.collect().await?.to_vec())
+}
+
 #[derive(Debug, thiserror::Error)]
 #[allow(clippy::large_enum_variant)]
 pub enum AttestationVerificationError {
@@ -236,42 +303,65 @@ pub enum AttestationVerificationError {
 
     #[error("Version mismatch, expected: {0} found: {1}")]
     VersionMismatch(String, String),
+
+    #[error("Invalid user data on attestation document: {0}")]
+    InvalidUserData(#[from] UserDataError),
+
+    #[error("Failed to verify attestation against the test root: {0}")]
+    TestRootVerificationError(#[from] CoseError),
+
+    #[error("Failed to read the test root certificate: {0}")]
+    TestRootCertError(std::io::Error),
+
+    #[error("Nonce mismatch, expected: {0} found: {1}")]
+    NonceMismatch(String, String),
 }
 
 /// Verifies an attestation for a given signer and hex-encoded PCR0 value.
 ///
+/// Returns the decoded user data, describing how the enclave was built and the policy it signs under.
+///
 /// # Errors
 /// - [`AttestationVerificationError::GetAttestationError`] - Failed to get the attestation.
 /// - [`AttestationVerificationError::Pcr0VerificationError`] - Failed to verify the PCR0 value.
 /// - [`AttestationVerificationError::VerificationError`] - Failed to verify the attestation.
+/// - [`AttestationVerificationError::InvalidUserData`] - Failed to decode the user data.
+/// - [`AttestationVerificationError::VersionMismatch`] - The signer is for a different TEE version.
 pub async fn verify_attestation_for_signer(
     signer: Address,
     version: u32,
     pcr0: &str,
-) -> Result<(), AttestationVerificationError> {
-    let client = s3_client_read_only().await;
-
-    // Fetch the attestation from S3.
-    let attestation = client
-        .get_object()
-        .bucket(crate::S3_BUCKET.to_string())
-        .key(signer.to_string())
-        .send()
-        .await
-        .map_err(GetAttestationError::from)?;
-
-    let bytes = attestation
-        .body
-        .collect()
-        .await
-        .map_err(GetAttestationError::from)?
-        .to_vec();
+) -> Result<AttestationUserData, AttestationVerificationError> {
+    let bytes = get_raw_attestation(signer).await?;
 
     // Verify the attestations root of trust.
     let doc = verify_attestation(bytes.as_ref())?;
 
+    check_signer_attestation(&doc, signer, version, pcr0)
+}
+
+/// Checks a verified attestation is for `signer`, with the given TEE version and hex-encoded PCR0 value.
+///
+/// Returns the decoded user data, see [`verify_attestation_for_signer`].
+///
+/// # Errors
+/// - [`AttestationVerificationError::Pcr0VerificationError`] - Failed to verify the PCR0 value.
+/// - [`AttestationVerificationError::InvalidUserData`] - Failed to decode the user data.
+/// - [`AttestationVerificationError::VersionMismatch`] - The signer is for a different TEE version.
+/// - [`AttestationVerificationError::AddressMismatch`] - The attestation is for another signer.
+#[allow(clippy::result_large_err)]
+pub fn check_signer_attestation(
+    doc: &AttestationDoc,
+    signer: Address,
+    version: u32,
+    pcr0: &str,
+) -> Result<AttestationUserData, AttestationVerificationError> {
     // Verify the PCR0 value.
-    let doc_pcr0 = hex::encode(doc.pcrs[&0].as_ref());
+    let doc_pcr0 = doc
+        .pcrs
+        .get(&0)
+        .map(|pcr0| hex::encode(pcr0.as_slice()))
+        .ok_or(AttestationVerificationError::MissingRequiredField("pcr0"))?;
     if doc_pcr0 != pcr0.replace("0x", "") {
         return Err(AttestationVerificationError::Pcr0VerificationError(
             pcr0.to_string(),
@@ -280,27 +370,19 @@ pub async fn verify_attestation_for_signer(
     }
 
     // Verify the version of the attestation.
-    if doc
-        .user_data
-        .as_ref()
-        .ok_or(AttestationVerificationError::MissingRequiredField(
-            "user_data",
-        ))?
-        != &version.to_le_bytes()
-    {
+    let user_data = AttestationUserData::decode(doc.user_data.as_ref().ok_or(
+        AttestationVerificationError::MissingRequiredField("user_data"),
+    )?)?;
+
+    if user_data.tee_version != version {
         return Err(AttestationVerificationError::VersionMismatch(
             version.to_string(),
-            String::from_utf8(doc.user_data.as_ref().unwrap().to_vec()).unwrap(),
+            user_data.tee_version.to_string(),
         ));
     }
 
     // Verify the address of the attestation.
-    let derived_address = doc
-        .public_key
-        .and_then(|b| crate::ethereum_address_from_sec1_bytes(b.as_ref()))
-        .ok_or(AttestationVerificationError::MissingRequiredField(
-            "public_key",
-        ))?;
+    let derived_address = attested_address(doc)?;
 
     if derived_address != signer {
         return Err(AttestationVerificationError::AddressMismatch(
@@ -309,7 +391,72 @@ pub async fn verify_attestation_for_signer(
         ));
     }
 
-    Ok(())
+    Ok(user_data)
+}
+
+/// Returns the signer of a verified attestation, if it signs with the TEE `version`.
+///
+/// This is how the server's `/signers` endpoint selects the signers to register.
+///
+/// # Errors
+/// - [`AttestationVerificationError::MissingRequiredField`] - The attestation has no valid public key.
+#[allow(clippy::result_large_err)]
+pub fn signer_for_version(
+    doc: &AttestationDoc,
+    version: u32,
+) -> Result<Option<Address>, AttestationVerificationError> {
+    let is_version = doc
+        .user_data
+        .as_ref()
+        .and_then(|user_data| AttestationUserData::decode(user_data).ok())
+        .is_some_and(|user_data| user_data.tee_version == version);
+
+    if !is_version {
+        return Ok(None);
+    }
+
+    attested_address(doc).map(Some)
+}
+
+/// The address of the public key an attestation is for.
+#[allow(clippy::result_large_err)]
+fn attested_address(doc: &AttestationDoc) -> Result<Address, AttestationVerificationError> {
+    doc.public_key
+        .as_ref()
+        .and_then(|public_key| crate::ethereum_address_from_sec1_bytes(public_key.as_ref()))
+        .ok_or(AttestationVerificationError::MissingRequiredField(
+            "public_key",
+        ))
+}
+
+/// Verifies a freshly requested attestation, checking it binds the `nonce` the caller sent with the request.
+///
+/// See the `/attestation` endpoint of the server.
+///
+/// # Errors
+/// - [`AttestationVerificationError::VerificationError`] - Failed to verify the attestation.
+/// - [`AttestationVerificationError::MissingRequiredField`] - The attestation has no nonce.
+/// - [`AttestationVerificationError::NonceMismatch`] - The attestation binds a different nonce.
+#[allow(clippy::result_large_err)]
+pub fn verify_attestation_with_nonce(
+    attestation: &[u8],
+    nonce: &[u8],
+) -> Result<AttestationDoc, AttestationVerificationError> {
+    let doc = verify_attestation(attestation)?;
+
+    let doc_nonce = doc
+        .nonce
+        .as_ref()
+        .ok_or(AttestationVerificationError::MissingRequiredField("nonce"))?;
+
+    if doc_nonce.as_ref() != nonce {
+        return Err(AttestationVerificationError::NonceMismatch(
+            hex::encode(nonce),
+            hex::encode(doc_nonce),
+        ));
+    }
+
+    Ok(doc)
 }
 
 /// Verifies an attestation, this should be the COSESign1 attestation from the enclave.
@@ -322,6 +469,187 @@ pub async fn verify_attestation_for_signer(
 ///
 /// This function is the "low-level" verification of the root of trust of the attestation.
 /// For protocol level verification, see [`verify_attestation_for_signer`].
-pub fn verify_attestation(attestation: &[u8]) -> AttestResult<AttestationDoc> {
-    attestation_doc_validation::validate_and_parse_attestation_doc(attestation)
+///
+/// With the `test-root` feature, if [`TEST_ROOT_CERT_ENV`] is set, the attestation must be issued by the test root instead.
+#[allow(clippy::result_large_err)]
+pub fn verify_attestation(
+    attestation: &[u8],
+) -> Result<AttestationDoc, AttestationVerificationError> {
+    #[cfg(feature = "test-root")]
+    if let Some(path) = std::env::var_os(TEST_ROOT_CERT_ENV) {
+        tracing::warn!(
+            "INSECURE: Verifying attestations against the test root {:?} instead of the AWS Nitro root, \
+            the attestation proves nothing about the enclave",
+            path
+        );
+
+        let root = std::fs::read(path).map_err(AttestationVerificationError::TestRootCertError)?;
+
+        return Ok(sp1_tee_common::cose::verify(attestation, &root)?);
+    }
+
+    Ok(attestation_doc_validation::validate_and_parse_attestation_doc(attestation)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_nitro_enclaves_nsm_api::api::Digest;
+    use k256::ecdsa::SigningKey;
+    use sp1_tee_common::{cose, test_ca::TestCa, SIGNATURE_FORMAT, USER_DATA_VERSION};
+    use std::collections::BTreeMap;
+
+    const TEE_VERSION: u32 = 1;
+    const PCR0: &str = "0x000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000";
+
+    fn user_data(tee_version: u32) -> AttestationUserData {
+        AttestationUserData {
+            version: USER_DATA_VERSION,
+            tee_version,
+            git_revision: None,
+            cargo_lock_hash: None,
+            max_allowed_cycles: Some(1_000_000),
+            unconstrained_cycle_limit: None,
+            signature_format: Some(SIGNATURE_FORMAT),
+            max_execution_secs: None,
+        }
+    }
+
+    /// The key of the `n`th enclave.
+    fn signing_key(n: u8) -> SigningKey {
+        SigningKey::from_slice(&[n; 32]).unwrap()
+    }
+
+    fn address(key: &SigningKey) -> Address {
+        ethereum_address_from_encoded_point(&key.verifying_key().to_encoded_point(false)).unwrap()
+    }
+
+    /// Attests to `key` as the mock NSM does, and stores the attestation under the key's address, as in S3.
+    fn attest(ca: &TestCa, key: &SigningKey, tee_version: u32) -> RawAttestation {
+        let doc = AttestationDoc::new(
+            "mock-nsm".to_string(),
+            Digest::SHA384,
+            0,
+            (0..3)
+                .map(|index| (index, vec![0; 48]))
+                .collect::<BTreeMap<_, _>>(),
+            ca.leaf_cert().to_vec(),
+            vec![ca.root_cert().to_vec()],
+            Some(user_data(tee_version).encode()),
+            None,
+            Some(
+                key.verifying_key()
+                    .to_encoded_point(false)
+                    .as_bytes()
+                    .to_vec(),
+            ),
+        );
+
+        RawAttestation {
+            address: address(key),
+            attestation: cose::sign(&doc, ca.leaf_key()).unwrap(),
+            retire_after: None,
+        }
+    }
+
+    /// Selects the signers to register from the stored attestations, as the `/signers` endpoint does
+    /// with the `test-root` feature.
+    fn signers(attestations: &[RawAttestation], root: &[u8]) -> Vec<Address> {
+        attestations
+            .iter()
+            .filter_map(|raw| cose::verify(&raw.attestation, root).ok())
+            .filter_map(|doc| signer_for_version(&doc, TEE_VERSION).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn registers_attested_signers() {
+        let ca = TestCa::generate().unwrap();
+        let other_ca = TestCa::generate().unwrap();
+
+        let attestations = vec![
+            attest(&ca, &signing_key(1), TEE_VERSION),
+            attest(&ca, &signing_key(2), TEE_VERSION),
+            // Another TEE version is registered with another verifier.
+            attest(&ca, &signing_key(3), TEE_VERSION + 1),
+            // Not issued by the trusted root.
+            attest(&other_ca, &signing_key(4), TEE_VERSION),
+        ];
+
+        assert_eq!(
+            signers(&attestations, ca.root_cert()),
+            [address(&signing_key(1)), address(&signing_key(2))]
+        );
+
+        // Each registered signer validates on its own.
+        for raw in &attestations[..2] {
+            let doc = cose::verify(&raw.attestation, ca.root_cert()).unwrap();
+
+            assert_eq!(
+                check_signer_attestation(&doc, raw.address, TEE_VERSION, PCR0).unwrap(),
+                user_data(TEE_VERSION)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_pcr0s() {
+        let ca = TestCa::generate().unwrap();
+        let raw = attest(&ca, &signing_key(1), TEE_VERSION);
+        let doc = cose::verify(&raw.attestation, ca.root_cert()).unwrap();
+
+        let pcr0 = PCR0.replace("0x00", "0x01");
+
+        assert!(matches!(
+            check_signer_attestation(&doc, raw.address, TEE_VERSION, &pcr0),
+            Err(AttestationVerificationError::Pcr0VerificationError(_, _))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let ca = TestCa::generate().unwrap();
+        let raw = attest(&ca, &signing_key(1), TEE_VERSION + 1);
+        let doc = cose::verify(&raw.attestation, ca.root_cert()).unwrap();
+
+        assert!(matches!(
+            check_signer_attestation(&doc, raw.address, TEE_VERSION, PCR0),
+            Err(AttestationVerificationError::VersionMismatch(_, _))
+        ));
+    }
+
+    #[test]
+    fn rejects_attestations_stored_under_another_signer() {
+        let ca = TestCa::generate().unwrap();
+        let raw = attest(&ca, &signing_key(1), TEE_VERSION);
+        let doc = cose::verify(&raw.attestation, ca.root_cert()).unwrap();
+
+        assert!(matches!(
+            check_signer_attestation(&doc, address(&signing_key(2)), TEE_VERSION, PCR0),
+            Err(AttestationVerificationError::AddressMismatch(_, _))
+        ));
+    }
+
+    #[test]
+    fn rejects_attestations_without_a_public_key() {
+        let ca = TestCa::generate().unwrap();
+        let doc = AttestationDoc::new(
+            "mock-nsm".to_string(),
+            Digest::SHA384,
+            0,
+            BTreeMap::from([(0, vec![0; 48])]),
+            ca.leaf_cert().to_vec(),
+            vec![ca.root_cert().to_vec()],
+            Some(user_data(TEE_VERSION).encode()),
+            None,
+            None,
+        );
+
+        assert!(matches!(
+            signer_for_version(&doc, TEE_VERSION),
+            Err(AttestationVerificationError::MissingRequiredField(
+                "public_key"
+            ))
+        ));
+    }
 }
@@ -7,6 +7,11 @@ mod bindings {
             /// @dev Only the owner or the manager can add a signer.
             function addSigner(address signer) external;
 
+            /// @notice Removes a signer from the list of signers.
+            ///
+            /// @dev Only the owner can remove a signer.
+            function removeSigner(address signer) external;
+
             /// @notice Returns the list of signers.
             function getSigners() external view returns (address[] memory);
 
@@ -0,0 +1,425 @@
+//! Computes the measurements of an Enclave Image File, without `nitro-cli`.
+//!
+//! An EIF is a fixed size header, followed by sections. Each section is a header and its data:
+//!
+//! ```text
+//! magic (4) || version (2) || flags (2) || default_mem (8) || default_cpus (8) || reserved (2)
+//!     || num_sections (2) || section_offsets (8 * 32) || section_sizes (8 * 32) || unused (4) || crc32 (4)
+//!
+//! section_type (2) || flags (2) || section_size (8) || data
+//! ```
+//!
+//! All integers are big endian. The PCRs are computed like `nitro-cli describe-eif`,
+//! each is a SHA-384 PCR extended once from zero, `sha384(0^48 || sha384(data))`:
+//!
+//! - PCR0, the image: the kernel, the cmdline, and every ramdisk.
+//! - PCR1, the kernel and bootstrap: the kernel, the cmdline, and the first ramdisk.
+//! - PCR2, the application: every ramdisk after the first.
+//!
+//! Like `nitro-cli`, sections are read back to back from the end of the header, ignoring the offsets in it.
+//! The signature and metadata sections are not measured.
+
+use sha2::{Digest, Sha384};
+use std::path::Path;
+
+/// The magic bytes at the start of every EIF, `.eif` in ASCII.
+pub const EIF_MAGIC: [u8; 4] = *b".eif";
+
+/// The number of section offsets and sizes in the header, whether or not they are used.
+const MAX_NUM_SECTIONS: usize = 32;
+
+/// The size of the EIF header, in bytes.
+const HEADER_LEN: usize = 4 + 2 + 2 + 8 + 8 + 2 + 2 + 8 * MAX_NUM_SECTIONS * 2 + 4 + 4;
+
+/// The size of a section header, in bytes.
+const SECTION_HEADER_LEN: usize = 2 + 2 + 8;
+
+/// The length of a SHA-384 PCR, in bytes.
+pub const PCR_LEN: usize = 48;
+
+/// The kind of an EIF section, as stored in its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum SectionKind {
+    Kernel = 1,
+    Cmdline = 2,
+    Ramdisk = 3,
+    Signature = 4,
+    Metadata = 5,
+}
+
+impl TryFrom<u16> for SectionKind {
+    type Error = EifError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(SectionKind::Kernel),
+            2 => Ok(SectionKind::Cmdline),
+            3 => Ok(SectionKind::Ramdisk),
+            4 => Ok(SectionKind::Signature),
+            5 => Ok(SectionKind::Metadata),
+            _ => Err(EifError::InvalidSection(value)),
+        }
+    }
+}
+
+/// The PCRs of an enclave booted from an EIF, as they appear in its attestation documents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EifMeasurements {
+    /// The EIF format version.
+    pub version: u16,
+    pub pcr0: [u8; PCR_LEN],
+    pub pcr1: [u8; PCR_LEN],
+    pub pcr2: [u8; PCR_LEN],
+}
+
+impl EifMeasurements {
+    /// Reads and measures the EIF at `path`.
+    ///
+    /// # Errors
+    /// - [`EifError::Io`] - The file could not be read.
+    /// - See [`EifMeasurements::measure`].
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, EifError> {
+        Self::measure(&std::fs::read(path)?)
+    }
+
+    /// Measures an EIF.
+    ///
+    /// # Errors
+    /// - [`EifError::NotAnEif`] - The bytes do not start with [`EIF_MAGIC`].
+    /// - [`EifError::Truncated`] - The header or a section extends past the end of the bytes.
+    /// - [`EifError::InvalidSection`] - A section has an unknown type.
+    /// - [`EifError::MissingSection`] - There is no kernel, cmdline or ramdisk.
+    pub fn measure(eif: &[u8]) -> Result<Self, EifError> {
+        if eif.len() < HEADER_LEN {
+            return Err(EifError::Truncated("header"));
+        }
+
+        if eif[..4] != EIF_MAGIC {
+            return Err(EifError::NotAnEif);
+        }
+
+        let version = read_u16(eif, 4);
+
+        let mut image = Sha384::new();
+        let mut bootstrap = Sha384::new();
+        let mut app = Sha384::new();
+
+        let mut kernel = false;
+        let mut cmdline = false;
+        let mut ramdisks = 0;
+
+        let mut offset = HEADER_LEN;
+
+        // Trailing bytes too short for a section header are ignored, as `nitro-cli` does.
+        while offset + SECTION_HEADER_LEN <= eif.len() {
+            let data = section(eif, offset)?;
+            offset += SECTION_HEADER_LEN + data.bytes.len();
+
+            match data.kind {
+                SectionKind::Kernel | SectionKind::Cmdline => {
+                    image.update(data.bytes);
+                    bootstrap.update(data.bytes);
+
+                    kernel |= data.kind == SectionKind::Kernel;
+                    cmdline |= data.kind == SectionKind::Cmdline;
+                }
+                SectionKind::Ramdisk => {
+                    image.update(data.bytes);
+
+                    // The first ramdisk bootstraps the enclave, the rest hold the application.
+                    if ramdisks == 0 {
+                        bootstrap.update(data.bytes);
+                    } else {
+                        app.update(data.bytes);
+                    }
+
+                    ramdisks += 1;
+                }
+                SectionKind::Signature | SectionKind::Metadata => {}
+            }
+        }
+
+        if !kernel {
+            return Err(EifError::MissingSection("kernel"));
+        }
+
+        if !cmdline {
+            return Err(EifError::MissingSection("cmdline"));
+        }
+
+        if ramdisks == 0 {
+            return Err(EifError::MissingSection("ramdisk"));
+        }
+
+        Ok(Self {
+            version,
+            pcr0: extend(image),
+            pcr1: extend(bootstrap),
+            pcr2: extend(app),
+        })
+    }
+
+    /// The measured PCRs, by index.
+    pub fn pcrs(&self) -> [(usize, &[u8; PCR_LEN]); 3] {
+        [(0, &self.pcr0), (1, &self.pcr1), (2, &self.pcr2)]
+    }
+}
+
+/// A section of an EIF, borrowed from its bytes.
+struct Section<'a> {
+    kind: SectionKind,
+    bytes: &'a [u8],
+}
+
+/// Reads the section whose header starts at `start`, which must fit in `eif`.
+fn section(eif: &[u8], start: usize) -> Result<Section<'_>, EifError> {
+    let data_start = start + SECTION_HEADER_LEN;
+
+    let kind = SectionKind::try_from(read_u16(eif, start))?;
+    let len = read_u64(eif, start + 4);
+
+    let data_end = usize::try_from(len)
+        .ok()
+        .and_then(|len| data_start.checked_add(len))
+        .filter(|end| *end <= eif.len())
+        .ok_or(EifError::Truncated("section data"))?;
+
+    Ok(Section {
+        kind,
+        bytes: &eif[data_start..data_end],
+    })
+}
+
+/// Extends a zeroed PCR with the hash of everything written to `hasher`.
+fn extend(hasher: Sha384) -> [u8; PCR_LEN] {
+    let mut pcr = Sha384::new();
+    pcr.update([0; PCR_LEN]);
+    pcr.update(hasher.finalize());
+
+    pcr.finalize().into()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(
+        bytes[offset..offset + 8]
+            .try_into()
+            .expect("slice is 8 bytes"),
+    )
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EifError {
+    #[error("Failed to read the EIF: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Not an EIF, the magic bytes are missing")]
+    NotAnEif,
+
+    #[error("The EIF is truncated, the {0} extends past the end of the file")]
+    Truncated(&'static str),
+
+    #[error("Invalid section type: {0}")]
+    InvalidSection(u16),
+
+    #[error("The EIF has no {0} section")]
+    MissingSection(&'static str),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an EIF with the sections, in order.
+    fn eif(sections: &[(u16, &[u8])]) -> Vec<u8> {
+        let mut eif = vec![0; HEADER_LEN];
+        eif[..4].copy_from_slice(&EIF_MAGIC);
+        eif[4..6].copy_from_slice(&4u16.to_be_bytes());
+
+        for (kind, data) in sections {
+            eif.extend_from_slice(&kind.to_be_bytes());
+            eif.extend_from_slice(&0u16.to_be_bytes());
+            eif.extend_from_slice(&(data.len() as u64).to_be_bytes());
+            eif.extend_from_slice(data);
+        }
+
+        eif
+    }
+
+    /// `sha384(0^48 || sha384(data))`, as `nitro-cli` computes it.
+    fn pcr(data: &[&[u8]]) -> [u8; PCR_LEN] {
+        let extended = [&[0; PCR_LEN][..], &Sha384::digest(data.concat())].concat();
+
+        Sha384::digest(extended).into()
+    }
+
+    const KERNEL: u16 = SectionKind::Kernel as u16;
+    const CMDLINE: u16 = SectionKind::Cmdline as u16;
+    const RAMDISK: u16 = SectionKind::Ramdisk as u16;
+
+    #[test]
+    fn measures_the_pcrs() {
+        let measurements = EifMeasurements::measure(&eif(&[
+            (KERNEL, b"kernel"),
+            (CMDLINE, b"cmdline"),
+            (RAMDISK, b"bootstrap"),
+            (RAMDISK, b"app"),
+            (RAMDISK, b"more app"),
+        ]))
+        .unwrap();
+
+        assert_eq!(measurements.version, 4);
+        assert_eq!(
+            measurements.pcr0,
+            pcr(&[b"kernel", b"cmdline", b"bootstrap", b"app", b"more app"])
+        );
+        assert_eq!(
+            measurements.pcr1,
+            pcr(&[b"kernel", b"cmdline", b"bootstrap"])
+        );
+        assert_eq!(measurements.pcr2, pcr(&[b"app", b"more app"]));
+    }
+
+    #[test]
+    fn the_application_only_changes_pcr0_and_pcr2() {
+        let measure = |app: &[u8]| {
+            EifMeasurements::measure(&eif(&[
+                (KERNEL, b"kernel"),
+                (CMDLINE, b"cmdline"),
+                (RAMDISK, b"bootstrap"),
+                (RAMDISK, app),
+            ]))
+            .unwrap()
+        };
+
+        let a = measure(b"app a");
+        let b = measure(b"app b");
+
+        assert_ne!(a.pcr0, b.pcr0);
+        assert_eq!(a.pcr1, b.pcr1);
+        assert_ne!(a.pcr2, b.pcr2);
+    }
+
+    #[test]
+    fn signatures_and_metadata_are_not_measured() {
+        let plain = eif(&[
+            (KERNEL, b"kernel"),
+            (CMDLINE, b"cmdline"),
+            (RAMDISK, b"bootstrap"),
+        ]);
+        let signed = eif(&[
+            (KERNEL, b"kernel"),
+            (CMDLINE, b"cmdline"),
+            (RAMDISK, b"bootstrap"),
+            (SectionKind::Signature as u16, b"signature"),
+            (SectionKind::Metadata as u16, b"{}"),
+        ]);
+
+        assert_eq!(
+            EifMeasurements::measure(&plain).unwrap(),
+            EifMeasurements::measure(&signed).unwrap()
+        );
+    }
+
+    #[test]
+    fn short_trailing_bytes_are_ignored() {
+        let mut image = eif(&[
+            (KERNEL, b"kernel"),
+            (CMDLINE, b"cmdline"),
+            (RAMDISK, b"bootstrap"),
+        ]);
+        let measurements = EifMeasurements::measure(&image).unwrap();
+
+        image.extend_from_slice(&[0xff; SECTION_HEADER_LEN - 1]);
+
+        assert_eq!(EifMeasurements::measure(&image).unwrap(), measurements);
+    }
+
+    #[test]
+    fn rejects_invalid_images() {
+        let valid = eif(&[
+            (KERNEL, b"kernel"),
+            (CMDLINE, b"cmdline"),
+            (RAMDISK, b"bootstrap"),
+        ]);
+
+        assert!(matches!(
+            EifMeasurements::measure(&valid[..HEADER_LEN - 1]),
+            Err(EifError::Truncated("header"))
+        ));
+
+        let mut magic = valid.clone();
+        magic[0] = b'x';
+        assert!(matches!(
+            EifMeasurements::measure(&magic),
+            Err(EifError::NotAnEif)
+        ));
+
+        assert!(matches!(
+            EifMeasurements::measure(&valid[..valid.len() - 1]),
+            Err(EifError::Truncated("section data"))
+        ));
+
+        // A section length that overflows is truncated, not a panic.
+        let mut overflow = eif(&[(KERNEL, b"kernel")]);
+        overflow[HEADER_LEN + 4..HEADER_LEN + 12].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(matches!(
+            EifMeasurements::measure(&overflow),
+            Err(EifError::Truncated("section data"))
+        ));
+
+        assert!(matches!(
+            EifMeasurements::measure(&eif(&[(KERNEL, b"kernel"), (9, b"unknown")])),
+            Err(EifError::InvalidSection(9))
+        ));
+    }
+
+    #[test]
+    fn requires_the_boot_sections() {
+        let missing = |sections: &[(u16, &[u8])]| match EifMeasurements::measure(&eif(sections)) {
+            Err(EifError::MissingSection(section)) => section,
+            result => panic!("Expected a missing section, got {:?}", result),
+        };
+
+        assert_eq!(
+            missing(&[(CMDLINE, b"cmdline"), (RAMDISK, b"bootstrap")]),
+            "kernel"
+        );
+        assert_eq!(
+            missing(&[(KERNEL, b"kernel"), (RAMDISK, b"bootstrap")]),
+            "cmdline"
+        );
+        assert_eq!(
+            missing(&[(KERNEL, b"kernel"), (CMDLINE, b"cmdline")]),
+            "ramdisk"
+        );
+    }
+
+    #[test]
+    fn measures_files() {
+        let image = eif(&[
+            (KERNEL, b"kernel"),
+            (CMDLINE, b"cmdline"),
+            (RAMDISK, b"bootstrap"),
+        ]);
+
+        let path = std::env::temp_dir().join(format!("sp1-tee-eif-{}.eif", std::process::id()));
+        std::fs::write(&path, &image).unwrap();
+
+        let measurements = EifMeasurements::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            measurements.unwrap(),
+            EifMeasurements::measure(&image).unwrap()
+        );
+        assert!(matches!(
+            EifMeasurements::from_file(&path),
+            Err(EifError::Io(_))
+        ));
+    }
+}
@@ -16,6 +16,9 @@ pub mod contract;
 #[cfg(feature = "attestations")]
 pub use contract::TEEVerifier;
 
+/// Computes the PCRs of an Enclave Image File, to check them against published attestations.
+pub mod eif;
+
 /// The API for interacting with the host server.
 #[cfg(any(feature = "server", feature = "client"))]
 pub mod api;
@@ -29,6 +32,9 @@ pub use server::stream::HostStream;
 #[cfg(feature = "client")]
 pub use sp1_sdk::network::tee::client::{Client, ClientError};
 
+#[cfg(all(feature = "production", feature = "test-root"))]
+compile_error!("The `test-root` feature trusts a test CA, it cannot be enabled with `production`.");
+
 #[cfg(feature = "production")]
 pub const S3_BUCKET: &str = "sp1-tee-attestations";
 #[cfg(not(feature = "production"))]
@@ -77,7 +83,7 @@ pub fn ethereum_address_from_encoded_point(encoded_point: &k256::EncodedPoint) -
 ///
 /// SEC1 bytes are of the form:
 ///
-/// ```
+/// ```text
 /// [ 0x04 || x || y ]
 /// ```
 ///
@@ -1,24 +1,80 @@
 #[cfg(feature = "production")]
 use auth::AuthClient;
 
+use crate::api::ReadinessResponse;
+use cosign::Cosigner;
+
 use axum::{http::StatusCode, response::IntoResponse, response::Response};
 use clap::Parser;
+use migration::MigrationError;
+use sealing::SealingError;
+use selftest::record_self_test;
 use serde::Deserialize;
-use std::{path::Path, sync::Arc, time::Duration};
-
+use sp1_tee_common::{
+    CommunicationError, EnclaveError, FrameLimits, SelfTestReport, SignedFailureReceipt,
+    ThresholdError, TransportAddr, DEFAULT_REQUEST_FRAME_LIMITS, DEFAULT_RESPONSE_FRAME_LIMITS,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use stream::HostStream;
+use upload::UploadError;
+
+pub mod cosign;
+pub mod migration;
+pub mod sealing;
+pub mod selftest;
 pub mod stream;
+pub mod upload;
 
 #[cfg(feature = "production")]
 pub mod auth;
 
+/// How long to wait before running a failed self-test again.
+const SELF_TEST_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
 /// The directory of the manifest file.
 ///
 /// Used for locating the enclave.sh script.
 const MANIFEST_DIR: &str = env!("CARGO_MANIFEST_DIR");
 
 pub struct Server {
-    pub execution_mutex: tokio::sync::Mutex<()>,
-    pub cid: u32,
+    /// The address of the enclave.
+    pub enclave_addr: TransportAddr,
+    /// The limits for requests sent to the enclave.
+    pub request_frame_limits: FrameLimits,
+    /// The limits for responses received from the enclave.
+    pub response_frame_limits: FrameLimits,
+    /// How long an execution may run before it is cancelled.
+    pub execution_timeout: Duration,
+    /// Whether the enclave should include the cycle count in the signed message.
+    pub sign_cycles: bool,
+    /// Whether the enclave should include the program and stdin hashes in the signed message.
+    pub sign_inputs: bool,
+    /// Where the enclave's sealed signing key is persisted, if sealing is enabled.
+    pub sealed_key_path: Option<PathBuf>,
+    /// The URL of a server whose enclave should donate its signing key to ours.
+    pub migrate_key_from: Option<String>,
+    /// Whether our enclave may donate its signing key, through the `/migrate` endpoint.
+    pub allow_key_migration: bool,
+    /// Whether our enclave has received its signing key from the donor.
+    key_migrated: AtomicBool,
+    /// The connection to the enclave, shared by all requests.
+    ///
+    /// Lazily (re)connected by [`Server::enclave`].
+    enclave: tokio::sync::Mutex<Option<HostStream>>,
+    /// The report of the enclave's latest self-test, `None` if it has not run.
+    self_test: Mutex<Option<SelfTestReport>>,
+    /// Independent enclaves that run every execution alongside ours.
+    pub cosigners: Vec<Cosigner>,
+    /// The number of enclaves, including ours, that must sign the same result, `None` if co-signing is disabled.
+    pub cosign_threshold: Option<usize>,
     #[cfg(feature = "production")]
     pub auth_client: AuthClient,
 }
@@ -27,6 +83,10 @@ impl Server {
     /// Create a new server.
     ///
     /// This function will block and start the enclave and spawn a task to save attestations to S3.
+    ///
+    /// Attestations are only saved once the enclave's signing key has been restored,
+    /// so a key that is about to be replaced is never registered as a signer,
+    /// and once the enclave's self-test has passed.
     pub fn new(args: &ServerArgs) -> Arc<Self> {
         #[cfg(feature = "production")]
         {
@@ -35,22 +95,198 @@ impl Server {
             }
         }
 
-        // Blocking start the enclave.
-        start_enclave(args);
+        let enclave_addr = args.enclave_addr();
+
+        // Blocking start the enclave, unless its managed outside of Nitro.
+        if args.manages_enclave() {
+            start_enclave(args);
+        } else {
+            tracing::info!("Using an externally managed enclave at {}", enclave_addr);
+        }
 
-        // Spawn a task to save attestations to S3.
-        spawn_attestation_task(
-            args.enclave_cid,
-            sp1_tee_common::ENCLAVE_PORT,
-            crate::attestations::ATTESTATION_INTERVAL,
-        );
+        let enclaves = args.cosigner_enclave_addrs.len() + 1;
+        let cosign_threshold = (enclaves > 1).then(|| args.cosign_threshold.unwrap_or(enclaves));
 
-        Arc::new(Self {
-            execution_mutex: tokio::sync::Mutex::new(()),
-            cid: args.enclave_cid,
+        if let Some(threshold) = cosign_threshold {
+            if threshold == 0 || threshold > enclaves {
+                panic!(
+                    "The cosign threshold must be between 1 and the number of enclaves ({}), found {}",
+                    enclaves, threshold
+                );
+            }
+        }
+
+        let server = Arc::new(Self {
+            enclave_addr,
+            request_frame_limits: args.request_frame_limits(),
+            response_frame_limits: args.response_frame_limits(),
+            execution_timeout: Duration::from_secs(args.execution_timeout_secs),
+            sign_cycles: args.sign_cycles,
+            sign_inputs: args.sign_inputs,
+            sealed_key_path: args.sealed_key_path.clone(),
+            migrate_key_from: args.migrate_key_from.clone(),
+            allow_key_migration: args.allow_key_migration,
+            key_migrated: AtomicBool::new(false),
+            enclave: tokio::sync::Mutex::new(None),
+            self_test: Mutex::new(None),
+            cosigners: args
+                .cosigner_enclave_addrs
+                .iter()
+                .cloned()
+                .map(Cosigner::new)
+                .collect(),
+            cosign_threshold,
             #[cfg(feature = "production")]
             auth_client: AuthClient::new(&args.prover_network_url),
-        })
+        });
+
+        // Connect to the enclave (restoring its signing key and running its self-test),
+        // then spawn a task to save attestations to S3 once the self-test passed.
+        tokio::spawn({
+            let server = server.clone();
+
+            async move {
+                const TRY_AGAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+                while let Err(e) = server.enclave().await {
+                    tracing::warn!("Failed to connect to enclave, retrying: {}", e);
+
+                    tokio::time::sleep(TRY_AGAIN_INTERVAL).await;
+                }
+
+                while !server.self_test_passed() {
+                    tokio::time::sleep(SELF_TEST_RETRY_INTERVAL).await;
+
+                    server.run_self_test().await;
+                }
+
+                spawn_attestation_task(
+                    server.enclave_addr.clone(),
+                    crate::attestations::ATTESTATION_INTERVAL,
+                );
+            }
+        });
+
+        // The cosigners' keys must be registered as well, each once its own self-test passed.
+        for index in 0..server.cosigners.len() {
+            let server = server.clone();
+
+            tokio::spawn(async move {
+                let cosigner = &server.cosigners[index];
+
+                loop {
+                    cosigner
+                        .run_self_test(server.request_frame_limits, server.response_frame_limits)
+                        .await;
+
+                    if cosigner.self_test_passed() {
+                        break;
+                    }
+
+                    tokio::time::sleep(SELF_TEST_RETRY_INTERVAL).await;
+                }
+
+                spawn_attestation_task(
+                    cosigner.addr().clone(),
+                    crate::attestations::ATTESTATION_INTERVAL,
+                );
+            });
+        }
+
+        server
+    }
+
+    /// Get the shared connection to the enclave, reconnecting if it was closed.
+    ///
+    /// If sealing is enabled, the signing key is restored on every new connection,
+    /// as the enclave may have restarted since the last one. If migration is enabled, the key is migrated
+    /// on the first connection only, as the enclave is started once, with the server, and a donor
+    /// may have rotated its key since. The connection is shared once its key is set.
+    ///
+    /// The self-test is run again on every new connection, after the key is restored so it signs
+    /// with the key that will be advertised. It runs outside the connection lock, so it does not
+    /// hold up other requests, and the server is not [ready](Self::readiness) until it passed.
+    pub async fn enclave(&self) -> Result<HostStream, EnclaveConnectionError> {
+        let stream = {
+            let mut enclave = self.enclave.lock().await;
+
+            if let Some(stream) = enclave.as_ref().filter(|stream| !stream.is_closed()) {
+                return Ok(stream.clone());
+            }
+
+            let stream = HostStream::connect_with_limits(
+                &self.enclave_addr,
+                self.request_frame_limits,
+                self.response_frame_limits,
+            )
+            .await?;
+
+            tracing::info!("Connected to enclave: {:?}", stream.enclave_info());
+
+            if let Some(path) = &self.sealed_key_path {
+                stream.restore_signing_key(path).await?;
+            } else if let Some(donor_url) = &self.migrate_key_from {
+                if !self.key_migrated.load(Ordering::Acquire) {
+                    stream.migrate_signing_key(donor_url).await?;
+                    self.key_migrated.store(true, Ordering::Release);
+                }
+            }
+
+            // The enclave may have restarted, its previous self-test no longer applies.
+            *self.self_test.lock().expect("Self-test lock poisoned") = None;
+            *enclave = Some(stream.clone());
+
+            stream
+        };
+
+        record_self_test(&self.enclave_addr, &stream, &self.self_test).await;
+
+        Ok(stream)
+    }
+
+    /// The report of the enclave's latest self-test, `None` if it has not run.
+    pub fn self_test(&self) -> Option<SelfTestReport> {
+        self.self_test
+            .lock()
+            .expect("Self-test lock poisoned")
+            .clone()
+    }
+
+    /// Returns true if the enclave's latest self-test passed.
+    pub fn self_test_passed(&self) -> bool {
+        self.self_test().is_some_and(|report| report.passed)
+    }
+
+    /// Runs the enclave's self-test, unless the latest one passed.
+    pub async fn run_self_test(&self) {
+        match self.enclave().await {
+            Ok(stream) if !self.self_test_passed() => {
+                record_self_test(&self.enclave_addr, &stream, &self.self_test).await
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to connect to enclave: {}", e),
+        }
+    }
+
+    /// Whether the server is ready to serve traffic, with the latest self-test of every enclave.
+    ///
+    /// The server is ready once our enclave's self-test passed, and if co-signing is enabled,
+    /// enough cosigners' self-tests passed to meet the threshold.
+    pub fn readiness(&self) -> ReadinessResponse {
+        let cosigners = self
+            .cosigners
+            .iter()
+            .filter_map(|cosigner| Some((cosigner.addr().to_string(), cosigner.self_test()?)))
+            .collect::<BTreeMap<_, _>>();
+
+        let passed = self.self_test_passed() as usize
+            + cosigners.values().filter(|report| report.passed).count();
+
+        ReadinessResponse {
+            ready: self.self_test_passed() && passed >= self.cosign_threshold.unwrap_or(1),
+            self_test: self.self_test(),
+            cosigners,
+        }
     }
 }
 
@@ -68,6 +304,65 @@ pub struct ServerArgs {
     #[clap(long, default_value_t = sp1_tee_common::ENCLAVE_CID)]
     pub enclave_cid: u32,
 
+    /// The address of an enclave that is not managed by this server.
+    ///
+    /// Of the form `unix://<path>` or `tcp://<addr>`, useful for running the enclave binary
+    /// on an ordinary Linux machine. When set, Nitro is not used to start or terminate the enclave.
+    #[clap(long)]
+    pub enclave_addr: Option<TransportAddr>,
+
+    /// The maximum size (in bytes) of a request frame carrying a program and stdin.
+    ///
+    /// This should match the value the enclave was started with.
+    #[clap(long, default_value_t = DEFAULT_REQUEST_FRAME_LIMITS.payload)]
+    pub max_payload_frame_size: u32,
+
+    /// The maximum size (in bytes) of any other request frame.
+    ///
+    /// This should match the value the enclave was started with.
+    #[clap(long, default_value_t = DEFAULT_REQUEST_FRAME_LIMITS.control)]
+    pub max_control_frame_size: u32,
+
+    /// The maximum size (in bytes) of a response frame carrying a result, i.e. public values.
+    ///
+    /// This should match the value the enclave was started with.
+    #[clap(long, default_value_t = DEFAULT_RESPONSE_FRAME_LIMITS.payload)]
+    pub max_response_payload_frame_size: u32,
+
+    /// The maximum size (in bytes) of any other response frame.
+    ///
+    /// This should match the value the enclave was started with.
+    #[clap(long, default_value_t = DEFAULT_RESPONSE_FRAME_LIMITS.control)]
+    pub max_response_control_frame_size: u32,
+
+    /// How long (in seconds) an execution may run before it is cancelled.
+    #[clap(long, default_value = "1800")]
+    pub execution_timeout_secs: u64,
+
+    /// Include the cycle count in the signed message, so it can be relied on for cost accounting.
+    ///
+    /// NOTE: Verifiers must expect the cycle count to be appended to the signed message,
+    /// the deployed `SP1TeeVerifier` contract rejects these signatures.
+    #[clap(long)]
+    pub sign_cycles: bool,
+
+    /// Include the hashes of the program and stdin in the signed message, binding the result to its inputs.
+    ///
+    /// NOTE: Verifiers must expect the input commitment to be appended to the signed message,
+    /// the deployed `SP1TeeVerifier` contract rejects these signatures.
+    #[clap(long)]
+    pub sign_inputs: bool,
+
+    /// The addresses of independent enclaves that co-sign every execution, each on its own CID, i.e. `vsock://11:5005`.
+    ///
+    /// These enclaves are not managed by this server, and keep their own signing keys.
+    #[clap(long, value_delimiter = ',')]
+    pub cosigner_enclave_addrs: Vec<TransportAddr>,
+
+    /// The number of enclaves, including ours, that must sign the same result, defaults to all of them.
+    #[clap(long, requires = "cosigner_enclave_addrs")]
+    pub cosign_threshold: Option<usize>,
+
     /// The number of cores to use for the enclave.
     #[clap(long, default_value = "12")]
     pub enclave_cores: u32,
@@ -80,11 +375,69 @@ pub struct ServerArgs {
     #[clap(short, long)]
     pub debug: bool,
 
+    /// Where to persist the enclave's sealed signing key, so the signer survives an enclave restart.
+    ///
+    /// The enclave must be started with an `--enc-key-arn`, i.e. by setting `ENC_KEY_ARN` when building it.
+    #[clap(long, conflicts_with = "migrate_key_from")]
+    pub sealed_key_path: Option<PathBuf>,
+
+    /// The URL of a running server, whose enclave should donate its signing key to ours.
+    ///
+    /// Both enclaves must run the same image (PCR0), this is an alternative to sealing with KMS.
+    #[clap(long)]
+    pub migrate_key_from: Option<String>,
+
+    /// Allow other enclaves running the same image to fetch our signing key, through `/migrate`.
+    #[clap(long)]
+    pub allow_key_migration: bool,
+
     /// The RPC URL of the prover network.
     #[clap(long, default_value = "https://rpc.production.succinct.xyz/")]
     pub prover_network_url: String,
 }
 
+impl ServerArgs {
+    /// The address of the enclave, defaults to the vsock address of `enclave_cid`.
+    pub fn enclave_addr(&self) -> TransportAddr {
+        self.enclave_addr
+            .clone()
+            .unwrap_or(TransportAddr::vsock(self.enclave_cid))
+    }
+
+    /// The limits for requests sent to the enclave.
+    pub fn request_frame_limits(&self) -> FrameLimits {
+        FrameLimits {
+            control: self.max_control_frame_size,
+            payload: self.max_payload_frame_size,
+        }
+    }
+
+    /// The limits for responses received from the enclave.
+    pub fn response_frame_limits(&self) -> FrameLimits {
+        FrameLimits {
+            control: self.max_response_control_frame_size,
+            payload: self.max_response_payload_frame_size,
+        }
+    }
+
+    /// Returns true if this server should start and terminate the enclave using Nitro.
+    pub fn manages_enclave(&self) -> bool {
+        self.enclave_addr.is_none()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EnclaveConnectionError {
+    #[error(transparent)]
+    Communication(#[from] CommunicationError),
+
+    #[error("Failed to restore the signing key: {0}")]
+    Sealing(#[from] SealingError),
+
+    #[error("Failed to migrate the signing key: {0}")]
+    Migration(#[from] MigrationError),
+}
+
 #[derive(Debug, thiserror::Error)]
 #[allow(clippy::large_enum_variant)]
 pub enum ServerError {
@@ -104,7 +457,26 @@ pub enum ServerError {
     FailedToConvertPublicKeyToAddress,
 
     #[error("Enclave error: {0}")]
-    EnclaveError(String),
+    EnclaveError(EnclaveError),
+
+    /// The program deterministically failed, and the enclave signed a receipt of the failure.
+    #[error("Enclave error: {error}")]
+    ExecutionFailedWithReceipt {
+        error: EnclaveError,
+        receipt: Box<SignedFailureReceipt>,
+    },
+
+    #[error("Execution timed out after {0:?}")]
+    ExecutionTimedOut(Duration),
+
+    #[error("Only {signatures} of the required {threshold} enclaves signed the result")]
+    CosigningFailed { threshold: usize, signatures: usize },
+
+    #[error("Failed to build the threshold proof: {0}")]
+    FailedToBuildThresholdProof(ThresholdError),
+
+    #[error("Streamed executions are not co-signed, use `/execute` instead")]
+    StreamingNotCosigned,
 
     #[error("Stdin is too large, found {0} bytes")]
     StdinTooLarge(usize),
@@ -112,9 +484,21 @@ pub enum ServerError {
     #[error("Program is too large, found {0} bytes")]
     ProgramTooLarge(usize),
 
+    #[error("Proof is too large, found {0} bytes")]
+    ProofTooLarge(usize),
+
     #[error("Failed to deserialize request, {0}")]
     FailedToDeserializeRequest(bincode::Error),
 
+    #[error("Missing or invalid header: {0}")]
+    InvalidHeader(&'static str),
+
+    #[error("Failed to read request body: {0}")]
+    FailedToReadBody(axum::Error),
+
+    #[error("Failed to upload to enclave: {0}")]
+    FailedToUpload(#[from] UploadError),
+
     #[error("Failed to deserialize enclave measurement: {0}")]
     FailedToParseEnclaveMeasurement(#[from] serde_json::Error),
 
@@ -124,67 +508,126 @@ pub enum ServerError {
     #[error("Failed to get attestations: {0}")]
     FailedToGetAttestations(#[from] crate::attestations::GetAttestationError),
 
+    #[error("Key migration is not allowed by this server")]
+    KeyMigrationDisabled,
+
+    #[error(
+        "Invalid nonce, expected at most {} hex encoded bytes",
+        sp1_tee_common::MAX_NONCE_LEN
+    )]
+    InvalidNonce,
+
     #[cfg(feature = "production")]
     #[error("Failed to authenticate request")]
     FailedToAuthenticateRequest,
 }
 
+impl ServerError {
+    /// A stable, machine readable code for this error, sent to clients along with the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServerError::FailedToConnectToEnclave
+            | ServerError::FailedToSendRequestToEnclave
+            | ServerError::FailedToReceiveResponseFromEnclave => "ENCLAVE_UNAVAILABLE",
+            ServerError::UnexpectedResponseFromEnclave
+            | ServerError::FailedToConvertPublicKeyToAddress
+            | ServerError::FailedToBuildThresholdProof(_) => "INTERNAL",
+            ServerError::EnclaveError(e)
+            | ServerError::ExecutionFailedWithReceipt { error: e, .. } => e.code(),
+            ServerError::ExecutionTimedOut(_) => "EXECUTION_TIMEOUT",
+            ServerError::CosigningFailed { .. } => "COSIGNING_FAILED",
+            ServerError::StreamingNotCosigned => "NOT_IMPLEMENTED",
+            ServerError::StdinTooLarge(_) => "STDIN_TOO_LARGE",
+            ServerError::ProgramTooLarge(_) => "PROGRAM_TOO_LARGE",
+            ServerError::ProofTooLarge(_) => "PROOF_TOO_LARGE",
+            ServerError::FailedToDeserializeRequest(_)
+            | ServerError::InvalidHeader(_)
+            | ServerError::FailedToReadBody(_)
+            | ServerError::InvalidNonce => "INVALID_REQUEST",
+            ServerError::FailedToUpload(UploadError::Rejected(e)) => e.code(),
+            ServerError::FailedToUpload(UploadError::LengthMismatch { .. }) => "INVALID_REQUEST",
+            ServerError::FailedToUpload(_) => "ENCLAVE_UNAVAILABLE",
+            ServerError::FailedToParseEnclaveMeasurement(_)
+            | ServerError::IoError(_)
+            | ServerError::FailedToGetAttestations(_) => "INTERNAL",
+            ServerError::KeyMigrationDisabled => "FORBIDDEN",
+            #[cfg(feature = "production")]
+            ServerError::FailedToAuthenticateRequest => "UNAUTHORIZED",
+        }
+    }
+
+    /// The HTTP status code for this error.
+    ///
+    /// Faults of the request map to 4xx statuses, and should not be retried.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ServerError::FailedToConnectToEnclave
+            | ServerError::FailedToSendRequestToEnclave
+            | ServerError::FailedToReceiveResponseFromEnclave => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::EnclaveError(e)
+            | ServerError::ExecutionFailedWithReceipt { error: e, .. } => enclave_error_status(e),
+            ServerError::ExecutionTimedOut(_) => StatusCode::GATEWAY_TIMEOUT,
+            ServerError::CosigningFailed { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::StreamingNotCosigned => StatusCode::NOT_IMPLEMENTED,
+            ServerError::StdinTooLarge(_)
+            | ServerError::ProgramTooLarge(_)
+            | ServerError::ProofTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ServerError::FailedToDeserializeRequest(_)
+            | ServerError::InvalidHeader(_)
+            | ServerError::FailedToReadBody(_)
+            | ServerError::InvalidNonce => StatusCode::BAD_REQUEST,
+            ServerError::FailedToUpload(UploadError::Rejected(e)) => enclave_error_status(e),
+            ServerError::FailedToUpload(UploadError::LengthMismatch { .. }) => {
+                StatusCode::BAD_REQUEST
+            }
+            ServerError::FailedToUpload(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::KeyMigrationDisabled => StatusCode::FORBIDDEN,
+            #[cfg(feature = "production")]
+            ServerError::FailedToAuthenticateRequest => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The HTTP status code for an error returned by the enclave.
+fn enclave_error_status(error: &EnclaveError) -> StatusCode {
+    match error {
+        EnclaveError::InvalidRequest(_) | EnclaveError::CycleLimitTooHigh { .. } => {
+            StatusCode::BAD_REQUEST
+        }
+        // Matches the status of requests the host rejects before sending, see `check_request_size`.
+        EnclaveError::RequestTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+        EnclaveError::ExecutionFailed(_)
+        | EnclaveError::CycleLimitExceeded(_)
+        | EnclaveError::ExecutionTimeLimitExceeded(_)
+        | EnclaveError::InvalidProof(_)
+        | EnclaveError::WorkerCrashed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        // The host falls back to sending the full program, so this should not reach a client.
+        EnclaveError::ResourceExhausted(_)
+        | EnclaveError::AttestationFailed(_)
+        | EnclaveError::SealingFailed(_)
+        | EnclaveError::NotReady(_)
+        | EnclaveError::ProgramNotCached { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        // The host only cancels requests that timed out, or whose client went away.
+        EnclaveError::Cancelled => StatusCode::GATEWAY_TIMEOUT,
+        EnclaveError::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+        EnclaveError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 impl IntoResponse for ServerError {
     fn into_response(self) -> Response {
-        let err = match self {
-            ServerError::FailedToConnectToEnclave => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to connect to enclave".to_string(),
-            ),
-            ServerError::FailedToSendRequestToEnclave => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to send request to enclave".to_string(),
-            ),
-            ServerError::FailedToReceiveResponseFromEnclave => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to receive response from enclave".to_string(),
-            ),
-            ServerError::UnexpectedResponseFromEnclave => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Unexpected response from enclave".to_string(),
-            ),
-            ServerError::FailedToConvertPublicKeyToAddress => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to convert public key to address, this is a bug.".to_string(),
-            ),
-            ServerError::EnclaveError(e) => (StatusCode::INTERNAL_SERVER_ERROR, e),
-            ServerError::StdinTooLarge(size) => (
-                StatusCode::PAYLOAD_TOO_LARGE,
-                format!("Stdin is too large, found {} bytes", size),
-            ),
-            ServerError::ProgramTooLarge(size) => (
-                StatusCode::PAYLOAD_TOO_LARGE,
-                format!("Program is too large, found {} bytes", size),
-            ),
-            ServerError::FailedToDeserializeRequest(e) => (
-                StatusCode::BAD_REQUEST,
-                format!("Failed to deserialize request, {}", e),
-            ),
-            ServerError::FailedToParseEnclaveMeasurement(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to parse enclave measurement, {}", e),
-            ),
-            ServerError::IoError(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Io error when fetching enclave measurement, {}", e),
-            ),
-            ServerError::FailedToGetAttestations(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to get attestations, {}", e),
-            ),
-            #[cfg(feature = "production")]
-            ServerError::FailedToAuthenticateRequest => (
-                StatusCode::UNAUTHORIZED,
-                "Failed to authenticate request".to_string(),
-            ),
+        let status = self.status_code();
+        let code = self.code();
+
+        let message = match self {
+            ServerError::FailedToConvertPublicKeyToAddress => {
+                "Failed to convert public key to address, this is a bug.".to_string()
+            }
+            e => e.to_string(),
         };
 
-        err.into_response()
+        (status, format!("{}: {}", code, message)).into_response()
     }
 }
 
@@ -286,7 +729,7 @@ pub async fn get_enclave_measurement() -> Result<EnclaveMeasurement, ServerError
 /// Spawn a task that will save attestations to S3.
 ///
 /// This function will run until the program is killed.
-pub fn spawn_attestation_task(cid: u32, port: u16, interval: Duration) {
+pub fn spawn_attestation_task(addr: TransportAddr, interval: Duration) {
     tokio::spawn(async move {
         // If the attestation fails, we try again sooner.
         const TRY_AGAIN_INTERVAL: Duration = Duration::from_secs(5);
@@ -299,8 +742,7 @@ pub fn spawn_attestation_task(cid: u32, port: u16, interval: Duration) {
         loop {
             if let Err(e) =
                 crate::attestations::save_attestation(crate::attestations::SaveAttestationArgs {
-                    cid,
-                    port,
+                    addr: addr.clone(),
                     ..Default::default()
                 })
                 .await
@@ -0,0 +1,118 @@
+//! Independent enclaves that co-sign executions, so a single compromised enclave cannot forge a result.
+//!
+//! Each cosigner keeps its own signing key, which must be registered with the verifier like ours.
+
+use super::selftest::record_self_test;
+use super::stream::HostStream;
+
+use sp1_tee_common::{
+    CommunicationError, EnclaveResponse, FrameLimits, SelfTestReport, SignedMessage, TransportAddr,
+};
+use std::sync::Mutex;
+
+pub struct Cosigner {
+    /// The address of the enclave, on its own CID.
+    addr: TransportAddr,
+    /// The connection to the enclave, shared by all requests.
+    ///
+    /// Lazily (re)connected by [`Cosigner::enclave`].
+    stream: tokio::sync::Mutex<Option<HostStream>>,
+    /// The report of the enclave's latest self-test, `None` if it has not run.
+    self_test: Mutex<Option<SelfTestReport>>,
+}
+
+impl Cosigner {
+    pub fn new(addr: TransportAddr) -> Self {
+        Self {
+            addr,
+            stream: tokio::sync::Mutex::new(None),
+            self_test: Mutex::new(None),
+        }
+    }
+
+    pub fn addr(&self) -> &TransportAddr {
+        &self.addr
+    }
+
+    /// The report of the enclave's latest self-test, `None` if it has not run.
+    pub fn self_test(&self) -> Option<SelfTestReport> {
+        self.self_test
+            .lock()
+            .expect("Self-test lock poisoned")
+            .clone()
+    }
+
+    /// Returns true if the enclave's latest self-test passed.
+    pub fn self_test_passed(&self) -> bool {
+        self.self_test().is_some_and(|report| report.passed)
+    }
+
+    /// Runs the enclave's self-test, unless the latest one passed.
+    pub async fn run_self_test(&self, request_limits: FrameLimits, response_limits: FrameLimits) {
+        match self.enclave(request_limits, response_limits).await {
+            Ok(stream) if !self.self_test_passed() => {
+                record_self_test(&self.addr, &stream, &self.self_test).await
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to connect to cosigner {}: {}", self.addr, e),
+        }
+    }
+
+    /// Get the shared connection to the enclave, reconnecting if it was closed.
+    ///
+    /// The enclave's self-test is run on every new connection, as the enclave may have restarted since the last one.
+    /// It runs outside the connection lock, and the cosigner does not count towards readiness until it passed.
+    pub async fn enclave(
+        &self,
+        request_limits: FrameLimits,
+        response_limits: FrameLimits,
+    ) -> Result<HostStream, CommunicationError> {
+        let connected = {
+            let mut stream = self.stream.lock().await;
+
+            if let Some(stream) = stream.as_ref().filter(|stream| !stream.is_closed()) {
+                return Ok(stream.clone());
+            }
+
+            let connected =
+                HostStream::connect_with_limits(&self.addr, request_limits, response_limits)
+                    .await?;
+
+            tracing::info!(
+                "Connected to cosigner {}: {:?}",
+                self.addr,
+                connected.enclave_info()
+            );
+
+            // The enclave may have restarted, its previous self-test no longer applies.
+            *self.self_test.lock().expect("Self-test lock poisoned") = None;
+            *stream = Some(connected.clone());
+
+            connected
+        };
+
+        record_self_test(&self.addr, &connected, &self.self_test).await;
+
+        Ok(connected)
+    }
+}
+
+/// The message an enclave signed in its response to an execution, `None` if it did not sign a result.
+pub fn signed_message(tee_version: u32, response: &EnclaveResponse) -> Option<SignedMessage<'_>> {
+    match response {
+        EnclaveResponse::SignedPublicValues {
+            vkey,
+            public_values,
+            report,
+            ..
+        } => Some(SignedMessage {
+            tee_version,
+            vkey,
+            public_values,
+            cycles: report.cycles_signed.then_some(report.cycles),
+            domain: report.domain.as_ref(),
+            inputs: report.inputs.as_ref(),
+        }),
+        _ => None,
+    }
+}
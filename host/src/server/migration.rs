@@ -0,0 +1,74 @@
+use super::stream::HostStream;
+use crate::api::KeyMigrationResponse;
+use alloy::transports::http::reqwest;
+use sp1_tee_common::{CommunicationError, EnclaveError, EnclaveRequest, EnclaveResponse};
+
+impl HostStream {
+    /// Replaces the enclave's signing key with the key of the donor enclave behind `donor_url`.
+    ///
+    /// The donor's host must be started with `--allow-key-migration`, and the enclaves must run
+    /// the same image. The key is encrypted end to end, so neither host sees it.
+    ///
+    /// # Errors
+    /// - [`MigrationError::Rejected`] - Either enclave rejected the other's attestation.
+    pub async fn migrate_signing_key(&self, donor_url: &str) -> Result<(), MigrationError> {
+        let attestation = match self.request(EnclaveRequest::BeginKeyMigration).await? {
+            EnclaveResponse::KeyMigrationRequest { attestation } => attestation,
+            EnclaveResponse::Error(e) => return Err(MigrationError::Rejected(e)),
+            response => return Err(MigrationError::UnexpectedResponse(response.type_of())),
+        };
+
+        let body = reqwest::Client::new()
+            .post(format!("{}/migrate", donor_url.trim_end_matches('/')))
+            .body(attestation)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        let KeyMigrationResponse {
+            attestation,
+            ciphertext,
+        } = bincode::deserialize(&body)?;
+
+        let response = self
+            .request(EnclaveRequest::ImportSigningKey {
+                attestation,
+                ciphertext,
+            })
+            .await?;
+
+        match response {
+            EnclaveResponse::PublicKey(public_key) => {
+                tracing::info!(
+                    "Migrated the signing key from {}, address: {:?}",
+                    donor_url,
+                    crate::ethereum_address_from_encoded_point(&public_key)
+                );
+
+                Ok(())
+            }
+            EnclaveResponse::Error(e) => Err(MigrationError::Rejected(e)),
+            response => Err(MigrationError::UnexpectedResponse(response.type_of())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    #[error(transparent)]
+    Communication(#[from] CommunicationError),
+
+    #[error("Enclave rejected the key migration: {0}")]
+    Rejected(EnclaveError),
+
+    #[error("Unexpected response from enclave: {0}")]
+    UnexpectedResponse(&'static str),
+
+    #[error("Failed to reach the donor: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Failed to decode the donor's response: {0}")]
+    Decode(#[from] bincode::Error),
+}
@@ -0,0 +1,121 @@
+use super::stream::HostStream;
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::config::ProvideCredentials;
+use sp1_tee_common::{
+    AwsCredentials, CommunicationError, EnclaveError, EnclaveRequest, EnclaveResponse,
+};
+use std::path::Path;
+
+impl HostStream {
+    /// Restores the enclave's signing key from the sealed key at `path`.
+    ///
+    /// If there is no sealed key yet, the enclave's current key is sealed and written to `path`,
+    /// so the same signer is used after the enclave restarts.
+    ///
+    /// # Errors
+    /// - [`SealingError::Rejected`] - The enclave failed to (un)seal the key, or sealing is disabled.
+    pub async fn restore_signing_key(&self, path: &Path) -> Result<(), SealingError> {
+        let credentials = aws_credentials().await;
+
+        let sealed_key = match tokio::fs::read(path).await {
+            Ok(sealed_key) => sealed_key,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return self.seal_signing_key_with(path, credentials).await;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let response = self
+            .request(EnclaveRequest::SetSigningKey {
+                sealed_key,
+                credentials,
+            })
+            .await?;
+
+        match response {
+            EnclaveResponse::PublicKey(public_key) => {
+                tracing::info!(
+                    "Restored the enclave signing key, address: {:?}",
+                    crate::ethereum_address_from_encoded_point(&public_key)
+                );
+
+                Ok(())
+            }
+            EnclaveResponse::Error(e) => Err(SealingError::Rejected(e)),
+            response => Err(SealingError::UnexpectedResponse(response.type_of())),
+        }
+    }
+
+    /// Seals the enclave's signing key, and writes it to `path`, replacing any existing sealed key.
+    ///
+    /// If a rotation is in progress, it is sealed too, and resumes when the key is restored.
+    pub async fn seal_signing_key(&self, path: &Path) -> Result<(), SealingError> {
+        self.seal_signing_key_with(path, aws_credentials().await)
+            .await
+    }
+
+    async fn seal_signing_key_with(
+        &self,
+        path: &Path,
+        credentials: Option<AwsCredentials>,
+    ) -> Result<(), SealingError> {
+        let response = self
+            .request(EnclaveRequest::GetEncryptedSigningKey { credentials })
+            .await?;
+
+        let sealed_key = match response {
+            EnclaveResponse::EncryptedSigningKey(sealed_key) => sealed_key,
+            EnclaveResponse::Error(e) => return Err(SealingError::Rejected(e)),
+            response => return Err(SealingError::UnexpectedResponse(response.type_of())),
+        };
+
+        write_atomic(path, &sealed_key).await?;
+
+        tracing::info!("Sealed the enclave signing key to {}", path.display());
+
+        Ok(())
+    }
+}
+
+/// Resolves credentials from the default AWS provider chain, for the enclave to call KMS with.
+///
+/// Returns `None` if there are no credentials, i.e. when sealing to a file outside of AWS.
+async fn aws_credentials() -> Option<AwsCredentials> {
+    let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+
+    match config.credentials_provider()?.provide_credentials().await {
+        Ok(credentials) => Some(AwsCredentials {
+            access_key_id: credentials.access_key_id().to_string(),
+            secret_access_key: credentials.secret_access_key().to_string(),
+            session_token: credentials.session_token().map(str::to_string),
+        }),
+        Err(e) => {
+            tracing::debug!("No AWS credentials to send to the enclave: {}", e);
+
+            None
+        }
+    }
+}
+
+/// Writes to a temporary file and renames it, so a crash never leaves a truncated sealed key.
+async fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+
+    tokio::fs::write(&tmp, data).await?;
+    tokio::fs::rename(&tmp, path).await
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SealingError {
+    #[error(transparent)]
+    Communication(#[from] CommunicationError),
+
+    #[error("Enclave failed to seal the signing key: {0}")]
+    Rejected(EnclaveError),
+
+    #[error("Unexpected response from enclave: {0}")]
+    UnexpectedResponse(&'static str),
+
+    #[error("Io error with the sealed key: {0}")]
+    Io(#[from] std::io::Error),
+}
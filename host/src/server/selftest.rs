@@ -0,0 +1,73 @@
+//! The enclave's known-answer test, which must pass before its signing key is attested.
+
+use super::stream::HostStream;
+
+use sp1_tee_common::{
+    CommunicationError, EnclaveError, EnclaveRequest, EnclaveResponse, SelfTestReport,
+    TransportAddr,
+};
+use std::sync::Mutex;
+
+impl HostStream {
+    /// Runs the enclave's self-test, see [`EnclaveRequest::SelfTest`].
+    ///
+    /// A self-test that ran but failed is not an error, check [`SelfTestReport::passed`].
+    ///
+    /// # Errors
+    /// - [`SelfTestError::Rejected`] - The enclave could not run the self-test.
+    pub async fn self_test(&self) -> Result<SelfTestReport, SelfTestError> {
+        match self.request(EnclaveRequest::SelfTest).await? {
+            EnclaveResponse::SelfTest(report) => Ok(report),
+            EnclaveResponse::Error(e) => Err(SelfTestError::Rejected(e)),
+            response => Err(SelfTestError::UnexpectedResponse(response.type_of())),
+        }
+    }
+}
+
+/// Runs the self-test of the enclave at `addr` over `stream`, recording its report in `latest`.
+///
+/// If the self-test could not be run, `latest` is cleared, as the enclave may have restarted since.
+pub(crate) async fn record_self_test(
+    addr: &TransportAddr,
+    stream: &HostStream,
+    latest: &Mutex<Option<SelfTestReport>>,
+) {
+    match stream.self_test().await {
+        Ok(report) if report.passed => {
+            tracing::info!(
+                "Self-test of enclave {} passed in {}ms",
+                addr,
+                report.total_time_ms
+            );
+
+            *latest.lock().expect("Self-test lock poisoned") = Some(report);
+        }
+        Ok(report) => {
+            tracing::error!(
+                alert = true,
+                "Self-test of enclave {} failed: {}",
+                addr,
+                report.error.as_deref().unwrap_or("unknown error")
+            );
+
+            *latest.lock().expect("Self-test lock poisoned") = Some(report);
+        }
+        Err(e) => {
+            tracing::error!("Failed to run the self-test of enclave {}: {}", addr, e);
+
+            *latest.lock().expect("Self-test lock poisoned") = None;
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SelfTestError {
+    #[error(transparent)]
+    Communication(#[from] CommunicationError),
+
+    #[error("Enclave failed to run the self-test: {0}")]
+    Rejected(EnclaveError),
+
+    #[error("Unexpected response from enclave: {0}")]
+    UnexpectedResponse(&'static str),
+}
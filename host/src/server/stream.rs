@@ -1,52 +1,327 @@
-use sp1_tee_common::{CommunicationError, EnclaveRequest, EnclaveResponse, VsockStream};
+use sp1_sdk::network::tee::SP1_TEE_VERSION;
+use sp1_tee_common::{
+    CommunicationError, EnclaveInfo, EnclaveRequest, EnclaveResponse, Frame, FrameLimits,
+    FrameReader, FrameWriter, HandshakeError, RequestId, TransportAddr, VsockStream,
+    HANDSHAKE_REQUEST_ID, PROTOCOL_VERSION,
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
 
-/// A wrapper around [`VsockStream`] that allows for sending messages to the enclave.
+/// How long to wait for the enclave's protocol preamble.
 ///
-/// This stream is bi-directional, and it will automatically close the connection when the stream is dropped.
+/// An enclave that predates the preamble waits for the rest of what it reads as a frame, and never answers.
+const PREAMBLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type ResponseSender = oneshot::Sender<Result<EnclaveResponse, CommunicationError>>;
+
+/// A request to be written to the enclave, along with a channel for the result of the write.
+type Outgoing = (
+    RequestId,
+    EnclaveRequest,
+    oneshot::Sender<Result<(), CommunicationError>>,
+);
+
+/// A multiplexed connection to the enclave.
+///
+/// Every request is tagged with an ID, and awaits its own response, so the enclave may answer out of order.
+/// This type is cheap to clone and can be shared across tasks.
+///
+/// The connection is closed once every clone of the stream has been dropped.
+#[derive(Clone)]
 pub struct HostStream {
-    stream: Option<VsockStream<EnclaveResponse, EnclaveRequest>>,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    /// The requests to be written by the writer task.
+    ///
+    /// Writes happen in a separate task so a request future being dropped never leaves a partial frame.
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+    /// The requests that are waiting for a response.
+    pending: Arc<Mutex<Pending>>,
+    /// The ID of the next request.
+    next_id: AtomicU64,
+    /// The information the enclave sent during the handshake.
+    enclave_info: EnclaveInfo,
+    /// The task dispatching responses to the pending requests.
+    reader_task: tokio::task::JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct Pending {
+    /// Set once the connection is closed, no new requests can be sent after this.
+    closed: bool,
+    senders: HashMap<RequestId, ResponseSender>,
 }
 
 impl HostStream {
-    /// Connects to the enclave on the given CID and port.
-    pub async fn new(cid: u32, port: u16) -> Result<Self, CommunicationError> {
-        let stream = VsockStream::connect(cid, port as u32).await?;
+    /// Connects to the enclave at the given address, and performs the handshake.
+    ///
+    /// # Errors
+    /// - [`CommunicationError::Handshake`] - The enclave is not compatible with this host,
+    ///   or speaks a different framing.
+    pub async fn new(addr: &TransportAddr) -> Result<Self, CommunicationError> {
+        Self::connect_with_limits(
+            addr,
+            EnclaveRequest::DEFAULT_LIMITS,
+            EnclaveResponse::DEFAULT_LIMITS,
+        )
+        .await
+    }
+
+    /// Connects to the enclave at the given address, and performs the handshake.
+    ///
+    /// The limits for requests sent to the enclave should match the limits the enclave was started with,
+    /// so oversized requests are rejected before they are sent. Responses larger than `response_limits`
+    /// are discarded, and fail the request they answer.
+    pub async fn connect_with_limits(
+        addr: &TransportAddr,
+        request_limits: FrameLimits,
+        response_limits: FrameLimits,
+    ) -> Result<Self, CommunicationError> {
+        let mut stream = VsockStream::<EnclaveResponse, EnclaveRequest>::connect(addr)
+            .await?
+            .with_send_limits(request_limits)
+            .with_recv_limits(response_limits);
+
+        let protocol_version = tokio::time::timeout(PREAMBLE_TIMEOUT, stream.exchange_preamble())
+            .await
+            .map_err(|_| HandshakeError::PreambleTimeout)??;
+
+        if protocol_version != PROTOCOL_VERSION {
+            return Err(HandshakeError::ProtocolMismatch {
+                host: PROTOCOL_VERSION,
+                enclave: protocol_version,
+            }
+            .into());
+        }
+
+        stream
+            .send(
+                HANDSHAKE_REQUEST_ID,
+                EnclaveRequest::Hello {
+                    protocol_version: PROTOCOL_VERSION,
+                    tee_version: SP1_TEE_VERSION,
+                },
+            )
+            .await?;
+
+        let enclave_info = match stream.recv().await? {
+            (_, EnclaveResponse::Hello(info)) => info,
+            (_, EnclaveResponse::Error(e)) => {
+                return Err(HandshakeError::Rejected(e.to_string()).into())
+            }
+            (_, msg) => return Err(HandshakeError::UnexpectedResponse(msg.type_of()).into()),
+        };
+
+        enclave_info.check_compatible(SP1_TEE_VERSION)?;
+
+        let (reader, writer) = stream.into_split();
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        let reader_task = tokio::task::spawn(dispatch_responses(reader, pending.clone()));
+
+        let (outgoing, outgoing_rx) = mpsc::unbounded_channel();
+        tokio::task::spawn(write_requests(writer, outgoing_rx));
 
         Ok(Self {
-            stream: Some(stream),
+            inner: Arc::new(Inner {
+                outgoing,
+                pending,
+                next_id: AtomicU64::new(HANDSHAKE_REQUEST_ID + 1),
+                enclave_info,
+                reader_task,
+            }),
         })
     }
 
-    /// Sends a request to the enclave.
-    pub async fn send(&mut self, request: EnclaveRequest) -> Result<(), CommunicationError> {
-        self.stream
-            .as_mut()
-            .expect("Stream should be initialized, this is a bug")
-            .send(request)
-            .await
+    /// The information the enclave sent during the handshake.
+    pub fn enclave_info(&self) -> &EnclaveInfo {
+        &self.inner.enclave_info
+    }
+
+    /// Returns true if the connection to the enclave has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.inner
+            .pending
+            .lock()
+            .expect("Pending requests lock poisoned")
+            .closed
     }
 
-    /// Receives a response from the enclave.
-    pub async fn recv(&mut self) -> Result<EnclaveResponse, CommunicationError> {
-        self.stream
-            .as_mut()
-            .expect("Stream should be initialized, this is a bug")
-            .recv()
+    /// Sends a request to the enclave, and waits for its response.
+    ///
+    /// Many requests may be in flight at once, from any clone of this stream.
+    ///
+    /// If this future is dropped before the response arrives, the enclave is told to cancel the request.
+    ///
+    /// # Errors
+    /// - [`CommunicationError::Disconnected`] - The connection was closed before a response was received.
+    /// - [`CommunicationError::FrameTooLarge`] - The request (or its response) exceeded the frame limits.
+    pub async fn request(
+        &self,
+        request: EnclaveRequest,
+    ) -> Result<EnclaveResponse, CommunicationError> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut pending = self
+                .inner
+                .pending
+                .lock()
+                .expect("Pending requests lock poisoned");
+
+            if pending.closed {
+                return Err(CommunicationError::Disconnected);
+            }
+
+            pending.senders.insert(id, tx);
+        }
+
+        // Ensure the pending entry is removed if sending fails, or this future is dropped.
+        let mut guard = PendingGuard {
+            stream: self,
+            id,
+            // A cancel request is never cancelled itself.
+            cancel_on_drop: !matches!(request, EnclaveRequest::Cancel { .. }),
+            in_flight: false,
+        };
+
+        let (written_tx, written_rx) = oneshot::channel();
+        self.inner
+            .outgoing
+            .send((id, request, written_tx))
+            .map_err(|_| CommunicationError::Disconnected)?;
+
+        // Once queued, the request will reach the enclave even if this future is dropped.
+        guard.in_flight = true;
+
+        if let Err(e) = written_rx
             .await
+            .map_err(|_| CommunicationError::Disconnected)?
+        {
+            // The request was never written, so there is nothing to cancel.
+            guard.in_flight = false;
+
+            return Err(e);
+        }
+
+        let response = rx.await.map_err(|_| CommunicationError::Disconnected)?;
+
+        guard.in_flight = false;
+
+        response
     }
 }
 
-impl Drop for HostStream {
+/// Removes a pending request when dropped, and cancels it if its still in flight.
+struct PendingGuard<'a> {
+    stream: &'a HostStream,
+    id: RequestId,
+    cancel_on_drop: bool,
+    /// Set while the request has been queued, but no response has arrived.
+    in_flight: bool,
+}
+
+impl Drop for PendingGuard<'_> {
     fn drop(&mut self) {
-        let mut stream = self
-            .stream
-            .take()
-            .expect("Stream should be initialized, this is a bug");
-
-        tokio::task::spawn(async move {
-            if let Err(e) = stream.send(EnclaveRequest::CloseSession).await {
-                tracing::error!("Failed to send close session request: {}", e);
+        if let Ok(mut pending) = self.stream.inner.pending.lock() {
+            pending.senders.remove(&self.id);
+
+            if pending.closed {
+                return;
+            }
+        }
+
+        if self.in_flight && self.cancel_on_drop {
+            tracing::debug!("Cancelling request {}", self.id);
+
+            let stream = self.stream.clone();
+            let request_id = self.id;
+            tokio::spawn(async move {
+                if let Err(e) = stream.request(EnclaveRequest::Cancel { request_id }).await {
+                    tracing::debug!("Failed to cancel request {}: {}", request_id, e);
+                }
+            });
+        }
+    }
+}
+
+/// Writes requests to the enclave, in the order they were made.
+///
+/// Once every sender is dropped, a [`EnclaveRequest::CloseSession`] is sent and the task exits.
+async fn write_requests(
+    mut writer: FrameWriter<EnclaveRequest>,
+    mut outgoing: mpsc::UnboundedReceiver<Outgoing>,
+) {
+    while let Some((id, request, written)) = outgoing.recv().await {
+        let result = writer.send(id, request).await;
+
+        // A failed write (other than an oversized frame) means the connection is unusable.
+        let fatal =
+            matches!(&result, Err(e) if !matches!(e, CommunicationError::FrameTooLarge { .. }));
+
+        let _ = written.send(result);
+
+        if fatal {
+            return;
+        }
+    }
+
+    if let Err(e) = writer
+        .send(HANDSHAKE_REQUEST_ID, EnclaveRequest::CloseSession)
+        .await
+    {
+        tracing::error!("Failed to send close session request: {}", e);
+    }
+}
+
+/// Reads responses from the enclave, and sends them to the request they answer.
+///
+/// Once the connection is closed, every pending request is failed with [`CommunicationError::Disconnected`].
+async fn dispatch_responses(
+    mut reader: FrameReader<EnclaveResponse>,
+    pending: Arc<Mutex<Pending>>,
+) {
+    loop {
+        let (id, response) = match reader.recv().await {
+            Ok((id, response)) => (id, Ok(response)),
+            // The frame was discarded, so the stream is still usable.
+            Err(e @ CommunicationError::FrameTooLarge { id, .. }) => (id, Err(e)),
+            Err(e) => {
+                tracing::debug!("Connection to enclave closed: {}", e);
+                break;
+            }
+        };
+
+        let sender = pending
+            .lock()
+            .expect("Pending requests lock poisoned")
+            .senders
+            .remove(&id);
+
+        match sender {
+            Some(sender) => {
+                // The requester may have given up waiting, this is fine.
+                let _ = sender.send(response);
             }
-        });
+            None => tracing::warn!("Received response for unknown request: {}", id),
+        }
+    }
+
+    let mut pending = pending.lock().expect("Pending requests lock poisoned");
+    pending.closed = true;
+
+    // Dropping the senders fails the pending requests.
+    pending.senders.clear();
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // The writer task sends the close session request once `outgoing` is dropped.
+        self.reader_task.abort();
     }
 }
@@ -0,0 +1,232 @@
+use super::stream::HostStream;
+use sp1_tee_common::{
+    CommunicationError, EnclaveError, EnclaveRequest, EnclaveResponse, UploadHasher, UploadId,
+    UploadKind, UPLOAD_CHUNK_SIZE,
+};
+
+/// Streams an upload into the enclave, one chunk at a time.
+///
+/// Each chunk waits for the enclave to acknowledge it before the next one is sent,
+/// so at most one chunk is buffered on the host.
+///
+/// If the writer is dropped before [`UploadWriter::commit`] is called, the upload is aborted.
+pub struct UploadWriter {
+    stream: HostStream,
+    upload_id: UploadId,
+    /// The number of bytes the enclave has received.
+    offset: u64,
+    /// The number of bytes declared when the upload was started.
+    total_len: u64,
+    /// Bytes written, but not yet sent to the enclave.
+    buffer: Vec<u8>,
+    hasher: UploadHasher,
+    /// Set once the upload is committed or aborted.
+    finished: bool,
+}
+
+impl HostStream {
+    /// Starts an upload of `total_len` bytes to the enclave.
+    ///
+    /// The upload only lives as long as this connection, so it must be used from the same stream.
+    ///
+    /// # Errors
+    /// - [`UploadError::Rejected`] - The enclave refused the upload, i.e. it does not have enough memory.
+    pub async fn upload(
+        &self,
+        kind: UploadKind,
+        total_len: u64,
+    ) -> Result<UploadWriter, UploadError> {
+        let response = self
+            .request(EnclaveRequest::BeginUpload { kind, total_len })
+            .await?;
+
+        let upload_id = match response {
+            EnclaveResponse::UploadStarted { upload_id } => upload_id,
+            EnclaveResponse::Error(e) => return Err(UploadError::Rejected(e)),
+            response => return Err(UploadError::UnexpectedResponse(response.type_of())),
+        };
+
+        Ok(UploadWriter {
+            stream: self.clone(),
+            upload_id,
+            offset: 0,
+            total_len,
+            buffer: Vec::with_capacity(UPLOAD_CHUNK_SIZE),
+            hasher: UploadHasher::new(),
+            finished: false,
+        })
+    }
+}
+
+impl UploadWriter {
+    /// The ID the enclave assigned to this upload.
+    pub fn upload_id(&self) -> UploadId {
+        self.upload_id
+    }
+
+    /// Writes bytes to the upload, sending every full chunk to the enclave.
+    ///
+    /// # Errors
+    /// - [`UploadError::LengthMismatch`] - More bytes were written than declared.
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<(), UploadError> {
+        let written = self.offset + (self.buffer.len() + data.len()) as u64;
+        if written > self.total_len {
+            return Err(UploadError::LengthMismatch {
+                declared: self.total_len,
+                written,
+            });
+        }
+
+        while !data.is_empty() {
+            let take = (UPLOAD_CHUNK_SIZE - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+
+            if self.buffer.len() == UPLOAD_CHUNK_SIZE {
+                self.flush().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends any buffered bytes, and commits the upload.
+    ///
+    /// # Errors
+    /// - [`UploadError::LengthMismatch`] - Fewer bytes were written than declared.
+    /// - [`UploadError::Rejected`] - The enclave computed a different digest.
+    pub async fn commit(mut self) -> Result<CommittedUpload, UploadError> {
+        self.flush().await?;
+
+        if self.offset != self.total_len {
+            return Err(UploadError::LengthMismatch {
+                declared: self.total_len,
+                written: self.offset,
+            });
+        }
+
+        let digest = std::mem::take(&mut self.hasher).finalize();
+
+        // The enclave discards the upload if the commit fails, so there is nothing left to abort.
+        self.finished = true;
+
+        let response = self
+            .stream
+            .request(EnclaveRequest::CommitUpload {
+                upload_id: self.upload_id,
+                digest,
+            })
+            .await?;
+
+        match response {
+            EnclaveResponse::UploadCommitted { digest, .. } => Ok(CommittedUpload {
+                stream: self.stream.clone(),
+                upload_id: self.upload_id,
+                digest,
+                consumed: false,
+            }),
+            EnclaveResponse::Error(e) => Err(UploadError::Rejected(e)),
+            response => Err(UploadError::UnexpectedResponse(response.type_of())),
+        }
+    }
+
+    /// Sends the buffered bytes to the enclave, and waits for it to acknowledge them.
+    async fn flush(&mut self) -> Result<(), UploadError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let data = std::mem::replace(&mut self.buffer, Vec::with_capacity(UPLOAD_CHUNK_SIZE));
+        self.hasher.update(&data);
+
+        let response = self
+            .stream
+            .request(EnclaveRequest::UploadChunk {
+                upload_id: self.upload_id,
+                offset: self.offset,
+                data,
+            })
+            .await?;
+
+        match response {
+            EnclaveResponse::UploadProgress { received, .. } => {
+                self.offset = received;
+
+                Ok(())
+            }
+            EnclaveResponse::Error(e) => Err(UploadError::Rejected(e)),
+            response => Err(UploadError::UnexpectedResponse(response.type_of())),
+        }
+    }
+}
+
+impl Drop for UploadWriter {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+
+        spawn_abort(self.stream.clone(), self.upload_id);
+    }
+}
+
+/// An upload the enclave has verified, waiting to be used by a request.
+///
+/// If this is dropped before [`CommittedUpload::into_id`] is called, the upload is aborted.
+pub struct CommittedUpload {
+    stream: HostStream,
+    upload_id: UploadId,
+    digest: [u8; 32],
+    consumed: bool,
+}
+
+impl CommittedUpload {
+    /// The digest of the upload, as computed by the enclave.
+    pub fn digest(&self) -> [u8; 32] {
+        self.digest
+    }
+
+    /// Returns the ID of the upload, to be passed to a request that consumes it.
+    pub fn into_id(mut self) -> UploadId {
+        self.consumed = true;
+
+        self.upload_id
+    }
+}
+
+impl Drop for CommittedUpload {
+    fn drop(&mut self) {
+        if !self.consumed {
+            spawn_abort(self.stream.clone(), self.upload_id);
+        }
+    }
+}
+
+/// Aborts an upload in the background.
+///
+/// This releases the memory reserved in the enclave, as the connection may outlive the upload.
+fn spawn_abort(stream: HostStream, upload_id: UploadId) {
+    tokio::spawn(async move {
+        if let Err(e) = stream
+            .request(EnclaveRequest::AbortUpload { upload_id })
+            .await
+        {
+            tracing::debug!("Failed to abort upload {}: {}", upload_id, e);
+        }
+    });
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error(transparent)]
+    Communication(#[from] CommunicationError),
+
+    #[error("Enclave rejected the upload: {0}")]
+    Rejected(EnclaveError),
+
+    #[error("Unexpected response from enclave: {0}")]
+    UnexpectedResponse(&'static str),
+
+    #[error("Upload length mismatch, declared {declared} bytes, wrote {written} bytes")]
+    LengthMismatch { declared: u64, written: u64 },
+}
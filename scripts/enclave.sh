@@ -17,6 +17,7 @@ if [[ -z "$1" || ! $VALID_COMMANDS =~ " $1 " ]]; then
     echo "  ENCLAVE_CPU_COUNT: The number of CPU cores to use for the enclave"
     echo "  ENCLAVE_MEMORY: The amount of memory to use for the enclave"
     echo "  ENCLAVE_CID: The CID to use for the enclave"
+    echo "  ENC_KEY_ARN: The KMS key used to seal the signing key (optional)"
     exit 1
 fi
 
@@ -33,9 +34,9 @@ fi
 
 # Always build the enclave from scratch.
 if [[ $2 == "-f" || $2 == "--debug" ]]; then
-    docker build --build-arg DEBUG_MODE=1 -t sp1-tee .
+    docker build --build-arg DEBUG_MODE=1 --build-arg ENC_KEY_ARN="${ENC_KEY_ARN:-none}" -t sp1-tee .
 else
-    docker build -t sp1-tee .
+    docker build --build-arg ENC_KEY_ARN="${ENC_KEY_ARN:-none}" -t sp1-tee .
 fi
 
 # Create the EIF from the enclave.